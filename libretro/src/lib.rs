@@ -0,0 +1,732 @@
+//! A libretro core running `chip8::system::System`, for RetroArch and other libretro frontends.
+//!
+//! The libretro API is a set of C functions the frontend calls, plus callbacks it hands to the
+//! core. Only the parts the emulator needs are declared here, following `libretro.h`.
+
+use chip8::keymap::Keymap;
+use chip8::palette::Palette;
+use chip8::system::{Quirks, System, Variant, COLOR_WIDTH, HEIGHT, STATE_SIZE, WIDTH};
+use chip8::timing::FRAME_RATE;
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+const RETRO_API_VERSION: c_uint = 1;
+
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS: c_uint = 11;
+const RETRO_ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const RETRO_ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+const RETRO_ENVIRONMENT_SET_SUPPORT_NO_GAME: c_uint = 18;
+
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_DEVICE_KEYBOARD: c_uint = 3;
+
+const RETRO_DEVICE_ID_JOYPAD_B: c_uint = 0;
+const RETRO_DEVICE_ID_JOYPAD_Y: c_uint = 1;
+const RETRO_DEVICE_ID_JOYPAD_UP: c_uint = 4;
+const RETRO_DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+const RETRO_DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+const RETRO_DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+const RETRO_DEVICE_ID_JOYPAD_A: c_uint = 8;
+const RETRO_DEVICE_ID_JOYPAD_X: c_uint = 9;
+
+const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+
+const RETRO_REGION_NTSC: c_uint = 0;
+
+/// Joypad buttons and the CHIP-8 keys they press. The directions match the 2/4/6/8 layout most
+/// games use for movement, and the face buttons cover the usual action keys.
+const JOYPAD: [(c_uint, u8, &[u8]); 8] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x2, b"Up (2)\0"),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8, b"Down (8)\0"),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x4, b"Left (4)\0"),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x6, b"Right (6)\0"),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x5, b"5\0"),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x0, b"0\0"),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0xA, b"A\0"),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0xB, b"B\0"),
+];
+
+/// The audio sample rate, in Hz
+const SAMPLE_RATE: u32 = 44_100;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAME_RATE) as usize;
+/// The pitch of the beep played while the sound timer runs, in Hz
+const BEEP_FREQUENCY: u32 = 440;
+const BEEP_VOLUME: i16 = 0x1000;
+
+const SPEED_KEY: &[u8] = b"chip8_speed\0";
+const VARIANT_KEY: &[u8] = b"chip8_variant\0";
+const SHIFT_KEY: &[u8] = b"chip8_quirk_shift\0";
+const KEEP_I_KEY: &[u8] = b"chip8_quirk_keep_i\0";
+const VF_RESET_KEY: &[u8] = b"chip8_quirk_vf_reset\0";
+const JUMP_KEY: &[u8] = b"chip8_quirk_jump\0";
+
+/// The core options and their descriptions, as `description; default|other values`. Each quirk
+/// follows the variant unless it is enabled or disabled on its own.
+const VARIABLES: [(&[u8], &[u8]); 6] = [
+    (SPEED_KEY, b"Emulation speed; 1x|2x|3x|4x\0"),
+    (VARIANT_KEY, b"Variant; default|chip8|schip\0"),
+    (
+        SHIFT_KEY,
+        b"Shift VY into VX (8XY6, 8XYE); variant|enabled|disabled\0",
+    ),
+    (
+        KEEP_I_KEY,
+        b"Keep I on save and load (FX55, FX65); variant|enabled|disabled\0",
+    ),
+    (
+        VF_RESET_KEY,
+        b"Reset VF on logic (8XY1, 8XY2, 8XY3); variant|enabled|disabled\0",
+    ),
+    (
+        JUMP_KEY,
+        b"Jump to XNN plus VX (BXNN); variant|enabled|disabled\0",
+    ),
+];
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    geometry: RetroGameGeometry,
+    timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[repr(C)]
+struct RetroVariable {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+#[repr(C)]
+struct RetroInputDescriptor {
+    port: c_uint,
+    device: c_uint,
+    index: c_uint,
+    id: c_uint,
+    description: *const c_char,
+}
+
+/// Everything the core keeps between calls from the frontend
+struct Core {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+    system: Option<System>,
+    rom: Vec<u8>,
+    /// How many frames are emulated for every frame the frontend asks for
+    speed: u32,
+    quirks: Quirks,
+    /// Where the beep's square wave is, in samples, so it stays continuous across frames
+    beep_phase: u32,
+    video: Vec<u32>,
+    audio: Vec<i16>,
+}
+
+static CORE: Mutex<Core> = Mutex::new(Core {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+    system: None,
+    rom: Vec::new(),
+    speed: 1,
+    quirks: Quirks {
+        shift_vy: false,
+        keep_i: false,
+        vf_reset: false,
+        jump_vx: false,
+    },
+    beep_phase: 0,
+    video: Vec::new(),
+    audio: Vec::new(),
+});
+
+/// Locks the core's state. Frontends call the core from a single thread, so the lock is never
+/// contended, but a panic while it was held must not make every later call panic too.
+fn core() -> MutexGuard<'static, Core> {
+    CORE.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl Core {
+    fn environment(&self, cmd: c_uint, data: *mut c_void) -> bool {
+        match self.environment {
+            Some(environment) => unsafe { environment(cmd, data) },
+            None => false,
+        }
+    }
+
+    /// Returns the value of a core option, if the frontend has one
+    fn variable(&self, key: &[u8]) -> Option<String> {
+        let mut variable = RetroVariable {
+            key: key.as_ptr() as *const c_char,
+            value: ptr::null(),
+        };
+
+        if self.environment(
+            RETRO_ENVIRONMENT_GET_VARIABLE,
+            &mut variable as *mut _ as *mut c_void,
+        ) && !variable.value.is_null()
+        {
+            let value = unsafe { CStr::from_ptr(variable.value) }.to_string_lossy();
+            Some(value.into_owned())
+        } else {
+            None
+        }
+    }
+
+    /// Reads the core options, applying any the frontend reports as changed
+    fn update_variables(&mut self) {
+        if let Some(value) = self.variable(SPEED_KEY) {
+            self.speed = parse_speed(&value).unwrap_or(1);
+        }
+
+        let variant = self.variable(VARIANT_KEY);
+        let mut quirks = match variant.as_deref().and_then(Variant::parse) {
+            Some(variant) => variant.get_quirks(),
+            None => Quirks::default(),
+        };
+        quirks.shift_vy = parse_quirk(self.variable(SHIFT_KEY).as_deref(), quirks.shift_vy);
+        quirks.keep_i = parse_quirk(self.variable(KEEP_I_KEY).as_deref(), quirks.keep_i);
+        quirks.vf_reset = parse_quirk(self.variable(VF_RESET_KEY).as_deref(), quirks.vf_reset);
+        quirks.jump_vx = parse_quirk(self.variable(JUMP_KEY).as_deref(), quirks.jump_vx);
+
+        self.quirks = quirks;
+        if let Some(system) = self.system.as_mut() {
+            system.set_quirks(quirks);
+        }
+    }
+
+    fn poll_keys(&self) -> u16 {
+        let (poll, state) = match (self.input_poll, self.input_state) {
+            (Some(poll), Some(state)) => (poll, state),
+            _ => return 0,
+        };
+
+        unsafe { poll() };
+
+        let mut keys = 0;
+        let keymap = Keymap::default();
+
+        for key in 0..16u8 {
+            // libretro key codes match ASCII for digits and lower case letters
+            let code = keymap.char_for(key) as c_uint;
+            if unsafe { state(0, RETRO_DEVICE_KEYBOARD, 0, code) } != 0 {
+                keys |= 1 << key;
+            }
+        }
+
+        for (button, key, _) in JOYPAD.iter() {
+            if unsafe { state(0, RETRO_DEVICE_JOYPAD, 0, *button) } != 0 {
+                keys |= 1 << key;
+            }
+        }
+
+        keys
+    }
+
+    fn run_frame(&mut self) {
+        let keys = self.poll_keys();
+        let speed = self.speed;
+
+        let system = match self.system.as_mut() {
+            Some(system) => system,
+            None => return,
+        };
+
+        system.set_keys(keys);
+        for _ in 0..speed {
+            system.run_to_next_frame();
+        }
+
+        let palette = Palette::default();
+        self.video.clear();
+        self.video.extend(
+            system
+                .get_framebuffer()
+                .chunks_exact(COLOR_WIDTH)
+                .map(|pixel| {
+                    let [r, g, b] = palette.color(pixel[0] != 0);
+                    (r as u32) << 16 | (g as u32) << 8 | b as u32
+                }),
+        );
+
+        let beeping = system.get_sound_timer() > 0;
+        self.audio.clear();
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = beep_sample(self.beep_phase, beeping);
+            self.beep_phase = (self.beep_phase + 1) % SAMPLE_RATE;
+            self.audio.extend_from_slice(&[sample, sample]);
+        }
+
+        if let Some(video_refresh) = self.video_refresh {
+            let pitch = WIDTH * std::mem::size_of::<u32>();
+            unsafe {
+                video_refresh(
+                    self.video.as_ptr() as *const c_void,
+                    WIDTH as c_uint,
+                    HEIGHT as c_uint,
+                    pitch,
+                )
+            };
+        }
+
+        if let Some(audio_sample_batch) = self.audio_sample_batch {
+            unsafe { audio_sample_batch(self.audio.as_ptr(), SAMPLES_PER_FRAME) };
+        }
+    }
+}
+
+/// Parses the speed core option, e.g. `2x`
+///
+/// # Arguments
+///
+/// * `value` - The option's value
+fn parse_speed(value: &str) -> Option<u32> {
+    match value.trim_end_matches('x').parse() {
+        Ok(speed) if speed > 0 => Some(speed),
+        _ => None,
+    }
+}
+
+/// Parses a quirk core option, which follows the variant unless it is `enabled` or `disabled`
+///
+/// # Arguments
+///
+/// * `value` - The option's value, if the frontend has one
+/// * `variant` - Whether the variant has the quirk
+fn parse_quirk(value: Option<&str>, variant: bool) -> bool {
+    match value {
+        Some("enabled") => true,
+        Some("disabled") => false,
+        _ => variant,
+    }
+}
+
+/// Returns a sample of the square wave played while the sound timer runs
+///
+/// # Arguments
+///
+/// * `phase` - The index of the sample within the current second
+/// * `beeping` - Whether the sound timer is running, silence otherwise
+fn beep_sample(phase: u32, beeping: bool) -> i16 {
+    if !beeping {
+        return 0;
+    }
+
+    let half_period = SAMPLE_RATE / BEEP_FREQUENCY / 2;
+    if (phase / half_period).is_multiple_of(2) {
+        BEEP_VOLUME
+    } else {
+        -BEEP_VOLUME
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(environment: EnvironmentFn) {
+    let mut core = core();
+    core.environment = Some(environment);
+
+    let mut variables: Vec<RetroVariable> = VARIABLES
+        .iter()
+        .map(|(key, value)| RetroVariable {
+            key: key.as_ptr() as *const c_char,
+            value: value.as_ptr() as *const c_char,
+        })
+        .collect();
+    variables.push(RetroVariable {
+        key: ptr::null(),
+        value: ptr::null(),
+    });
+    core.environment(
+        RETRO_ENVIRONMENT_SET_VARIABLES,
+        variables.as_mut_ptr() as *mut c_void,
+    );
+
+    let mut no_game = false;
+    core.environment(
+        RETRO_ENVIRONMENT_SET_SUPPORT_NO_GAME,
+        &mut no_game as *mut bool as *mut c_void,
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: VideoRefreshFn) {
+    core().video_refresh = Some(video_refresh);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_audio_sample: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: AudioSampleBatchFn) {
+    core().audio_sample_batch = Some(audio_sample_batch);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: InputPollFn) {
+    core().input_poll = Some(input_poll);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: InputStateFn) {
+    core().input_state = Some(input_state);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    let mut core = core();
+    core.system = None;
+    core.rom.clear();
+}
+
+/// # Safety
+///
+/// `info` must point to a `retro_system_info` the frontend allocated
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: b"CHIP-8\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|c8\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+///
+/// `info` must point to a `retro_system_av_info` the frontend allocated
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: RetroSystemTiming {
+            fps: FRAME_RATE as f64,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    let mut core = core();
+
+    if core.system.is_some() {
+        let mut system = System::new();
+        system.write_rom(core.rom.clone());
+        system.set_quirks(core.quirks);
+        core.system = Some(system);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let mut core = core();
+
+    let mut updated = false;
+    core.environment(
+        RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE,
+        &mut updated as *mut bool as *mut c_void,
+    );
+    if updated {
+        core.update_variables();
+    }
+
+    core.run_frame();
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    let core = core();
+
+    // States grow when symbols are saved with them
+    core.system
+        .as_ref()
+        .map_or(STATE_SIZE, |system| system.save_state().len())
+}
+
+/// # Safety
+///
+/// `data` must point to at least `size` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = core();
+
+    match core.system.as_ref().map(System::save_state) {
+        Some(state) if size >= state.len() => {
+            ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+            true
+        }
+        _ => false,
+    }
+}
+
+/// # Safety
+///
+/// `data` must point to at least `size` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = core();
+    let state = slice::from_raw_parts(data as *const u8, size);
+
+    match core.system.as_mut() {
+        Some(system) => system.load_state(state).is_ok(),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+///
+/// `game` must be null or point to a `retro_game_info` whose data is `size` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    let mut core = core();
+
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+
+    let rom = slice::from_raw_parts((*game).data as *const u8, (*game).size);
+    if rom.len() > 4096 - 0x200 {
+        return false;
+    }
+
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !core.environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut format as *mut c_uint as *mut c_void,
+    ) {
+        return false;
+    }
+
+    let mut descriptors: Vec<RetroInputDescriptor> = JOYPAD
+        .iter()
+        .map(|(button, _, description)| RetroInputDescriptor {
+            port: 0,
+            device: RETRO_DEVICE_JOYPAD,
+            index: 0,
+            id: *button,
+            description: description.as_ptr() as *const c_char,
+        })
+        .collect();
+    descriptors.push(RetroInputDescriptor {
+        port: 0,
+        device: 0,
+        index: 0,
+        id: 0,
+        description: ptr::null(),
+    });
+    core.environment(
+        RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+        descriptors.as_mut_ptr() as *mut c_void,
+    );
+
+    let mut system = System::new();
+    system.write_rom(rom.to_vec());
+
+    core.rom = rom.to_vec();
+    core.system = Some(system);
+    core.beep_phase = 0;
+    core.update_variables();
+
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const RetroGameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    let mut core = core();
+    core.system = None;
+    core.rom.clear();
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    let mut core = core();
+
+    match core.system.as_mut() {
+        // The system lives in a static, so the memory stays put until the game is unloaded
+        Some(system) if id == RETRO_MEMORY_SYSTEM_RAM => {
+            system.get_memory_mut().as_mut_ptr() as *mut c_void
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    let core = core();
+
+    match core.system.as_ref() {
+        Some(system) if id == RETRO_MEMORY_SYSTEM_RAM => system.get_memory().len(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FRAMES: Mutex<Vec<Vec<u32>>> = Mutex::new(Vec::new());
+
+    unsafe extern "C" fn environment(cmd: c_uint, _data: *mut c_void) -> bool {
+        cmd == RETRO_ENVIRONMENT_SET_PIXEL_FORMAT
+    }
+
+    unsafe extern "C" fn video_refresh(
+        data: *const c_void,
+        width: c_uint,
+        height: c_uint,
+        _pitch: usize,
+    ) {
+        let pixels = slice::from_raw_parts(data as *const u32, (width * height) as usize);
+        FRAMES.lock().unwrap().push(pixels.to_vec());
+    }
+
+    #[test]
+    fn test_parse_speed() {
+        assert_eq!(parse_speed("3x"), Some(3));
+        assert_eq!(parse_speed("0x"), None);
+        assert_eq!(parse_speed("fast"), None);
+    }
+
+    #[test]
+    fn test_parse_quirk() {
+        assert!(parse_quirk(Some("enabled"), false));
+        assert!(!parse_quirk(Some("disabled"), true));
+        assert!(parse_quirk(Some("variant"), true));
+        assert!(!parse_quirk(None, false));
+    }
+
+    #[test]
+    fn test_beep_is_square_wave() {
+        assert_eq!(beep_sample(0, false), 0);
+        assert_eq!(beep_sample(0, true), BEEP_VOLUME);
+        assert_eq!(
+            beep_sample(SAMPLE_RATE / BEEP_FREQUENCY / 2, true),
+            -BEEP_VOLUME
+        );
+    }
+
+    #[test]
+    fn test_run_and_serialize() {
+        // Draw the "0" glyph in the top-left corner, then loop forever
+        let rom: [u8; 4] = [0xD0, 0x05, 0x12, 0x02];
+        let game = RetroGameInfo {
+            path: ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: ptr::null(),
+        };
+
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        assert!(unsafe { retro_load_game(&game) });
+
+        retro_run();
+        let frame = FRAMES.lock().unwrap().pop().unwrap();
+        assert_eq!(frame.len(), WIDTH * HEIGHT);
+        assert_eq!(frame[0], 0xFF_FFFF);
+        assert_eq!(frame[4], 0);
+
+        let mut state = vec![0u8; retro_serialize_size()];
+        assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+
+        retro_reset();
+        assert_eq!(
+            unsafe { *(retro_get_memory_data(RETRO_MEMORY_SYSTEM_RAM) as *const u8) },
+            0xF0
+        );
+        assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+        assert!(!unsafe { retro_unserialize(state.as_ptr() as *const c_void, 4) });
+
+        retro_unload_game();
+        assert!(retro_get_memory_data(RETRO_MEMORY_SYSTEM_RAM).is_null());
+    }
+}
@@ -0,0 +1,1122 @@
+use crate::disasm::PROGRAM_START;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+
+/// The machines a program can be assembled for, which differ in how much memory they have
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Target {
+    /// A `.ch8` program for 4 KiB of memory
+    #[default]
+    Chip8,
+    /// An `.xo8` program for XO-CHIP's 64 KiB of memory
+    XoChip,
+}
+
+impl Target {
+    /// Returns the target matching a file extension, `ch8` or `xo8`
+    ///
+    /// # Arguments
+    ///
+    /// * `extension` - The extension, without the dot
+    pub fn from_extension(extension: &str) -> Option<Target> {
+        match extension.to_ascii_lowercase().as_str() {
+            "ch8" => Some(Target::Chip8),
+            "xo8" => Some(Target::XoChip),
+            _ => None,
+        }
+    }
+
+    /// Returns the size of the target's memory
+    pub fn get_memory_size(&self) -> usize {
+        match self {
+            Target::Chip8 => 0x1000,
+            Target::XoChip => 0x10000,
+        }
+    }
+}
+
+/// An error in a source file, with the position of the token it was found at
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// An assembled program
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Assembly {
+    /// The program's bytes, to be loaded at `0x200`
+    pub bytes: Vec<u8>,
+    /// The address of every label
+    pub symbols: BTreeMap<String, usize>,
+    /// The source line, counted from `1`, of every assembled byte by address
+    pub lines: BTreeMap<usize, usize>,
+}
+
+impl Assembly {
+    /// Returns the source line an address was assembled from
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of an instruction or data byte
+    pub fn get_line(&self, address: usize) -> Option<usize> {
+        self.lines.get(&address).copied()
+    }
+
+    /// Returns the first address assembled from a source line
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line, counted from `1`
+    pub fn get_address(&self, line: usize) -> Option<usize> {
+        self.lines
+            .iter()
+            .find(|(_, assembled)| **assembled == line)
+            .map(|(address, _)| *address)
+    }
+
+    /// Writes the symbol map, one `address name` line per label in address order
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where the map is written
+    pub fn write_symbols<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut symbols: Vec<(&String, &usize)> = self.symbols.iter().collect();
+        symbols.sort_by_key(|(name, address)| (**address, name.as_str()));
+
+        for (name, address) in symbols {
+            writeln!(writer, "{:#05x} {}", address, name)?;
+        }
+
+        writer.flush()
+    }
+}
+
+/// Assembles a program written in Octo's syntax.
+///
+/// Supported are instructions, labels, `:const`, `:alias`, `:macro`, `:calc`, `:org`, `:byte`,
+/// `:call`, `loop`/`while`/`again`, `if … then`, `if … begin … else … end` and bare numbers as
+/// sprite data. Like Octo, execution starts at the label `main`, with a jump to it at `0x200`
+/// unless it is the first thing in the program. `:calc` works on integers rather than floats,
+/// and evaluates right to left like Octo. For an `.xo8` target, XO-CHIP's `i := long`, which
+/// reaches all of its 64 KiB, `save`/`load` of register ranges, `plane`, `audio` and `pitch` are
+/// supported too.
+///
+/// # Arguments
+///
+/// * `source` - The program's source
+/// * `target` - The machine to assemble for
+pub fn assemble(source: &str, target: Target) -> Result<Assembly, AsmError> {
+    let mut assembler = Assembler::new(source, target);
+
+    while let Some(token) = assembler.next_token() {
+        assembler.statement(token)?;
+    }
+
+    assembler.finish()
+}
+
+/// How deeply macros may expand inside each other, which stops macros that invoke themselves
+const MAX_MACRO_DEPTH: usize = 64;
+
+/// A whitespace separated word of source, with its 1-based position
+#[derive(Clone, Debug, PartialEq)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+    /// How many macro expansions the token came from, `0` for tokens of the source itself
+    depth: usize,
+}
+
+impl Token {
+    fn error<S: Into<String>>(&self, message: S) -> AsmError {
+        AsmError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+/// Splits source into tokens, dropping `#` comments
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+
+    for (idx, line) in source.lines().enumerate() {
+        let mut start = None;
+
+        // A trailing space ends the last token
+        for (column, c) in line.chars().chain(std::iter::once(' ')).enumerate() {
+            match (start, c.is_whitespace()) {
+                (None, false) if c == '#' => break,
+                (None, false) => start = Some(column),
+                (Some(first), true) => {
+                    tokens.push_back(Token {
+                        text: line.chars().skip(first).take(column - first).collect(),
+                        line: idx + 1,
+                        column: first + 1,
+                        depth: 0,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Parses a decimal, `0x` hexadecimal or `0b` binary number, optionally negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+/// Parses a register name, `v0` to `vf` in either case
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+
+    if digit.len() == 1 {
+        u8::from_str_radix(digit, 16).ok()
+    } else {
+        None
+    }
+}
+
+/// A comparison in an `if` or `while`
+enum Condition {
+    EqByte(u8, u8),
+    NeByte(u8, u8),
+    EqReg(u8, u8),
+    NeReg(u8, u8),
+    Key(u8),
+    NotKey(u8),
+}
+
+impl Condition {
+    /// Returns the instruction that skips the next one when the condition is false
+    fn skip_unless(&self) -> u16 {
+        match *self {
+            Condition::EqByte(x, byte) => xnn(0x4, x, byte),
+            Condition::NeByte(x, byte) => xnn(0x3, x, byte),
+            Condition::EqReg(x, y) => xy(0x9, x, y, 0x0),
+            Condition::NeReg(x, y) => xy(0x5, x, y, 0x0),
+            Condition::Key(x) => xnn(0xE, x, 0xA1),
+            Condition::NotKey(x) => xnn(0xE, x, 0x9E),
+        }
+    }
+
+    /// Returns the instruction that skips the next one when the condition is true
+    fn skip_if(&self) -> u16 {
+        match *self {
+            Condition::EqByte(x, byte) => xnn(0x3, x, byte),
+            Condition::NeByte(x, byte) => xnn(0x4, x, byte),
+            Condition::EqReg(x, y) => xy(0x5, x, y, 0x0),
+            Condition::NeReg(x, y) => xy(0x9, x, y, 0x0),
+            Condition::Key(x) => xnn(0xE, x, 0x9E),
+            Condition::NotKey(x) => xnn(0xE, x, 0xA1),
+        }
+    }
+}
+
+fn xnn(group: u16, x: u8, byte: u8) -> u16 {
+    group << 12 | (x as u16) << 8 | byte as u16
+}
+
+fn xy(group: u16, x: u8, y: u8, n: u8) -> u16 {
+    group << 12 | (x as u16) << 8 | (y as u16) << 4 | n as u16
+}
+
+/// An open `loop` or `if … begin` block
+enum Block {
+    /// A loop starting at an address, with the `while` jumps that leave it
+    Loop {
+        start: usize,
+        exits: Vec<usize>,
+        token: Token,
+    },
+    /// A conditional block, with the jump to patch when it ends or reaches its `else`
+    If {
+        jump: usize,
+        has_else: bool,
+        token: Token,
+    },
+}
+
+/// A jump, call or `I` load of a label that was not defined yet
+struct Fixup {
+    address: usize,
+    name: String,
+    token: Token,
+    /// Whether the address is the 16 bit operand of `i := long`, rather than 12 bits of an
+    /// instruction
+    long: bool,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Assembler {
+    target: Target,
+    tokens: VecDeque<Token>,
+    memory: Vec<u8>,
+    here: usize,
+    end: usize,
+    /// Whether `0x200` is still reserved for a jump to `main`
+    main_jump: bool,
+    /// Whether `:org` has moved assembly, which keeps the jump to `main`
+    org: bool,
+    labels: BTreeMap<String, usize>,
+    lines: BTreeMap<usize, usize>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    /// The token the last statement started at, for errors found after reading all tokens
+    last: Option<Token>,
+}
+
+impl Assembler {
+    fn new(source: &str, target: Target) -> Self {
+        Assembler {
+            target,
+            tokens: tokenize(source),
+            memory: vec![0; target.get_memory_size()],
+            here: PROGRAM_START + 2,
+            end: PROGRAM_START + 2,
+            main_jump: true,
+            org: false,
+            labels: BTreeMap::new(),
+            lines: BTreeMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: vec![],
+            blocks: vec![],
+            last: None,
+        }
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        let token = self.tokens.pop_front();
+        if token.is_some() {
+            self.last = token.clone();
+        }
+
+        token
+    }
+
+    /// Returns the next token, or an error at `after` when the source ends
+    fn expect(&mut self, after: &Token, what: &str) -> Result<Token, AsmError> {
+        self.next_token()
+            .ok_or_else(|| after.error(format!("expected {} after '{}'", what, after.text)))
+    }
+
+    fn expect_text(&mut self, after: &Token, text: &str) -> Result<Token, AsmError> {
+        let token = self.expect(after, &format!("'{}'", text))?;
+
+        if token.text == text {
+            Ok(token)
+        } else {
+            Err(token.error(format!("expected '{}', found '{}'", text, token.text)))
+        }
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        if let Some(x) = self.register(&token) {
+            return self.register_statement(x, &token);
+        }
+        if let Some(value) = self.constant(&token) {
+            let byte = self.check_byte(&token, value)?;
+            return self.emit_byte(&token, byte);
+        }
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name(&token)?;
+                self.define_label(name)
+            }
+            ":const" => {
+                let name = self.name(&token)?;
+                let value = self.expect(&token, "a value")?;
+                let value = self
+                    .value(&value)
+                    .ok_or_else(|| value.error(format!("unknown value '{}'", value.text)))?;
+                self.define_constant(name, value)
+            }
+            ":alias" => {
+                let name = self.name(&token)?;
+                let register = self.expect(&token, "a register")?;
+                let register = self.expect_register(&register)?;
+                self.aliases.insert(name.text, register);
+                Ok(())
+            }
+            ":macro" => self.define_macro(&token),
+            ":calc" => {
+                let name = self.name(&token)?;
+                let open = self.expect_text(&name, "{")?;
+                let value = self.calc(&open)?;
+                self.expect_text(&token, "}")?;
+                self.define_constant(name, value)
+            }
+            ":org" => {
+                let address = self.expect(&token, "an address")?;
+                let value = self.address_value(&address)?;
+                if value < PROGRAM_START {
+                    return Err(address.error(format!(
+                        "address {:#x} is below {:#x}, where programs start",
+                        value, PROGRAM_START
+                    )));
+                }
+                self.here = value;
+                self.org = true;
+                Ok(())
+            }
+            ":byte" => {
+                let value = self.expect(&token, "a byte")?;
+                let byte = self.byte(&value)?;
+                self.emit_byte(&value, byte)
+            }
+            ":call" => {
+                let target = self.expect(&token, "an address")?;
+                self.emit_address(&target, 0x2000)
+            }
+            "clear" => self.emit_op(&token, 0x00E0),
+            "return" | ";" => self.emit_op(&token, 0x00EE),
+            "jump" => {
+                let target = self.expect(&token, "an address")?;
+                self.emit_address(&target, 0x1000)
+            }
+            "jump0" => {
+                let target = self.expect(&token, "an address")?;
+                self.emit_address(&target, 0xB000)
+            }
+            "sprite" => {
+                let x = self.expect(&token, "a register")?;
+                let x = self.expect_register(&x)?;
+                let y = self.expect(&token, "a register")?;
+                let y = self.expect_register(&y)?;
+                let height = self.expect(&token, "a height")?;
+                let rows = self.byte(&height)?;
+                if rows > 0xF {
+                    return Err(height.error("sprite height must be 0 to 15"));
+                }
+                self.emit_op(&token, xy(0xD, x, y, rows))
+            }
+            "bcd" | "save" | "load" => {
+                let x = self.expect(&token, "a register")?;
+                let x = self.expect_register(&x)?;
+
+                // XO-CHIP's `save vx - vy` and `load vx - vy`
+                if token.text != "bcd"
+                    && self.tokens.front().map(|next| next.text.as_str()) == Some("-")
+                {
+                    let dash = self.expect(&token, "'-'")?;
+                    self.xo_chip(&token)?;
+                    let y = self.expect(&dash, "a register")?;
+                    let y = self.expect_register(&y)?;
+                    let n = if token.text == "save" { 0x2 } else { 0x3 };
+                    return self.emit_op(&token, xy(0x5, x, y, n));
+                }
+
+                let byte = match token.text.as_str() {
+                    "bcd" => 0x33,
+                    "save" => 0x55,
+                    _ => 0x65,
+                };
+                self.emit_op(&token, xnn(0xF, x, byte))
+            }
+            "delay" | "buzzer" => {
+                self.expect_text(&token, ":=")?;
+                let x = self.expect(&token, "a register")?;
+                let x = self.expect_register(&x)?;
+                let byte = if token.text == "delay" { 0x15 } else { 0x18 };
+                self.emit_op(&token, xnn(0xF, x, byte))
+            }
+            "i" => self.i_statement(&token),
+            "plane" => {
+                self.xo_chip(&token)?;
+                let planes = self.expect(&token, "a plane mask")?;
+                let mask = self.byte(&planes)?;
+                if mask > 0x3 {
+                    return Err(planes.error("plane mask must be 0 to 3"));
+                }
+                self.emit_op(&token, xnn(0xF, mask, 0x01))
+            }
+            "audio" => {
+                self.xo_chip(&token)?;
+                self.emit_op(&token, 0xF002)
+            }
+            "pitch" => {
+                self.xo_chip(&token)?;
+                self.expect_text(&token, ":=")?;
+                let x = self.expect(&token, "a register")?;
+                let x = self.expect_register(&x)?;
+                self.emit_op(&token, xnn(0xF, x, 0x3A))
+            }
+            "loop" => {
+                self.blocks.push(Block::Loop {
+                    start: self.here,
+                    exits: vec![],
+                    token,
+                });
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition(&token)?;
+                let exit = self.here + 2;
+                match self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { exits, .. } => Some(exits),
+                    _ => None,
+                }) {
+                    Some(exits) => exits.push(exit),
+                    None => return Err(token.error("'while' outside of a loop")),
+                }
+                self.emit_op(&token, condition.skip_if())?;
+                self.emit_op(&token, 0x1000)
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits, .. }) => {
+                    self.emit_op(&token, 0x1000 | start as u16)?;
+                    for exit in exits {
+                        self.patch(exit, self.here);
+                    }
+                    Ok(())
+                }
+                _ => Err(token.error("'again' without a matching 'loop'")),
+            },
+            "if" => {
+                let condition = self.condition(&token)?;
+                let keyword = self.expect(&token, "'then' or 'begin'")?;
+                match keyword.text.as_str() {
+                    "then" => self.emit_op(&token, condition.skip_unless()),
+                    "begin" => {
+                        self.emit_op(&token, condition.skip_if())?;
+                        self.blocks.push(Block::If {
+                            jump: self.here,
+                            has_else: false,
+                            token: token.clone(),
+                        });
+                        self.emit_op(&token, 0x1000)
+                    }
+                    _ => Err(keyword.error(format!(
+                        "expected 'then' or 'begin', found '{}'",
+                        keyword.text
+                    ))),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If {
+                    jump,
+                    has_else: false,
+                    token: start,
+                }) => {
+                    let end_jump = self.here;
+                    self.emit_op(&token, 0x1000)?;
+                    self.patch(jump, self.here);
+                    self.blocks.push(Block::If {
+                        jump: end_jump,
+                        has_else: true,
+                        token: start,
+                    });
+                    Ok(())
+                }
+                _ => Err(token.error("'else' without a matching 'begin'")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    self.patch(jump, self.here);
+                    Ok(())
+                }
+                _ => Err(token.error("'end' without a matching 'begin'")),
+            },
+            text if text.starts_with(':') => {
+                Err(token.error(format!("unsupported directive '{}'", text)))
+            }
+            text if self.macros.contains_key(text) => self.expand_macro(&token),
+            // A bare name calls the label
+            _ => self.emit_address(&token, 0x2000),
+        }
+    }
+
+    /// Assembles a statement starting with a register, e.g. `v1 += 2`
+    fn register_statement(&mut self, x: u8, token: &Token) -> Result<(), AsmError> {
+        let operator = self.expect(token, "an operator")?;
+        let operand = self.expect(&operator, "an operand")?;
+        let y = self.register(&operand);
+
+        let op = match (operator.text.as_str(), y) {
+            (":=", Some(y)) => xy(0x8, x, y, 0x0),
+            ("|=", Some(y)) => xy(0x8, x, y, 0x1),
+            ("&=", Some(y)) => xy(0x8, x, y, 0x2),
+            ("^=", Some(y)) => xy(0x8, x, y, 0x3),
+            ("+=", Some(y)) => xy(0x8, x, y, 0x4),
+            ("-=", Some(y)) => xy(0x8, x, y, 0x5),
+            (">>=", Some(y)) => xy(0x8, x, y, 0x6),
+            ("=-", Some(y)) => xy(0x8, x, y, 0x7),
+            ("<<=", Some(y)) => xy(0x8, x, y, 0xE),
+            (":=", None) => match operand.text.as_str() {
+                "random" => {
+                    let mask = self.expect(&operand, "a mask")?;
+                    xnn(0xC, x, self.byte(&mask)?)
+                }
+                "delay" => xnn(0xF, x, 0x07),
+                "key" => xnn(0xF, x, 0x0A),
+                _ => xnn(0x6, x, self.byte(&operand)?),
+            },
+            ("+=", None) => xnn(0x7, x, self.byte(&operand)?),
+            ("-=", None) => xnn(0x7, x, self.byte(&operand)?.wrapping_neg()),
+            _ => {
+                return Err(operator.error(format!(
+                    "unsupported operation '{} {}'",
+                    operator.text, operand.text
+                )))
+            }
+        };
+
+        self.emit_op(token, op)
+    }
+
+    /// Assembles a statement starting with `i`
+    fn i_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        let operator = self.expect(token, "an operator")?;
+        let operand = self.expect(&operator, "an operand")?;
+
+        match operator.text.as_str() {
+            ":=" if operand.text == "hex" => {
+                let x = self.expect(&operand, "a register")?;
+                let x = self.expect_register(&x)?;
+                self.emit_op(token, xnn(0xF, x, 0x29))
+            }
+            ":=" if operand.text == "long" => {
+                self.xo_chip(&operand)?;
+                let target = self.expect(&operand, "an address")?;
+                let address = if self.value(&target).is_some() {
+                    self.address_value(&target)?
+                } else {
+                    self.fixups.push(Fixup {
+                        address: self.here + 2,
+                        name: target.text.clone(),
+                        token: target.clone(),
+                        long: true,
+                    });
+                    0
+                };
+
+                self.emit_op(token, 0xF000)?;
+                self.emit_op(&target, address as u16)
+            }
+            ":=" => self.emit_address(&operand, 0xA000),
+            "+=" => {
+                let x = self.expect_register(&operand)?;
+                self.emit_op(token, xnn(0xF, x, 0x1E))
+            }
+            _ => Err(operator.error(format!("unsupported operation 'i {}'", operator.text))),
+        }
+    }
+
+    /// Parses the condition of an `if` or `while`, e.g. `v0 == 5` or `v1 -key`
+    fn condition(&mut self, after: &Token) -> Result<Condition, AsmError> {
+        let register = self.expect(after, "a register")?;
+        let x = self.expect_register(&register)?;
+        let operator = self.expect(&register, "a comparison")?;
+
+        match operator.text.as_str() {
+            "key" => return Ok(Condition::Key(x)),
+            "-key" => return Ok(Condition::NotKey(x)),
+            "==" | "!=" => {}
+            _ => return Err(operator.error(format!("unsupported comparison '{}'", operator.text))),
+        }
+
+        let operand = self.expect(&operator, "an operand")?;
+        let equal = operator.text == "==";
+
+        Ok(match (self.register(&operand), equal) {
+            (Some(y), true) => Condition::EqReg(x, y),
+            (Some(y), false) => Condition::NeReg(x, y),
+            (None, true) => Condition::EqByte(x, self.byte(&operand)?),
+            (None, false) => Condition::NeByte(x, self.byte(&operand)?),
+        })
+    }
+
+    /// Fails at an XO-CHIP instruction unless assembling for XO-CHIP
+    fn xo_chip(&self, token: &Token) -> Result<(), AsmError> {
+        match self.target {
+            Target::XoChip => Ok(()),
+            Target::Chip8 => Err(token.error(format!(
+                "'{}' is an XO-CHIP instruction, which needs an .xo8 target",
+                token.text
+            ))),
+        }
+    }
+
+    /// Reads the name being defined by a directive
+    fn name(&mut self, directive: &Token) -> Result<Token, AsmError> {
+        let name = self.expect(directive, "a name")?;
+
+        if parse_number(&name.text).is_some() || parse_register(&name.text).is_some() {
+            Err(name.error(format!("'{}' cannot be used as a name", name.text)))
+        } else {
+            Ok(name)
+        }
+    }
+
+    fn define_label(&mut self, name: Token) -> Result<(), AsmError> {
+        if self.labels.contains_key(&name.text) {
+            return Err(name.error(format!("label '{}' is already defined", name.text)));
+        }
+
+        // Like Octo, the jump to main is left out when main comes first
+        if name.text == "main" && self.main_jump && !self.org && self.here == PROGRAM_START + 2 {
+            self.main_jump = false;
+            self.here = PROGRAM_START;
+            self.end = PROGRAM_START;
+            for address in self.labels.values_mut() {
+                if *address == PROGRAM_START + 2 {
+                    *address = PROGRAM_START;
+                }
+            }
+        }
+
+        self.labels.insert(name.text, self.here);
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: Token, value: i64) -> Result<(), AsmError> {
+        if self.constants.contains_key(&name.text) {
+            return Err(name.error(format!("constant '{}' is already defined", name.text)));
+        }
+
+        self.constants.insert(name.text, value);
+        Ok(())
+    }
+
+    /// Reads `:macro name params { body }`, where braces in the body must balance
+    fn define_macro(&mut self, directive: &Token) -> Result<(), AsmError> {
+        let name = self.name(directive)?;
+        let mut params = vec![];
+
+        loop {
+            let token = self.expect(&name, "'{'")?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+
+        let mut body = vec![];
+        let mut depth = 0;
+        loop {
+            let token = self.expect(&name, "'}'")?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    /// Replaces a macro invocation and its arguments with the macro's body
+    fn expand_macro(&mut self, name: &Token) -> Result<(), AsmError> {
+        if name.depth >= MAX_MACRO_DEPTH {
+            return Err(name.error("macro recursion too deep"));
+        }
+
+        let count = self.macros[&name.text].params.len();
+        let mut args = HashMap::new();
+
+        for idx in 0..count {
+            let arg = self.expect(name, "a macro argument")?;
+            args.insert(self.macros[&name.text].params[idx].clone(), arg.text);
+        }
+
+        for token in self.macros[&name.text].body.iter().rev() {
+            let mut token = token.clone();
+            token.depth = name.depth + 1;
+            if let Some(arg) = args.get(&token.text) {
+                token.text = arg.clone();
+            }
+            self.tokens.push_front(token);
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates a `:calc` expression up to its closing brace. Like Octo, operators have no
+    /// precedence and are applied right to left.
+    fn calc(&mut self, open: &Token) -> Result<i64, AsmError> {
+        let value = self.calc_term(open)?;
+
+        match self.tokens.front().map(|token| token.text.as_str()) {
+            Some("}") | Some(")") | None => Ok(value),
+            Some(_) => {
+                let operator = self.expect(open, "an operator")?;
+                let rhs = self.calc(&operator)?;
+                let result = match operator.text.as_str() {
+                    "+" => value.checked_add(rhs),
+                    "-" => value.checked_sub(rhs),
+                    "*" => value.checked_mul(rhs),
+                    "/" => value.checked_div(rhs),
+                    "%" => value.checked_rem(rhs),
+                    "&" => Some(value & rhs),
+                    "|" => Some(value | rhs),
+                    "^" => Some(value ^ rhs),
+                    "<<" => u32::try_from(rhs)
+                        .ok()
+                        .and_then(|rhs| value.checked_shl(rhs)),
+                    ">>" => u32::try_from(rhs)
+                        .ok()
+                        .and_then(|rhs| value.checked_shr(rhs)),
+                    _ => {
+                        return Err(operator.error(format!("unknown operator '{}'", operator.text)))
+                    }
+                };
+                result.ok_or_else(|| operator.error("calculation overflowed or divided by zero"))
+            }
+        }
+    }
+
+    fn calc_term(&mut self, after: &Token) -> Result<i64, AsmError> {
+        let token = self.expect(after, "a value")?;
+
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc(&token)?;
+                self.expect_text(&token, ")")?;
+                Ok(value)
+            }
+            "-" => Ok(-self.calc_term(&token)?),
+            "~" => Ok(!self.calc_term(&token)?),
+            "HERE" => Ok(self.here as i64),
+            _ => self
+                .value(&token)
+                .ok_or_else(|| token.error(format!("unknown value '{}'", token.text))),
+        }
+    }
+
+    /// Returns the value of a number, constant or label defined so far
+    fn value(&self, token: &Token) -> Option<i64> {
+        self.constant(token)
+            .or_else(|| self.labels.get(&token.text).map(|address| *address as i64))
+    }
+
+    /// Returns the value of a number or constant
+    fn constant(&self, token: &Token) -> Option<i64> {
+        parse_number(&token.text).or_else(|| self.constants.get(&token.text).copied())
+    }
+
+    fn register(&self, token: &Token) -> Option<u8> {
+        parse_register(&token.text).or_else(|| self.aliases.get(&token.text).copied())
+    }
+
+    fn expect_register(&self, token: &Token) -> Result<u8, AsmError> {
+        self.register(token)
+            .ok_or_else(|| token.error(format!("expected a register, found '{}'", token.text)))
+    }
+
+    fn byte(&self, token: &Token) -> Result<u8, AsmError> {
+        let value = self
+            .constant(token)
+            .ok_or_else(|| token.error(format!("expected a byte, found '{}'", token.text)))?;
+
+        self.check_byte(token, value)
+    }
+
+    fn check_byte(&self, token: &Token, value: i64) -> Result<u8, AsmError> {
+        if (-128..=255).contains(&value) {
+            Ok(value as u8)
+        } else {
+            Err(token.error(format!("{} does not fit in a byte", value)))
+        }
+    }
+
+    fn address_value(&self, token: &Token) -> Result<usize, AsmError> {
+        let value = self
+            .value(token)
+            .ok_or_else(|| token.error(format!("unknown address '{}'", token.text)))?;
+
+        if (0..self.target.get_memory_size() as i64).contains(&value) {
+            Ok(value as usize)
+        } else {
+            Err(token.error(format!("address {:#x} is out of range", value)))
+        }
+    }
+
+    /// Emits an instruction taking a 12 bit address, which may be a label defined later
+    fn emit_address(&mut self, token: &Token, op: u16) -> Result<(), AsmError> {
+        let address = if self.value(token).is_some() {
+            self.address_value(token)?
+        } else {
+            self.fixups.push(Fixup {
+                address: self.here,
+                name: token.text.clone(),
+                token: token.clone(),
+                long: false,
+            });
+            0
+        };
+
+        if address > 0xFFF {
+            return Err(token.error(format!("address {:#x} is out of range", address)));
+        }
+
+        self.emit_op(token, op | address as u16)
+    }
+
+    fn emit_op(&mut self, token: &Token, op: u16) -> Result<(), AsmError> {
+        self.emit_byte(token, (op >> 8) as u8)?;
+        self.emit_byte(token, op as u8)
+    }
+
+    fn emit_byte(&mut self, token: &Token, byte: u8) -> Result<(), AsmError> {
+        if self.here >= self.memory.len() {
+            return Err(token.error("program does not fit in memory"));
+        }
+
+        self.memory[self.here] = byte;
+        self.lines.insert(self.here, token.line);
+        self.here += 1;
+        self.end = self.end.max(self.here);
+
+        Ok(())
+    }
+
+    /// Sets the address of the jump at `at`
+    fn patch(&mut self, at: usize, address: usize) {
+        self.memory[at] = (self.memory[at] & 0xF0) | (address >> 8) as u8 & 0x0F;
+        self.memory[at + 1] = address as u8;
+    }
+
+    fn finish(mut self) -> Result<Assembly, AsmError> {
+        if let Some(block) = self.blocks.pop() {
+            return Err(match block {
+                Block::Loop { token, .. } => token.error("'loop' without a matching 'again'"),
+                Block::If { token, .. } => token.error("'begin' without a matching 'end'"),
+            });
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            match self.labels.get(&fixup.name) {
+                Some(address) if fixup.long => {
+                    self.memory[fixup.address] = (*address >> 8) as u8;
+                    self.memory[fixup.address + 1] = *address as u8;
+                }
+                Some(address) if *address <= 0xFFF => self.patch(fixup.address, *address),
+                Some(_) => {
+                    return Err(fixup
+                        .token
+                        .error(format!("label '{}' is out of range", fixup.name)))
+                }
+                None => {
+                    return Err(fixup
+                        .token
+                        .error(format!("undefined label '{}'", fixup.name)))
+                }
+            }
+        }
+
+        if self.main_jump {
+            let main = match self.labels.get("main") {
+                Some(main) => *main,
+                None => {
+                    let end = self.last.unwrap_or(Token {
+                        text: String::new(),
+                        line: 1,
+                        column: 1,
+                        depth: 0,
+                    });
+                    return Err(end.error("the program has no 'main' label"));
+                }
+            };
+            self.patch(PROGRAM_START, main);
+            self.memory[PROGRAM_START] |= 0x10;
+        }
+
+        Ok(Assembly {
+            bytes: self.memory[PROGRAM_START..self.end.max(PROGRAM_START)].to_vec(),
+            symbols: self.labels,
+            lines: self.lines,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::Syntax;
+    use crate::flow::Program;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source, Target::Chip8).unwrap().bytes
+    }
+
+    #[test]
+    fn test_instructions() {
+        assert_eq!(
+            bytes(": main clear v1 := 0x20 i := hex v1 sprite v0 v1 5 v2 -= 1 jump main"),
+            [0x00, 0xE0, 0x61, 0x20, 0xF1, 0x29, 0xD0, 0x15, 0x72, 0xFF, 0x12, 0x00]
+        );
+
+        // A jump to main is added when it does not come first
+        assert_eq!(
+            bytes(": ball 0x3C 0x42 : main i := ball"),
+            [0x12, 0x04, 0x3C, 0x42, 0xA2, 0x02]
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let source = "
+            : main
+            loop
+                if v0 == 1 then v1 += 1
+                while v2 != v3
+                if v4 key begin
+                    clear
+                else
+                    return
+                end
+            again
+        ";
+
+        assert_eq!(
+            bytes(source),
+            [
+                0x40, 0x01, 0x71, 0x01, // if ... then
+                0x92, 0x30, 0x12, 0x14, // while, leaving to 0x214
+                0xE4, 0x9E, 0x12, 0x10, 0x00, 0xE0, 0x12, 0x12, // begin ... else
+                0x00, 0xEE, // else ... end
+                0x12, 0x00, // again
+            ]
+        );
+
+        let assembly = assemble(source, Target::Chip8).unwrap();
+        assert_eq!(assembly.get_address(4), Some(0x200));
+        assert_eq!(assembly.get_address(3), None);
+        assert_eq!(assembly.get_line(0x206), Some(5));
+        assert_eq!(assembly.get_line(0x212), Some(11));
+    }
+
+    #[test]
+    fn test_directives() {
+        let source = "
+            :const SPEED 3
+            :alias x v5
+            :calc DOUBLE { SPEED * 2 + 1 }
+            :macro add-twice reg amount { reg += amount reg += amount }
+            : main
+            x := DOUBLE
+            add-twice x SPEED
+            :org 0x210
+            : data
+            :byte 0xAA
+        ";
+        let assembly = assemble(source, Target::Chip8).unwrap();
+
+        // Right to left, SPEED * (2 + 1)
+        assert_eq!(&assembly.bytes[..6], &[0x65, 0x09, 0x75, 0x03, 0x75, 0x03]);
+        assert_eq!(assembly.bytes[0x10], 0xAA);
+        assert_eq!(assembly.symbols["data"], 0x210);
+
+        let mut symbols = vec![];
+        assembly.write_symbols(&mut symbols).unwrap();
+        assert_eq!(
+            String::from_utf8(symbols).unwrap(),
+            "0x200 main\n0x210 data\n"
+        );
+
+        // Moving main with :org keeps the jump to it
+        let assembly = assemble(":org 0x300 : main clear", Target::Chip8).unwrap();
+        assert_eq!(assembly.symbols["main"], 0x300);
+        assert_eq!(&assembly.bytes[..2], &[0x13, 0x00]);
+        assert_eq!(&assembly.bytes[0x100..], &[0x00, 0xE0]);
+    }
+
+    #[test]
+    fn test_xo_chip() {
+        let source = ": main i := long data save v1 - v3 plane 2 :org 0x1234 : data 0xFF";
+        let assembly = assemble(source, Target::XoChip).unwrap();
+        assert_eq!(
+            &assembly.bytes[..8],
+            &[0xF0, 0x00, 0x12, 0x34, 0x51, 0x32, 0xF2, 0x01]
+        );
+        assert_eq!(assembly.bytes.len(), 0x1035);
+
+        let error = assemble(source, Target::Chip8).unwrap_err();
+        assert_eq!(
+            error.message,
+            "'long' is an XO-CHIP instruction, which needs an .xo8 target"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = assemble(": main\n  v1 := 300\n", Target::Chip8).unwrap_err();
+        assert_eq!((error.line, error.column), (2, 9));
+        assert_eq!(error.to_string(), "2:9: 300 does not fit in a byte");
+
+        let error = assemble(": main jump nowhere", Target::Chip8).unwrap_err();
+        assert_eq!(error.message, "undefined label 'nowhere'");
+        assert_eq!(error.column, 13);
+
+        assert!(assemble(": main loop", Target::Chip8).is_err());
+        assert!(assemble("clear", Target::Chip8).is_err());
+        assert!(assemble(": main :org 0x1000 clear", Target::Chip8).is_err());
+        assert!(assemble(": main :org 0x1000 clear", Target::XoChip).is_ok());
+        assert!(assemble(": main :org 0x100 clear", Target::Chip8).is_err());
+
+        let error = assemble(": main\n:macro m { m }\nm", Target::Chip8).unwrap_err();
+        assert_eq!(error.message, "macro recursion too deep");
+        assert_eq!((error.line, error.column), (2, 12));
+    }
+
+    #[test]
+    fn test_disassembly_round_trip() {
+        let rom = [
+            0x22, 0x0A, 0x30, 0x01, 0x12, 0x00, 0x12, 0x06, 0xF0, 0x90, 0xA2, 0x08, 0xD0, 0x12,
+            0x00, 0xEE, 0x51, 0x21,
+        ];
+        let mut source = vec![];
+        Program::analyze(&rom, PROGRAM_START, &[])
+            .write_source(&mut source, Syntax::Octo)
+            .unwrap();
+
+        assert_eq!(bytes(&String::from_utf8(source).unwrap()), rom);
+    }
+}
@@ -0,0 +1,109 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::process;
+
+use chip8::asm::{assemble, Target};
+
+const USAGE: &str = "\
+Usage: chip8-asm [OPTIONS] SOURCE
+
+Options:
+    -o, --output <FILE>        Where to write the program (default: SOURCE with a .ch8 extension)
+    --symbols <FILE>           Write the address of every label, one `address name` per line
+    -h, --help                 Print this help
+
+SOURCE is written in Octo's syntax. Programs written to a .xo8 file may use XO-CHIP's 64 KiB
+of memory, reached with `i := long`, and its other instructions. Others are limited to 4 KiB.";
+
+/// Options of the assembler
+struct AsmOptions {
+    source: PathBuf,
+    output: PathBuf,
+    symbols: Option<PathBuf>,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(&options) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/// Assembles the source and writes the program and symbol map
+///
+/// # Arguments
+///
+/// * `options` - The parsed command line
+fn run(options: &AsmOptions) -> Result<(), String> {
+    let source = fs::read_to_string(&options.source)
+        .map_err(|err| format!("Failed to read {}: {}", options.source.display(), err))?;
+    let target = options
+        .output
+        .extension()
+        .and_then(|extension| Target::from_extension(&extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    let assembly =
+        assemble(&source, target).map_err(|err| format!("{}:{}", options.source.display(), err))?;
+
+    fs::write(&options.output, &assembly.bytes)
+        .map_err(|err| format!("Failed to write {}: {}", options.output.display(), err))?;
+
+    if let Some(path) = options.symbols.as_ref() {
+        File::create(path)
+            .and_then(|file| assembly.write_symbols(BufWriter::new(file)))
+            .map_err(|err| format!("Failed to write {}: {}", path.display(), err))?;
+    }
+
+    Ok(())
+}
+
+/// Parses the command line, returning `None` when help was requested
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments, excluding the program name
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<AsmOptions>, String> {
+    let mut source = None;
+    let mut output = None;
+    let mut symbols = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", name))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output = Some(PathBuf::from(value(&arg)?)),
+            "--symbols" => symbols = Some(PathBuf::from(value(&arg)?)),
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if source.is_none() => source = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    let source = source.ok_or_else(|| String::from("missing SOURCE"))?;
+    let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+
+    Ok(Some(AsmOptions {
+        source,
+        output,
+        symbols,
+    }))
+}
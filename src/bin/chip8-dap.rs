@@ -0,0 +1,88 @@
+use std::env;
+use std::io::{self, BufReader};
+use std::process;
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+use chip8::dap::{read_message, write_message, Adapter};
+use chip8::json::Json;
+
+const USAGE: &str = "\
+Usage: chip8-dap
+
+Options:
+    -h, --help                 Print this help
+
+Serves the Debug Adapter Protocol over stdin and stdout, for editors such as VS Code. The
+program to debug is given by the `program` of the launch request, either a ROM or Octo source,
+which is assembled and can then have breakpoints on its lines and labels. ROMs can be given
+labels by a symbol map in the `symbols` of the launch request.";
+
+fn main() {
+    match env::args().nth(1).as_deref() {
+        None => {}
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            return;
+        }
+        Some(arg) => {
+            eprintln!("Unknown argument '{}'\n\n{}", arg, USAGE);
+            process::exit(2);
+        }
+    }
+
+    // Requests are read on their own thread so a running program can be paused
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut reader = BufReader::new(stdin.lock());
+
+        loop {
+            let message = read_message(&mut reader);
+            let end = !matches!(message, Ok(Some(_)));
+            if sender.send(message).is_err() || end {
+                break;
+            }
+        }
+    });
+
+    let mut adapter = Adapter::new();
+    let stdout = io::stdout();
+    let mut writer = stdout.lock();
+
+    while !adapter.is_done() {
+        let message = if adapter.is_running() {
+            match receiver.try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => {
+                    send(&mut writer, adapter.run_slice());
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match receiver.recv() {
+                Ok(message) => message,
+                Err(_) => break,
+            }
+        };
+
+        match message {
+            Ok(Some(request)) => send(&mut writer, adapter.handle(&request)),
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn send<W: io::Write>(writer: &mut W, messages: Vec<Json>) {
+    for message in messages {
+        if let Err(err) = write_message(writer, &message) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
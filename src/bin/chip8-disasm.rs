@@ -0,0 +1,142 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::process;
+
+use chip8::disasm::{disassemble, write_listing, Syntax, PROGRAM_START};
+use chip8::flow::{Coverage, Program};
+use chip8::headless::parse_number;
+use chip8::symbols::Symbols;
+use chip8::trace::write_binary_as_text;
+
+const USAGE: &str = "\
+Usage: chip8-disasm [OPTIONS] ROM
+
+Options:
+    --syntax <cowgod|octo>     Assembly syntax to write (default: cowgod)
+    --origin <ADDR>            Address the ROM is loaded at (default: 0x200)
+    --source                   Follow the program's control flow and write source that assembles
+                               back to the ROM, with labels and code separated from data
+    --coverage <FILE>          Also follow the addresses in FILE, as written by chip8-headless
+    --trace                    ROM is a binary trace written by chip8-headless; print it as text
+    --symbols <FILE>           Name addresses after the labels in FILE, a symbol map or Octo source
+    -h, --help                 Print this help
+
+By default, every pair of bytes is listed as an instruction with its address and raw bytes.
+Bytes that do not decode to an instruction are listed as data.";
+
+/// Options of the disassembler
+struct DisasmOptions {
+    rom: PathBuf,
+    syntax: Syntax,
+    origin: usize,
+    source: bool,
+    coverage: Vec<usize>,
+    trace: bool,
+    symbols: Symbols,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let rom = match fs::read(&options.rom) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("Failed to load {}: {}", options.rom.display(), err);
+            process::exit(1);
+        }
+    };
+
+    let stdout = io::stdout();
+    let writer = BufWriter::new(stdout.lock());
+    let result = if options.trace {
+        write_binary_as_text(&rom[..], writer, &options.symbols)
+    } else if options.source {
+        let mut program = Program::analyze(&rom, options.origin, &options.coverage);
+        program.set_symbols(options.symbols);
+        program.write_source(writer, options.syntax)
+    } else {
+        let lines = disassemble(&rom, options.origin);
+        write_listing(writer, &lines, options.syntax, &options.symbols)
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/// Parses the command line, returning `None` when help was requested
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments, excluding the program name
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<DisasmOptions>, String> {
+    let mut rom = None;
+    let mut syntax = Syntax::default();
+    let mut origin = PROGRAM_START;
+    let mut source = false;
+    let mut coverage = vec![];
+    let mut trace = false;
+    let mut symbols = Symbols::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", name))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--syntax" => {
+                let name = value(&arg)?;
+                syntax =
+                    Syntax::parse(&name).ok_or_else(|| format!("unknown syntax '{}'", name))?;
+            }
+            "--origin" => {
+                let address = value(&arg)?;
+                origin = parse_number(&address)
+                    .ok_or_else(|| format!("invalid address '{}'", address))?
+                    as usize;
+            }
+            "--source" => source = true,
+            "--coverage" => {
+                let path = value(&arg)?;
+                let trace = fs::read_to_string(&path)
+                    .map_err(|err| format!("failed to read {}: {}", path, err))?;
+                coverage = Coverage::parse(&trace)
+                    .map_err(|err| format!("{}: {}", path, err))?
+                    .get_addresses();
+            }
+            "--trace" => trace = true,
+            "--symbols" => symbols = Symbols::load(value(&arg)?)?,
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    match rom {
+        Some(rom) => Ok(Some(DisasmOptions {
+            rom,
+            syntax,
+            origin,
+            source,
+            coverage,
+            trace,
+            symbols,
+        })),
+        None => Err(String::from("missing ROM")),
+    }
+}
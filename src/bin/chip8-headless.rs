@@ -0,0 +1,430 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chip8::cli::{Options, OPTIONS_HELP};
+use chip8::debugger::Debugger;
+use chip8::dump::{write_json, write_pbm};
+use chip8::flow::Coverage;
+use chip8::gdb;
+use chip8::headless::{KeyScript, Runner, StopReason};
+use chip8::heatmap::Heatmap;
+use chip8::profile::Profiler;
+use chip8::record::Recorder;
+use chip8::symbols::Symbols;
+use chip8::system::System;
+use chip8::trace::{parse_range, TraceFilter, TraceFormat, Tracer};
+
+const HEADLESS_HELP: &str = "\
+Headless options:
+    --frames <N>               Stop after N frames (default: 3600)
+    --until-pc <ADDR>          Stop when the program counter reaches ADDR, an address or label
+    --until-halt               Stop when the program jumps to itself or runs into empty memory
+    --break <ADDR>             Stop before the instruction at ADDR, an address or label, and
+                               report it. May be given more than once
+    --keys <FILE>              Feed keys from FILE, one `<frame> <mask>` pair per line
+    --json <FILE>              Write the final registers, memory and framebuffer as JSON
+    --pbm <FILE>               Write the final framebuffer as a plain PBM image
+    --coverage <FILE>          Write the address of every executed instruction, for chip8-disasm
+    --trace <FILE>             Write the registers before every executed instruction
+    --trace-format <FORMAT>    Trace format: text, one line per instruction for diffing, symbolic,
+                               text naming addresses after --symbols, or binary, which
+                               chip8-disasm --trace turns back into text (default: text)
+    --trace-pc <START-END>     Only trace instructions at addresses from START to END
+    --trace-frames <START-END> Only trace instructions in frames START to END, counted from 0
+    --profile <FILE>           Write a report of where the ROM spends its cycles: the most
+                               executed addresses, opcode classes and subroutines, and the time
+                               spent drawing, waiting and on logic
+    --profile-stacks <FILE>    Write the cycles of every call stack in the collapsed format of
+                               flame graph tools
+    --heatmap <FILE>           Write a PNG of how often every address was read (green), written
+                               (red) and executed (blue), beside a hex view of memory
+    --heatmap-hex <FILE>       Write a hex view of memory marking how every byte was used
+    --heatmap-decay <FACTOR>   Multiply the heatmap's counts by FACTOR every frame, to show
+                               recent activity (default: 1, no decay)
+    --symbols <FILE>           Name addresses after the labels in FILE, a symbol map of `ADDR NAME`
+                               lines or Octo source, in symbolic traces, profiles and the debugger
+    --debug                    Step through the ROM at an interactive prompt instead, type help
+                               at the prompt for its commands
+    --gdb <PORT>               Wait for a GDB remote connection on 127.0.0.1:PORT and let it
+                               drive the ROM instead
+
+FILE may be - for stdout. Exits with status 1 if --until-pc or --until-halt was given but the
+frame limit was reached first.";
+
+/// Options only understood by the headless runner
+struct HeadlessOptions {
+    runner: Runner,
+    json: Option<PathBuf>,
+    pbm: Option<PathBuf>,
+    coverage: Option<PathBuf>,
+    trace: Option<PathBuf>,
+    trace_format: TraceFormat,
+    trace_filter: TraceFilter,
+    profile: Option<PathBuf>,
+    profile_stacks: Option<PathBuf>,
+    heatmap: Option<PathBuf>,
+    heatmap_hex: Option<PathBuf>,
+    heatmap_decay: f32,
+    symbols: Symbols,
+    debug: bool,
+    gdb: Option<u16>,
+}
+
+fn main() {
+    let (headless, options) = match parse_args(env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, usage());
+            process::exit(2);
+        }
+    };
+
+    if options.help {
+        println!("{}", usage());
+        return;
+    }
+
+    let mut system = match System::load_rom(&options.rom) {
+        Ok(system) => system,
+        Err(err) => {
+            eprintln!("Failed to load {}: {}", options.rom.display(), err);
+            process::exit(1);
+        }
+    };
+
+    system.set_symbols(headless.symbols.clone());
+    system.set_quirks(options.quirks);
+
+    if headless.debug {
+        if let Err(err) = debug(&mut system) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(port) = headless.gdb {
+        if let Err(err) = serve_gdb(&mut system, port) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    match run(&mut system, &headless, &options) {
+        Ok(StopReason::Frames)
+            if headless.runner.until_pc.is_some() || headless.runner.until_halt =>
+        {
+            process::exit(1)
+        }
+        Ok(_) => {}
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn usage() -> String {
+    format!(
+        "Usage: chip8-headless [OPTIONS] ROM\n\n{}\n\n{}",
+        HEADLESS_HELP, OPTIONS_HELP
+    )
+}
+
+/// Separates the headless options from the options shared with the other frontends
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments, excluding the program name
+fn parse_args<I: Iterator<Item = String>>(
+    mut args: I,
+) -> Result<(HeadlessOptions, Options), String> {
+    let mut headless = HeadlessOptions {
+        runner: Runner {
+            max_frames: 3600,
+            ..Runner::default()
+        },
+        json: None,
+        pbm: None,
+        coverage: None,
+        trace: None,
+        trace_format: TraceFormat::default(),
+        trace_filter: TraceFilter::default(),
+        profile: None,
+        profile_stacks: None,
+        heatmap: None,
+        heatmap_hex: None,
+        heatmap_decay: 1.0,
+        symbols: Symbols::new(),
+        debug: false,
+        gdb: None,
+    };
+    let mut shared = vec![];
+    let mut until_pc = None;
+    let mut breakpoints = vec![];
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", name))
+        };
+
+        match arg.as_str() {
+            "--frames" => {
+                let frames = value(&arg)?;
+                headless.runner.max_frames = frames
+                    .parse()
+                    .map_err(|_| format!("invalid frame count '{}'", frames))?;
+            }
+            // Resolved once all options are read, as it may name a label from --symbols
+            "--until-pc" => until_pc = Some(value(&arg)?),
+            "--until-halt" => headless.runner.until_halt = true,
+            "--break" => breakpoints.push(value(&arg)?),
+            "--keys" => {
+                let path = value(&arg)?;
+                let source = fs::read_to_string(&path)
+                    .map_err(|err| format!("failed to read {}: {}", path, err))?;
+                headless.runner.keys =
+                    KeyScript::parse(&source).map_err(|err| format!("{}: {}", path, err))?;
+            }
+            "--json" => headless.json = Some(PathBuf::from(value(&arg)?)),
+            "--pbm" => headless.pbm = Some(PathBuf::from(value(&arg)?)),
+            "--coverage" => headless.coverage = Some(PathBuf::from(value(&arg)?)),
+            "--trace" => headless.trace = Some(PathBuf::from(value(&arg)?)),
+            "--trace-format" => {
+                let format = value(&arg)?;
+                headless.trace_format = TraceFormat::parse(&format)
+                    .ok_or_else(|| format!("invalid trace format '{}'", format))?;
+            }
+            "--trace-pc" => {
+                let range = value(&arg)?;
+                let (start, end) =
+                    parse_range(&range).ok_or_else(|| format!("invalid range '{}'", range))?;
+                headless.trace_filter.addresses = Some((start as usize, end as usize));
+            }
+            "--trace-frames" => {
+                let range = value(&arg)?;
+                let (start, end) =
+                    parse_range(&range).ok_or_else(|| format!("invalid range '{}'", range))?;
+                headless.trace_filter.frames = Some((start as u64, end as u64));
+            }
+            "--profile" => headless.profile = Some(PathBuf::from(value(&arg)?)),
+            "--profile-stacks" => headless.profile_stacks = Some(PathBuf::from(value(&arg)?)),
+            "--heatmap" => headless.heatmap = Some(PathBuf::from(value(&arg)?)),
+            "--heatmap-hex" => headless.heatmap_hex = Some(PathBuf::from(value(&arg)?)),
+            "--heatmap-decay" => {
+                let decay = value(&arg)?;
+                headless.heatmap_decay = decay
+                    .parse()
+                    .ok()
+                    .filter(|decay| (0.0..=1.0).contains(decay))
+                    .ok_or_else(|| format!("invalid decay '{}', expected 0 to 1", decay))?;
+            }
+            "--symbols" => headless.symbols = Symbols::load(value(&arg)?)?,
+            "--debug" => headless.debug = true,
+            "--gdb" => {
+                let port = value(&arg)?;
+                headless.gdb = Some(
+                    port.parse()
+                        .map_err(|_| format!("invalid port '{}'", port))?,
+                );
+            }
+            _ => shared.push(arg),
+        }
+    }
+
+    if let Some(address) = until_pc {
+        headless.runner.until_pc = Some(
+            headless
+                .symbols
+                .resolve(&address)
+                .ok_or_else(|| format!("invalid address '{}'", address))?,
+        );
+    }
+
+    for address in breakpoints {
+        headless.runner.breakpoints.push(
+            headless
+                .symbols
+                .resolve(&address)
+                .ok_or_else(|| format!("invalid address '{}'", address))?,
+        );
+    }
+
+    Ok((headless, Options::parse(shared)?))
+}
+
+/// Runs the ROM, then writes the requested dumps
+///
+/// # Arguments
+///
+/// * `system` - The system to run
+/// * `headless` - The headless options
+/// * `options` - The options shared with the other frontends
+fn run(
+    system: &mut System,
+    headless: &HeadlessOptions,
+    options: &Options,
+) -> io::Result<StopReason> {
+    let mut recorder = match options.record.as_ref() {
+        Some(path) => Some(Recorder::create(
+            path,
+            options.record_format,
+            options.palette,
+            options.scale,
+        )?),
+        None => None,
+    };
+
+    let mut tracer = match headless.trace.as_ref() {
+        Some(path) => Some(Tracer::new(
+            output(path)?,
+            headless.trace_format,
+            headless.trace_filter,
+        )?),
+        None => None,
+    };
+
+    if headless.heatmap.is_some() || headless.heatmap_hex.is_some() {
+        system.set_heatmap(Some(Heatmap::new(headless.heatmap_decay)));
+    }
+
+    let mut record_error = None;
+    let mut trace_error = None;
+    let mut coverage = Coverage::default();
+    let mut profiler = Profiler::new();
+    let profiling = headless.profile.is_some() || headless.profile_stacks.is_some();
+    let (reason, frames) = headless.runner.run_traced(
+        system,
+        |system| {
+            if headless.coverage.is_some() {
+                coverage.add(system.get_pc());
+            }
+            if profiling {
+                profiler.record(system);
+            }
+            if let Some(tracer) = tracer.as_mut() {
+                if let Err(err) = tracer.trace(system) {
+                    trace_error.get_or_insert(err);
+                }
+            }
+        },
+        |system| {
+            if let Some(recorder) = recorder.as_mut() {
+                if let Err(err) = recorder.record_frame(system) {
+                    record_error.get_or_insert(err);
+                }
+            }
+        },
+    );
+
+    if let Some(err) = record_error.or(trace_error) {
+        return Err(err);
+    }
+    if let Some(tracer) = tracer {
+        tracer.finish()?;
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+
+    if let StopReason::Break(hit) = reason {
+        eprintln!("{}", hit.describe(system.get_symbols()));
+    }
+    eprintln!(
+        "Stopped after {} frames ({}) at pc {}",
+        frames,
+        reason.name(),
+        system.get_symbols().format(system.get_pc())
+    );
+
+    if let Some(path) = headless.json.as_ref() {
+        write_json(output(path)?, system)?;
+    }
+    if let Some(path) = headless.pbm.as_ref() {
+        write_pbm(output(path)?, system)?;
+    }
+    if let Some(path) = headless.coverage.as_ref() {
+        coverage.write(output(path)?)?;
+    }
+    if let Some(heatmap) = system.get_heatmap() {
+        if let Some(path) = headless.heatmap.as_ref() {
+            heatmap.write_png(output(path)?, system.get_memory())?;
+        }
+        if let Some(path) = headless.heatmap_hex.as_ref() {
+            heatmap.write_hex(output(path)?, system.get_memory())?;
+        }
+    }
+    if let Some(path) = headless.profile.as_ref() {
+        profiler.write_report(output(path)?, system.get_symbols())?;
+    }
+    if let Some(path) = headless.profile_stacks.as_ref() {
+        profiler.write_collapsed(output(path)?, system.get_symbols())?;
+    }
+
+    Ok(reason)
+}
+
+/// Reads debugger commands from stdin until `quit` or the end of input
+///
+/// # Arguments
+///
+/// * `system` - The system to debug
+fn debug(system: &mut System) -> io::Result<()> {
+    let mut debugger = Debugger::new();
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    write!(
+        stdout,
+        "{}",
+        debugger.execute(system, "disasm").unwrap_or_default()
+    )?;
+
+    loop {
+        write!(stdout, "(chip8) ")?;
+        stdout.flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 || matches!(line.trim(), "quit" | "q") {
+            return Ok(());
+        }
+
+        match debugger.execute(system, &line) {
+            Ok(output) => write!(stdout, "{}", output)?,
+            Err(err) => writeln!(stdout, "Error: {}", err)?,
+        }
+    }
+}
+
+/// Waits for a GDB connection on a local port and serves it until it detaches
+///
+/// # Arguments
+///
+/// * `system` - The system to debug
+/// * `port` - The port to listen on
+fn serve_gdb(system: &mut System, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!(
+        "Waiting for GDB on {}, connect with: target remote {}",
+        listener.local_addr()?,
+        listener.local_addr()?
+    );
+
+    let (stream, _) = listener.accept()?;
+    gdb::serve(stream, system)
+}
+
+/// Opens a file for writing, or stdout when `path` is `-`
+fn output(path: &Path) -> io::Result<Box<dyn Write>> {
+    if path == Path::new("-") {
+        Ok(Box::new(io::stdout()))
+    } else {
+        Ok(Box::new(BufWriter::new(File::create(path)?)))
+    }
+}
@@ -0,0 +1,259 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chip8::headless::{parse_number, KeyScript, Runner};
+use chip8::palette::Palette;
+use chip8::sprites::{DrawLog, Layout, Sheet};
+use chip8::symbols::Symbols;
+use chip8::system::System;
+
+const USAGE: &str = "\
+Usage: chip8-sprites [OPTIONS] ROM
+
+Options:
+    -o, --output <FILE>        Where to write the sheet of sprites, a PNG (default: ROM with a .png
+                               extension)
+    --range <START-END>        The addresses to show, inclusive (default: the ROM's)
+    --height <N>               Rows of each sprite, 1 to 15 (default: 8)
+    --wide                     Show 16x16 SCHIP sprites of 32 bytes instead, which the emulator
+                               does not draw itself
+    --columns <N>              Sprites per row of the sheet (default: 16)
+    --scale <N>                Image pixels per sprite pixel (default: 4)
+    --palette <BG:FG>          Background and foreground colors, e.g. 000000:FFFFFF
+    --frames <N>               Run the ROM for N frames first and highlight the memory DXYN drew
+                               from, fading over the last frames (default: 0)
+    --keys <FILE>              Feed keys from FILE during the run, see chip8-headless
+    --recent <N>               How many frames draws stay highlighted (default: 60)
+    --export <DIR>             Also write each selected sprite to DIR as its own PNG, named after
+                               its address
+    --select <ADDR,...>        The sprites to export, or drawn for every address DXYN drew from,
+                               as tall as the draw (default: drawn)
+    --symbols <FILE>           Accept labels from FILE, a symbol map or Octo source, as addresses
+    -h, --help                 Print this help
+
+FILE may be - for stdout.";
+
+/// Options of the sprite viewer
+struct SpritesOptions {
+    rom: PathBuf,
+    output: PathBuf,
+    range: Option<(usize, usize)>,
+    sheet: Sheet,
+    runner: Runner,
+    export: Option<PathBuf>,
+    select: Option<Vec<usize>>,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(&options) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/// Runs the ROM if asked to, then writes the sheet and exported sprites
+///
+/// # Arguments
+///
+/// * `options` - The parsed command line
+fn run(options: &SpritesOptions) -> Result<(), String> {
+    let error = |path: &Path, err: io::Error| format!("{}: {}", path.display(), err);
+    let mut system = System::load_rom(&options.rom).map_err(|err| error(&options.rom, err))?;
+    let len = fs::metadata(&options.rom)
+        .map_err(|err| error(&options.rom, err))?
+        .len() as usize;
+
+    let mut log = DrawLog::new();
+    if options.runner.max_frames > 0 {
+        options
+            .runner
+            .run_traced(&mut system, |system| log.record(system), |_| {});
+    }
+
+    let (start, end) = options.range.unwrap_or((0x200, 0x200 + len.max(1) - 1));
+    let memory = system.get_memory();
+    output(&options.output)
+        .and_then(|writer| {
+            options
+                .sheet
+                .write_png(writer, memory, start, end + 1, &log)
+        })
+        .map_err(|err| error(&options.output, err))?;
+
+    let dir = match options.export.as_ref() {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+
+    // Drawn sprites are exported as tall as they were drawn
+    let sprites: Vec<(usize, Layout)> = match options.select.as_ref() {
+        Some(addresses) => addresses
+            .iter()
+            .map(|address| (*address, options.sheet.layout))
+            .collect(),
+        None => log
+            .get_targets()
+            .iter()
+            .map(|(address, len)| (*address, Layout::Narrow(*len)))
+            .collect(),
+    };
+
+    fs::create_dir_all(dir).map_err(|err| error(dir, err))?;
+    for (address, layout) in sprites.iter() {
+        let path = dir.join(format!("{:03x}.png", address));
+        let sheet = Sheet {
+            layout: *layout,
+            ..options.sheet
+        };
+
+        output(&path)
+            .and_then(|writer| sheet.write_sprite_png(writer, memory, *address))
+            .map_err(|err| error(&path, err))?;
+    }
+
+    eprintln!("Exported {} sprites to {}", sprites.len(), dir.display());
+
+    Ok(())
+}
+
+/// Opens a file for writing, or stdout for `-`
+fn output(path: &Path) -> io::Result<Box<dyn Write>> {
+    if path == Path::new("-") {
+        Ok(Box::new(BufWriter::new(io::stdout())))
+    } else {
+        Ok(Box::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+/// Parses a number above zero
+fn positive(text: &str) -> Result<u32, String> {
+    parse_number(text)
+        .filter(|number| *number > 0)
+        .ok_or_else(|| format!("invalid number '{}'", text))
+}
+
+/// Parses the command line, returning `None` when help was requested
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments, excluding the program name
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<SpritesOptions>, String> {
+    let mut rom = None;
+    let mut output = None;
+    let mut sheet = Sheet::default();
+    let mut runner = Runner::default();
+    let mut export = None;
+    let mut symbols = Symbols::new();
+    // Addresses are resolved once all options are read, as they may name labels from --symbols
+    let mut range = None;
+    let mut select = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", name))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output = Some(PathBuf::from(value(&arg)?)),
+            "--range" => range = Some(value(&arg)?),
+            "--height" => {
+                sheet.layout = match positive(&value(&arg)?)? {
+                    rows @ 1..=15 => Layout::Narrow(rows as usize),
+                    rows => return Err(format!("invalid height '{}', expected 1 to 15", rows)),
+                }
+            }
+            "--wide" => sheet.layout = Layout::Wide,
+            "--columns" => sheet.columns = positive(&value(&arg)?)? as usize,
+            "--scale" => sheet.scale = positive(&value(&arg)?)? as usize,
+            "--palette" => {
+                let palette = value(&arg)?;
+                sheet.palette = Palette::parse(&palette)
+                    .ok_or_else(|| format!("invalid palette '{}'", palette))?;
+            }
+            "--frames" => {
+                let frames = value(&arg)?;
+                runner.max_frames = frames
+                    .parse()
+                    .map_err(|_| format!("invalid frame count '{}'", frames))?;
+            }
+            "--keys" => {
+                let path = value(&arg)?;
+                let source = fs::read_to_string(&path)
+                    .map_err(|err| format!("failed to read {}: {}", path, err))?;
+                runner.keys =
+                    KeyScript::parse(&source).map_err(|err| format!("{}: {}", path, err))?;
+            }
+            "--recent" => sheet.recent = positive(&value(&arg)?)? as u64,
+            "--export" => export = Some(PathBuf::from(value(&arg)?)),
+            "--select" => select = Some(value(&arg)?),
+            "--symbols" => symbols = Symbols::load(value(&arg)?)?,
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option '{}'", arg))
+            }
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    let address = |text: &str| {
+        symbols
+            .resolve(text)
+            .filter(|address| *address < 0x1000)
+            .ok_or_else(|| format!("invalid address '{}'", text))
+    };
+
+    let range = match range {
+        Some(range) => {
+            let (start, end) = range
+                .split_once('-')
+                .ok_or_else(|| format!("invalid range '{}', expected START-END", range))?;
+            let (start, end) = (address(start)?, address(end)?);
+            if start > end {
+                return Err(format!("invalid range '{}', START is after END", range));
+            }
+            Some((start, end))
+        }
+        None => None,
+    };
+
+    let select = match select.as_deref() {
+        Some(_) if export.is_none() => return Err(String::from("--select needs --export")),
+        Some("drawn") | None => None,
+        Some(addresses) => Some(
+            addresses
+                .split(',')
+                .map(address)
+                .collect::<Result<Vec<usize>, String>>()?,
+        ),
+    };
+
+    let rom = rom.ok_or_else(|| String::from("missing ROM"))?;
+    let output = output.unwrap_or_else(|| rom.with_extension("png"));
+
+    Ok(Some(SpritesOptions {
+        rom,
+        output,
+        range,
+        sheet,
+        runner,
+        export,
+        select,
+    }))
+}
@@ -0,0 +1,300 @@
+use std::env;
+use std::io::{self, Stdout, Write};
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Colors, Print, ResetColor, SetColors};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use chip8::cli::{usage, Options};
+use chip8::keymap::Keymap;
+use chip8::palette::Palette;
+use chip8::record::Recorder;
+use chip8::system::{System, HEIGHT, WIDTH};
+use chip8::timing::FrameClock;
+
+/// How long a key stays held after its first press when the terminal cannot report releases.
+/// This has to outlast the typical auto-repeat delay so held keys do not flicker.
+const INITIAL_HOLD: Duration = Duration::from_millis(250);
+
+/// How long a key stays held after an auto-repeat event when the terminal cannot report releases
+const REPEAT_HOLD: Duration = Duration::from_millis(100);
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, usage("chip8-tui"));
+            process::exit(2);
+        }
+    };
+
+    if options.help {
+        println!("{}", usage("chip8-tui"));
+        return;
+    }
+
+    if options.record.as_deref() == Some(Path::new("-")) {
+        eprintln!("Recording to stdout is not supported by the terminal frontend");
+        process::exit(2);
+    }
+
+    let mut system = match System::load_rom(&options.rom) {
+        Ok(system) => system,
+        Err(err) => {
+            eprintln!("Failed to load {}: {}", options.rom.display(), err);
+            process::exit(1);
+        }
+    };
+    system.set_quirks(options.quirks);
+
+    if let Err(err) = run(system, &options) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/// Runs the emulator at 60 frames per second until the user quits
+///
+/// # Arguments
+///
+/// * `system` - The system to run, with a ROM already loaded
+/// * `options` - The command line options
+fn run(mut system: System, options: &Options) -> io::Result<()> {
+    let mut recorder = match options.record.as_ref() {
+        Some(path) => Some(Recorder::create(
+            path,
+            options.record_format,
+            options.palette,
+            options.scale,
+        )?),
+        None => None,
+    };
+
+    let mut terminal = Terminal::new()?;
+    let mut keys = Keys::new(terminal.reports_releases);
+    let mut status = String::from("Esc: quit  F12: screenshot");
+
+    let mut clock = FrameClock::new(Instant::now());
+
+    loop {
+        while event::poll(clock.next_frame().saturating_duration_since(Instant::now()))? {
+            let key_event = match event::read()? {
+                Event::Key(key_event) => key_event,
+                Event::Resize(..) => {
+                    execute!(terminal.stdout, Clear(ClearType::All))?;
+                    continue;
+                }
+                _ => continue,
+            };
+
+            match key_event {
+                KeyEvent {
+                    code: KeyCode::Esc, ..
+                } => return finish(recorder),
+                KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                } => return finish(recorder),
+                KeyEvent {
+                    code: KeyCode::F(12),
+                    kind: KeyEventKind::Press,
+                    ..
+                } => {
+                    status = match system.save_screenshot(".", &options.palette, options.scale) {
+                        Ok(path) => format!("Saved screenshot to {}", path.display()),
+                        Err(err) => format!("Failed to save screenshot: {}", err),
+                    };
+                }
+                KeyEvent {
+                    code: KeyCode::Char(c),
+                    kind,
+                    ..
+                } => keys.handle(&options.keymap, c, kind, Instant::now()),
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        let frames = clock.advance(now);
+        if frames == 0 {
+            continue;
+        }
+
+        let mask = (0..16)
+            .filter(|key| keys.is_held(*key, now))
+            .fold(0u16, |mask, key| mask | 1 << key);
+        system.set_keys(mask);
+
+        for _ in 0..frames {
+            system.run_to_next_frame();
+
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.record_frame(&system)?;
+            }
+        }
+
+        draw(&mut terminal.stdout, &system, &options.palette, &status)?;
+    }
+}
+
+/// Finishes the recording, if any, before quitting
+fn finish(recorder: Option<Recorder>) -> io::Result<()> {
+    match recorder {
+        Some(recorder) => recorder.finish(),
+        None => Ok(()),
+    }
+}
+
+/// Draws the framebuffer using upper half-blocks, so every character cell shows two pixels: the
+/// top one as the foreground color and the bottom one as the background color
+///
+/// # Arguments
+///
+/// * `out` - The terminal to draw to
+/// * `system` - The system whose framebuffer is drawn
+/// * `palette` - The colors used for unlit and lit pixels
+/// * `status` - A message shown underneath the screen
+fn draw<W: Write>(out: &mut W, system: &System, palette: &Palette, status: &str) -> io::Result<()> {
+    let rgb = |color: [u8; 3]| Color::Rgb {
+        r: color[0],
+        g: color[1],
+        b: color[2],
+    };
+
+    for row in 0..HEIGHT.div_ceil(2) {
+        queue!(out, MoveTo(0, row as u16))?;
+
+        let mut previous = None;
+        for x in 0..WIDTH {
+            let top = system.get_pixel(x, row * 2);
+            let bottom = row * 2 + 1 < HEIGHT && system.get_pixel(x, row * 2 + 1);
+
+            if previous != Some((top, bottom)) {
+                let colors = Colors::new(rgb(palette.color(top)), rgb(palette.color(bottom)));
+                queue!(out, SetColors(colors))?;
+                previous = Some((top, bottom));
+            }
+
+            queue!(out, Print('\u{2580}'))?;
+        }
+    }
+
+    queue!(
+        out,
+        ResetColor,
+        MoveTo(0, HEIGHT.div_ceil(2) as u16),
+        Clear(ClearType::CurrentLine),
+        Print(status)
+    )?;
+
+    out.flush()
+}
+
+/// Tracks which CHIP-8 keys are held. Most terminals only send key presses and auto-repeats, so
+/// unless the terminal reports releases, a key is released once no repeat arrived for a while.
+struct Keys {
+    reports_releases: bool,
+    held: [bool; 16],
+    held_until: [Option<Instant>; 16],
+}
+
+impl Keys {
+    fn new(reports_releases: bool) -> Self {
+        Self {
+            reports_releases,
+            held: [false; 16],
+            held_until: [None; 16],
+        }
+    }
+
+    /// Updates the key state from a terminal key event
+    ///
+    /// # Arguments
+    ///
+    /// * `keymap` - Maps the typed character to a CHIP-8 key
+    /// * `c` - The character typed
+    /// * `kind` - Whether the key was pressed, repeated or released
+    /// * `now` - When the event was received
+    fn handle(&mut self, keymap: &Keymap, c: char, kind: KeyEventKind, now: Instant) {
+        let key = match keymap.key_for(c) {
+            Some(key) => key as usize,
+            None => return,
+        };
+
+        if self.reports_releases {
+            self.held[key] = kind != KeyEventKind::Release;
+            return;
+        }
+
+        let hold = if self.held_until[key].is_some_and(|until| until > now) {
+            REPEAT_HOLD
+        } else {
+            INITIAL_HOLD
+        };
+
+        self.held_until[key] = Some(now + hold);
+    }
+
+    /// Returns whether a CHIP-8 key is currently held
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The CHIP-8 key, from `0x0` to `0xF`
+    /// * `now` - The current time
+    fn is_held(&self, key: u8, now: Instant) -> bool {
+        if self.reports_releases {
+            self.held[key as usize]
+        } else {
+            self.held_until[key as usize].is_some_and(|until| until > now)
+        }
+    }
+}
+
+/// Puts the terminal into raw mode on an alternate screen, restoring it when dropped
+struct Terminal {
+    stdout: Stdout,
+    reports_releases: bool,
+}
+
+impl Terminal {
+    fn new() -> io::Result<Self> {
+        let mut stdout = io::stdout();
+
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+
+        // Terminals implementing the kitty keyboard protocol can report real key releases
+        let reports_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_releases {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        Ok(Self {
+            stdout,
+            reports_releases,
+        })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.reports_releases {
+            let _ = execute!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+
+        let _ = execute!(self.stdout, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
@@ -0,0 +1,631 @@
+use crate::condition::{Expr, Message};
+use crate::symbols::Symbols;
+use crate::system::System;
+
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// How an instruction touched memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// The accesses a watchpoint stops at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    /// Parses `r`, `w` or `rw`
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The kind of watchpoint
+    pub fn parse(name: &str) -> Option<WatchKind> {
+        match name {
+            "r" => Some(WatchKind::Read),
+            "w" => Some(WatchKind::Write),
+            "rw" => Some(WatchKind::ReadWrite),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, access: Access) -> bool {
+        match self {
+            WatchKind::Read => access == Access::Read,
+            WatchKind::Write => access == Access::Write,
+            WatchKind::ReadWrite => true,
+        }
+    }
+}
+
+/// A range of memory that stops execution when an instruction reads or writes it. Reads are by
+/// `DXYN` sprites and `FX65`, writes by `FX33` and `FX55`. Fetching instructions does not count.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watch {
+    pub start: usize,
+    pub len: usize,
+    pub kind: WatchKind,
+}
+
+impl Watch {
+    fn overlaps(&self, start: usize, len: usize) -> Option<usize> {
+        let first = self.start.max(start);
+
+        if first < (self.start + self.len).min(start + len) {
+            Some(first)
+        } else {
+            None
+        }
+    }
+}
+
+/// A register that stops execution when an instruction changes it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register {
+    V(u8),
+    I,
+}
+
+impl Register {
+    /// Parses `v0` to `vf` or `i`, in either case
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the register
+    pub fn parse(name: &str) -> Option<Register> {
+        let name = name.to_ascii_lowercase();
+
+        if name == "i" {
+            return Some(Register::I);
+        }
+
+        let digit = name.strip_prefix('v')?;
+        match u8::from_str_radix(digit, 16) {
+            Ok(idx) if digit.len() == 1 => Some(Register::V(idx)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(idx) => write!(f, "V{:X}", idx),
+            Register::I => write!(f, "I"),
+        }
+    }
+}
+
+/// A class of opcodes written like the instruction tables, e.g. `DXYN` for any sprite draw or
+/// `FX33` for any BCD store. Hex digits must match and `X`, `Y`, `N` match anything.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OpcodePattern {
+    name: [char; 4],
+    mask: u16,
+    value: u16,
+}
+
+impl OpcodePattern {
+    /// Parses a pattern of 4 hex digits or wildcards
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The pattern, e.g. `DXYN` or `8XY4`
+    pub fn parse(pattern: &str) -> Option<OpcodePattern> {
+        let mut parsed = OpcodePattern {
+            name: [' '; 4],
+            mask: 0,
+            value: 0,
+        };
+
+        if pattern.chars().count() != 4 {
+            return None;
+        }
+
+        for (idx, c) in pattern.chars().enumerate() {
+            let c = c.to_ascii_uppercase();
+            let (mask, value) = match c {
+                'X' | 'Y' | 'N' => (0x0, 0x0),
+                _ => (0xF, c.to_digit(16)? as u16),
+            };
+
+            parsed.name[idx] = c;
+            parsed.mask = parsed.mask << 4 | mask;
+            parsed.value = parsed.value << 4 | value;
+        }
+
+        Some(parsed)
+    }
+
+    pub fn matches(&self, op: u16) -> bool {
+        op & self.mask == self.value
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name.iter().collect::<String>())
+    }
+}
+
+/// Why `System::tick` stopped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Break {
+    /// The next instruction is at a breakpoint
+    Address(usize),
+    /// The next instruction matches an opcode class
+    Opcode {
+        address: usize,
+        pattern: OpcodePattern,
+    },
+    /// The last instruction touched a watched address
+    Memory {
+        address: usize,
+        access: Access,
+        pc: usize,
+    },
+    /// The last instruction changed a watched register
+    Register {
+        register: Register,
+        old: u16,
+        new: u16,
+        pc: usize,
+    },
+}
+
+impl Break {
+    /// Describes the stop with addresses named by symbols where possible, e.g.
+    /// `Breakpoint at draw+0x4`
+    ///
+    /// # Arguments
+    ///
+    /// * `symbols` - The names of addresses
+    pub fn describe(&self, symbols: &Symbols) -> String {
+        let at = |address: &usize| symbols.format(*address);
+
+        match self {
+            Break::Address(address) => format!("Breakpoint at {}", at(address)),
+            Break::Opcode { address, pattern } => {
+                format!("Breakpoint on {} at {}", pattern, at(address))
+            }
+            Break::Memory {
+                address,
+                access,
+                pc,
+            } => {
+                let access = match access {
+                    Access::Read => "read",
+                    Access::Write => "written",
+                };
+                format!("{} {} by {}", at(address), access, at(pc))
+            }
+            Break::Register {
+                register,
+                old,
+                new,
+                pc,
+            } => format!(
+                "{} changed from {:#04x} to {:#04x} by {}",
+                register,
+                old,
+                new,
+                at(pc)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Break {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.describe(&Symbols::new()))
+    }
+}
+
+/// Identifies a breakpoint or watchpoint to attach a `Rule` to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    Address(usize),
+    Opcode(OpcodePattern),
+    /// The watchpoints starting at an address
+    Watch(usize),
+    Register(Register),
+}
+
+/// A condition and log message attached to a breakpoint or watchpoint. The breakpoint only
+/// stops when the condition is true, and a log-point, one with a message, logs the message
+/// instead of stopping.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rule {
+    pub condition: Option<Expr>,
+    pub message: Option<Message>,
+    hits: Cell<u64>,
+}
+
+impl Rule {
+    pub fn new(condition: Option<Expr>, message: Option<Message>) -> Self {
+        Self {
+            condition,
+            message,
+            hits: Cell::new(0),
+        }
+    }
+
+    /// Returns how many times the breakpoint was reached, whether or not the condition was true
+    pub fn get_hits(&self) -> u64 {
+        self.hits.get()
+    }
+}
+
+/// The breakpoints and watchpoints of a system, checked by `System::tick`.
+///
+/// Breakpoints on addresses and opcodes stop before the instruction runs, so continuing runs it.
+/// Watchpoints stop after the instruction that touched the memory or register. Either can have
+/// a `Rule`, evaluated against the system each time it is reached.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Breakpoints {
+    addresses: BTreeSet<usize>,
+    opcodes: Vec<OpcodePattern>,
+    watches: Vec<Watch>,
+    registers: Vec<Register>,
+    rules: Vec<(Trigger, Rule)>,
+}
+
+impl Breakpoints {
+    /// Returns whether nothing is set, which lets `tick` skip every check
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+            && self.opcodes.is_empty()
+            && self.watches.is_empty()
+            && self.registers.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn add_address(&mut self, address: usize) {
+        self.addresses.insert(address);
+    }
+
+    /// Removes a breakpoint, returning whether it was set
+    pub fn remove_address(&mut self, address: usize) -> bool {
+        self.remove_rule(Trigger::Address(address));
+        self.addresses.remove(&address)
+    }
+
+    pub fn get_addresses(&self) -> &BTreeSet<usize> {
+        &self.addresses
+    }
+
+    pub fn add_opcode(&mut self, pattern: OpcodePattern) {
+        if !self.opcodes.contains(&pattern) {
+            self.opcodes.push(pattern);
+        }
+    }
+
+    /// Removes an opcode class breakpoint, returning whether it was set
+    pub fn remove_opcode(&mut self, pattern: OpcodePattern) -> bool {
+        let len = self.opcodes.len();
+        self.remove_rule(Trigger::Opcode(pattern));
+        self.opcodes.retain(|opcode| *opcode != pattern);
+
+        self.opcodes.len() != len
+    }
+
+    pub fn get_opcodes(&self) -> &[OpcodePattern] {
+        &self.opcodes
+    }
+
+    pub fn add_watch(&mut self, watch: Watch) {
+        self.watches.push(watch);
+    }
+
+    /// Removes the watchpoints starting at an address, returning whether there were any
+    pub fn remove_watch(&mut self, start: usize) -> bool {
+        let len = self.watches.len();
+        self.remove_rule(Trigger::Watch(start));
+        self.watches.retain(|watch| watch.start != start);
+
+        self.watches.len() != len
+    }
+
+    /// Removes one watchpoint, keeping any others starting at the same address, returning
+    /// whether it was set
+    pub fn remove_watch_exact(&mut self, watch: Watch) -> bool {
+        let idx = match self.watches.iter().position(|watched| *watched == watch) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        self.watches.remove(idx);
+
+        if self
+            .watches
+            .iter()
+            .all(|watched| watched.start != watch.start)
+        {
+            self.remove_rule(Trigger::Watch(watch.start));
+        }
+
+        true
+    }
+
+    pub fn get_watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    pub fn add_register(&mut self, register: Register) {
+        if !self.registers.contains(&register) {
+            self.registers.push(register);
+        }
+    }
+
+    /// Stops watching a register, returning whether it was watched
+    pub fn remove_register(&mut self, register: Register) -> bool {
+        let len = self.registers.len();
+        self.remove_rule(Trigger::Register(register));
+        self.registers.retain(|watched| *watched != register);
+
+        self.registers.len() != len
+    }
+
+    pub fn get_registers(&self) -> &[Register] {
+        &self.registers
+    }
+
+    /// Attaches a rule to a breakpoint or watchpoint, replacing any previous one
+    ///
+    /// # Arguments
+    ///
+    /// * `trigger` - The breakpoint or watchpoint
+    /// * `rule` - The condition and log message
+    pub fn set_rule(&mut self, trigger: Trigger, rule: Rule) {
+        self.remove_rule(trigger);
+        self.rules.push((trigger, rule));
+    }
+
+    pub fn get_rule(&self, trigger: Trigger) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .find(|(attached, _)| *attached == trigger)
+            .map(|(_, rule)| rule)
+    }
+
+    /// Removes the rule of a breakpoint or watchpoint, returning whether it had one
+    pub fn remove_rule(&mut self, trigger: Trigger) -> bool {
+        let len = self.rules.len();
+        self.rules.retain(|(attached, _)| *attached != trigger);
+
+        self.rules.len() != len
+    }
+
+    /// Counts a breakpoint as reached and evaluates its rule, returning whether to stop
+    ///
+    /// # Arguments
+    ///
+    /// * `trigger` - The breakpoint or watchpoint reached
+    /// * `system` - The system to evaluate the rule against
+    /// * `logs` - Where to add the message of a log-point
+    fn fire(&self, trigger: Trigger, system: &System, logs: &mut Vec<String>) -> bool {
+        let rule = match self.get_rule(trigger) {
+            Some(rule) => rule,
+            None => return true,
+        };
+        let hits = rule.hits.get() + 1;
+        rule.hits.set(hits);
+
+        if let Some(condition) = rule.condition.as_ref() {
+            if !condition.is_true(system, hits) {
+                return false;
+            }
+        }
+
+        match rule.message.as_ref() {
+            Some(message) => {
+                logs.push(message.format(system, hits));
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Returns the break for the instruction about to run, if any
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The system, with `pc` at the instruction
+    /// * `logs` - Where to add the messages of log-points
+    pub(crate) fn check_next(&self, system: &System, logs: &mut Vec<String>) -> Option<Break> {
+        let address = system.get_pc();
+        let op = system.get_opcode(address);
+        let mut hit = None;
+
+        if self.addresses.contains(&address) && self.fire(Trigger::Address(address), system, logs) {
+            hit = Some(Break::Address(address));
+        }
+
+        for pattern in self.opcodes.iter().filter(|pattern| pattern.matches(op)) {
+            if self.fire(Trigger::Opcode(*pattern), system, logs) && hit.is_none() {
+                hit = Some(Break::Opcode {
+                    address,
+                    pattern: *pattern,
+                });
+            }
+        }
+
+        hit
+    }
+
+    /// Returns the break for an instruction's memory access, if a watchpoint covers it
+    ///
+    /// # Arguments
+    ///
+    /// * `access` - Whether the memory was read or written
+    /// * `start` - The first address accessed
+    /// * `len` - The number of bytes accessed
+    /// * `pc` - The address of the instruction
+    /// * `system` - The system, after the instruction ran
+    /// * `logs` - Where to add the messages of log-points
+    pub(crate) fn check_access(
+        &self,
+        (access, start, len): (Access, usize, usize),
+        pc: usize,
+        system: &System,
+        logs: &mut Vec<String>,
+    ) -> Option<Break> {
+        let mut hit = None;
+
+        for watch in self
+            .watches
+            .iter()
+            .filter(|watch| watch.kind.matches(access))
+        {
+            if let Some(address) = watch.overlaps(start, len) {
+                if self.fire(Trigger::Watch(watch.start), system, logs) && hit.is_none() {
+                    hit = Some(Break::Memory {
+                        address,
+                        access,
+                        pc,
+                    });
+                }
+            }
+        }
+
+        hit
+    }
+
+    /// Returns the break for an instruction's register changes, if a watched register changed
+    ///
+    /// # Arguments
+    ///
+    /// * `before` - The V registers and I before the instruction ran
+    /// * `pc` - The address of the instruction
+    /// * `system` - The system, after the instruction ran
+    /// * `logs` - Where to add the messages of log-points
+    pub(crate) fn check_registers(
+        &self,
+        before: ([u8; 16], u16),
+        pc: usize,
+        system: &System,
+        logs: &mut Vec<String>,
+    ) -> Option<Break> {
+        let mut hit = None;
+
+        for register in self.registers.iter() {
+            let (old, new) = match *register {
+                Register::V(idx) => (
+                    before.0[idx as usize] as u16,
+                    system.get_v()[idx as usize] as u16,
+                ),
+                Register::I => (before.1, system.get_i()),
+            };
+
+            if old != new && self.fire(Trigger::Register(*register), system, logs) && hit.is_none()
+            {
+                hit = Some(Break::Register {
+                    register: *register,
+                    old,
+                    new,
+                    pc,
+                });
+            }
+        }
+
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opcode_pattern() {
+        let draw = OpcodePattern::parse("DXYN").unwrap();
+        assert!(draw.matches(0xD015));
+        assert!(!draw.matches(0xA015));
+        assert_eq!(draw.to_string(), "DXYN");
+
+        let bcd = OpcodePattern::parse("fx33").unwrap();
+        assert!(bcd.matches(0xF533));
+        assert!(!bcd.matches(0xF555));
+        assert_eq!(bcd.to_string(), "FX33");
+
+        assert_eq!(OpcodePattern::parse("DXY"), None);
+        assert_eq!(OpcodePattern::parse("DXYZ"), None);
+    }
+
+    #[test]
+    fn test_check_access() {
+        let system = System::new();
+        let mut logs = vec![];
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.add_watch(Watch {
+            start: 0x300,
+            len: 4,
+            kind: WatchKind::Write,
+        });
+
+        let mut check = |access| breakpoints.check_access(access, 0x204, &system, &mut logs);
+        assert_eq!(
+            check((Access::Write, 0x2FE, 3)),
+            Some(Break::Memory {
+                address: 0x300,
+                access: Access::Write,
+                pc: 0x204
+            })
+        );
+        assert_eq!(check((Access::Read, 0x300, 1)), None);
+        assert_eq!(check((Access::Write, 0x304, 4)), None);
+        assert_eq!(check((Access::Write, 0x300, 0)), None);
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut system = System::new();
+        let mut logs = vec![];
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.add_address(0x200);
+        breakpoints.set_rule(
+            Trigger::Address(0x200),
+            Rule::new(Some(Expr::parse("v0 == 2 || hits > 3").unwrap()), None),
+        );
+
+        assert_eq!(breakpoints.check_next(&system, &mut logs), None);
+        system.set_v(0, 2);
+        assert_eq!(
+            breakpoints.check_next(&system, &mut logs),
+            Some(Break::Address(0x200))
+        );
+        system.set_v(0, 0);
+        assert_eq!(breakpoints.check_next(&system, &mut logs), None);
+        assert_eq!(
+            breakpoints.check_next(&system, &mut logs),
+            Some(Break::Address(0x200))
+        );
+        assert_eq!(
+            breakpoints
+                .get_rule(Trigger::Address(0x200))
+                .unwrap()
+                .get_hits(),
+            4
+        );
+
+        // A log-point logs instead of stopping
+        breakpoints.set_rule(
+            Trigger::Address(0x200),
+            Rule::new(None, Some(Message::parse("hit {hits}").unwrap())),
+        );
+        assert_eq!(breakpoints.check_next(&system, &mut logs), None);
+        assert_eq!(logs, vec!["hit 1"]);
+
+        assert!(breakpoints.remove_address(0x200));
+        assert_eq!(breakpoints.get_rule(Trigger::Address(0x200)), None);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::keymap::Keymap;
+use crate::palette::Palette;
+use crate::record::Format;
+use crate::system::{Quirks, Variant};
+
+use std::path::PathBuf;
+
+/// The ROM loaded when none is given on the command line
+pub const DEFAULT_ROM: &str = "./roms/breakout.ch8";
+
+/// Options understood by every frontend, printed after each frontend's usage line
+pub const OPTIONS_HELP: &str = "\
+Options:
+    --keymap <KEYS>            The 16 keys bound to CHIP-8 keys 0-F, e.g. x123qweasdzc4rfv
+    --palette <BG:FG>          Background and foreground colors, e.g. 000000:FFFFFF
+    --scale <N>                Integer scale of the window, screenshots and recordings
+    --variant <NAME>           Run the instructions that differ between interpreters like chip8,
+                               the COSMAC VIP's, or schip, SUPER-CHIP's
+    --quirks <LIST>            The quirks to enable instead, separated by commas: shift-vy,
+                               keep-i, vf-reset and jump-vx, or none
+    --record <FILE>            Record every frame to FILE, or to stdout when FILE is -
+    --record-format <FORMAT>   Recording format: gif, y4m or ppm (default: from FILE)
+    -h, --help                 Print this message";
+
+/// Builds the help message for a frontend
+///
+/// # Arguments
+///
+/// * `program` - The name of the frontend's executable
+pub fn usage(program: &str) -> String {
+    format!("Usage: {} [OPTIONS] [ROM]\n\n{}", program, OPTIONS_HELP)
+}
+
+/// Removes a frontend-specific `<name> <value>` pair from the arguments before they are parsed
+/// as `Options`, returning the value if the option was given
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments, excluding the program name
+/// * `name` - The option to remove, e.g. `--renderer`
+pub fn take_value(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    let idx = match args.iter().position(|arg| arg == name) {
+        Some(idx) => idx,
+        None => return Ok(None),
+    };
+
+    if idx + 1 >= args.len() {
+        return Err(format!("missing value for {}", name));
+    }
+
+    let value = args.remove(idx + 1);
+    args.remove(idx);
+
+    Ok(Some(value))
+}
+
+/// Removes a frontend-specific flag from the arguments before they are parsed as `Options`,
+/// returning whether it was given
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments, excluding the program name
+/// * `name` - The flag to remove, e.g. `--fullscreen`
+pub fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let before = args.len();
+    args.retain(|arg| arg != name);
+
+    args.len() != before
+}
+
+/// Options shared by every frontend
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub rom: PathBuf,
+    pub keymap: Keymap,
+    pub palette: Palette,
+    pub scale: usize,
+    pub quirks: Quirks,
+    pub record: Option<PathBuf>,
+    pub record_format: Format,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            rom: PathBuf::from(DEFAULT_ROM),
+            keymap: Keymap::default(),
+            palette: Palette::default(),
+            scale: 8,
+            quirks: Quirks::default(),
+            record: None,
+            record_format: Format::Gif,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    /// Parses command line arguments, excluding the program name
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The arguments to parse
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut record_format = None;
+        let mut variant = None;
+        let mut quirks = None;
+        let mut rom = None;
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", name))
+            };
+
+            match arg.as_str() {
+                "-h" | "--help" => options.help = true,
+                "--keymap" => {
+                    let keymap = value(&arg)?;
+                    options.keymap = Keymap::parse(&keymap)
+                        .ok_or_else(|| format!("invalid keymap '{}'", keymap))?;
+                }
+                "--palette" => {
+                    let palette = value(&arg)?;
+                    options.palette = Palette::parse(&palette)
+                        .ok_or_else(|| format!("invalid palette '{}'", palette))?;
+                }
+                "--scale" => {
+                    let scale = value(&arg)?;
+                    options.scale = match scale.parse() {
+                        Ok(scale) if scale > 0 => scale,
+                        _ => return Err(format!("invalid scale '{}'", scale)),
+                    };
+                }
+                "--variant" => {
+                    let name = value(&arg)?;
+                    variant = Some(
+                        Variant::parse(&name)
+                            .ok_or_else(|| format!("unknown variant '{}'", name))?,
+                    );
+                }
+                "--quirks" => {
+                    let list = value(&arg)?;
+                    quirks = Some(
+                        Quirks::parse(&list).ok_or_else(|| format!("invalid quirks '{}'", list))?,
+                    );
+                }
+                "--record" => options.record = Some(PathBuf::from(value(&arg)?)),
+                "--record-format" => {
+                    let format = value(&arg)?;
+                    record_format = Some(
+                        Format::parse(&format)
+                            .ok_or_else(|| format!("unknown recording format '{}'", format))?,
+                    );
+                }
+                _ if arg.starts_with('-') && arg != "-" => {
+                    return Err(format!("unknown option '{}'", arg));
+                }
+                _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                _ => return Err(format!("unexpected argument '{}'", arg)),
+            }
+        }
+
+        if let Some(rom) = rom {
+            options.rom = rom;
+        }
+
+        options.quirks = quirks
+            .or_else(|| variant.map(|variant| variant.get_quirks()))
+            .unwrap_or_default();
+
+        options.record_format = record_format
+            .or_else(|| options.record.as_deref().and_then(Format::from_path))
+            .unwrap_or(Format::Gif);
+
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        assert_eq!(parse(&[]).unwrap(), Options::default());
+    }
+
+    #[test]
+    fn test_record_format_from_path() {
+        let options = parse(&["game.ch8", "--record", "out.y4m"]).unwrap();
+
+        assert_eq!(options.rom, PathBuf::from("game.ch8"));
+        assert_eq!(options.record, Some(PathBuf::from("out.y4m")));
+        assert_eq!(options.record_format, Format::Y4m);
+    }
+
+    #[test]
+    fn test_quirks() {
+        let options = parse(&["--variant", "schip"]).unwrap();
+        assert_eq!(options.quirks, Variant::Schip.get_quirks());
+
+        // A list of quirks replaces the variant's
+        let options = parse(&["--quirks", "keep-i", "--variant", "chip8"]).unwrap();
+        assert!(options.quirks.keep_i && !options.quirks.shift_vy);
+    }
+
+    #[test]
+    fn test_take_value() {
+        let mut args = vec![
+            String::from("game.ch8"),
+            String::from("--renderer"),
+            String::from("software"),
+        ];
+
+        assert_eq!(
+            take_value(&mut args, "--renderer"),
+            Ok(Some(String::from("software")))
+        );
+        assert_eq!(args, vec![String::from("game.ch8")]);
+        assert_eq!(take_value(&mut args, "--renderer"), Ok(None));
+    }
+
+    #[test]
+    fn test_take_flag() {
+        let mut args = vec![String::from("--fullscreen"), String::from("game.ch8")];
+
+        assert!(take_flag(&mut args, "--fullscreen"));
+        assert_eq!(args, vec![String::from("game.ch8")]);
+        assert!(!take_flag(&mut args, "--fullscreen"));
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        assert!(parse(&["--scale", "0"]).is_err());
+        assert!(parse(&["--palette", "red:blue"]).is_err());
+        assert!(parse(&["--keymap", "0123"]).is_err());
+        assert!(parse(&["--variant", "xo-chip"]).is_err());
+        assert!(parse(&["--record"]).is_err());
+        assert!(parse(&["a.ch8", "b.ch8"]).is_err());
+    }
+}
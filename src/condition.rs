@@ -0,0 +1,447 @@
+use crate::system::System;
+
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A value an expression can read from the system
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Variable {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+    /// How many times the breakpoint has been reached, including this time
+    Hits,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Unary {
+    Not,
+    Negate,
+    Complement,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Binary {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Binary {
+    /// Returns the operator's precedence, higher binding tighter
+    fn precedence(&self) -> u8 {
+        match self {
+            Binary::Or => 1,
+            Binary::And => 2,
+            Binary::BitOr => 3,
+            Binary::BitXor => 4,
+            Binary::BitAnd => 5,
+            Binary::Eq | Binary::Ne => 6,
+            Binary::Lt | Binary::Le | Binary::Gt | Binary::Ge => 7,
+            Binary::Add | Binary::Sub => 8,
+            Binary::Mul | Binary::Div | Binary::Rem => 9,
+        }
+    }
+
+    fn parse(token: &str) -> Option<Binary> {
+        Some(match token {
+            "||" => Binary::Or,
+            "&&" => Binary::And,
+            "|" => Binary::BitOr,
+            "^" => Binary::BitXor,
+            "&" => Binary::BitAnd,
+            "==" => Binary::Eq,
+            "!=" => Binary::Ne,
+            "<" => Binary::Lt,
+            "<=" => Binary::Le,
+            ">" => Binary::Gt,
+            ">=" => Binary::Ge,
+            "+" => Binary::Add,
+            "-" => Binary::Sub,
+            "*" => Binary::Mul,
+            "/" => Binary::Div,
+            "%" => Binary::Rem,
+            _ => return None,
+        })
+    }
+
+    fn apply(&self, lhs: i64, rhs: i64) -> i64 {
+        match self {
+            Binary::Or => (lhs != 0 || rhs != 0) as i64,
+            Binary::And => (lhs != 0 && rhs != 0) as i64,
+            Binary::BitOr => lhs | rhs,
+            Binary::BitXor => lhs ^ rhs,
+            Binary::BitAnd => lhs & rhs,
+            Binary::Eq => (lhs == rhs) as i64,
+            Binary::Ne => (lhs != rhs) as i64,
+            Binary::Lt => (lhs < rhs) as i64,
+            Binary::Le => (lhs <= rhs) as i64,
+            Binary::Gt => (lhs > rhs) as i64,
+            Binary::Ge => (lhs >= rhs) as i64,
+            Binary::Add => lhs.wrapping_add(rhs),
+            Binary::Sub => lhs.wrapping_sub(rhs),
+            Binary::Mul => lhs.wrapping_mul(rhs),
+            // Dividing by zero gives zero rather than stopping the emulator
+            Binary::Div => lhs.checked_div(rhs).unwrap_or(0),
+            Binary::Rem => lhs.checked_rem(rhs).unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(i64),
+    Variable(Variable),
+    Memory(Box<Node>),
+    Unary(Unary, Box<Node>),
+    Binary(Binary, Box<Node>, Box<Node>),
+}
+
+/// An expression over the state of a system, parsed once and evaluated whenever a breakpoint or
+/// watchpoint is reached, e.g. `v3 == 0x10 && i >= 0x300` or `mem[0x3F0] != 0`.
+///
+/// Values are `v0` to `vf`, `i`, `pc`, `sp`, `dt`, `st`, `hits` (how many times the breakpoint
+/// was reached, including this time), `mem[ADDR]` and numbers in decimal or `0x` hexadecimal.
+/// Operators are those of C, with comparisons and logic giving `1` or `0`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+impl Expr {
+    /// Parses an expression
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The expression, e.g. `dt == 0`
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, next: 0 };
+        let root = parser.expression(0)?;
+
+        match parser.tokens.get(parser.next) {
+            Some(token) => Err(format!("unexpected '{}'", token)),
+            None => Ok(Expr {
+                source: source.trim().to_string(),
+                root,
+            }),
+        }
+    }
+
+    /// Evaluates the expression
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The system to read values from
+    /// * `hits` - How many times the breakpoint was reached
+    pub fn eval(&self, system: &System, hits: u64) -> i64 {
+        eval(&self.root, system, hits)
+    }
+
+    /// Returns whether the expression is true, i.e. not zero
+    pub fn is_true(&self, system: &System, hits: u64) -> bool {
+        self.eval(system, hits) != 0
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn eval(node: &Node, system: &System, hits: u64) -> i64 {
+    match node {
+        Node::Number(value) => *value,
+        Node::Variable(variable) => match variable {
+            Variable::V(idx) => system.get_v()[*idx as usize] as i64,
+            Variable::I => system.get_i() as i64,
+            Variable::Pc => system.get_pc() as i64,
+            Variable::Sp => system.get_sp() as i64,
+            Variable::DelayTimer => system.get_delay_timer() as i64,
+            Variable::SoundTimer => system.get_sound_timer() as i64,
+            Variable::Hits => hits as i64,
+        },
+        Node::Memory(address) => {
+            let address = eval(address, system, hits);
+            usize::try_from(address)
+                .ok()
+                .and_then(|address| system.get_memory().get(address))
+                .map_or(0, |value| *value as i64)
+        }
+        Node::Unary(op, operand) => {
+            let value = eval(operand, system, hits);
+            match op {
+                Unary::Not => (value == 0) as i64,
+                Unary::Negate => value.wrapping_neg(),
+                Unary::Complement => !value,
+            }
+        }
+        Node::Binary(op, lhs, rhs) => op.apply(eval(lhs, system, hits), eval(rhs, system, hits)),
+    }
+}
+
+/// Splits an expression into numbers, names and operators
+fn tokenize(source: &str) -> Result<Vec<String>, String> {
+    const OPERATORS: [&str; 22] = [
+        "||", "&&", "==", "!=", "<=", ">=", "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "!",
+        "~", "(", ")", "[", "]",
+    ];
+
+    let mut tokens = vec![];
+    let mut rest = source.trim_start();
+
+    while !rest.is_empty() {
+        let len = if rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+            rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len())
+        } else {
+            OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .map(|op| op.len())
+                .ok_or_else(|| format!("unexpected '{}'", rest.chars().next().unwrap()))?
+        };
+
+        tokens.push(rest[..len].to_string());
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.next).map(|token| token.as_str())
+    }
+
+    fn take(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .get(self.next)
+            .cloned()
+            .ok_or_else(|| String::from("unexpected end of expression"))?;
+        self.next += 1;
+
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.take()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected '{}', found '{}'", expected, token)),
+        }
+    }
+
+    /// Parses operators binding tighter than `min_precedence`, left to right
+    fn expression(&mut self, min_precedence: u8) -> Result<Node, String> {
+        let mut lhs = self.unary()?;
+
+        while let Some(op) = self.peek().and_then(Binary::parse) {
+            if op.precedence() <= min_precedence {
+                break;
+            }
+
+            self.next += 1;
+            let rhs = self.expression(op.precedence())?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        let op = match self.peek() {
+            Some("!") => Unary::Not,
+            Some("-") => Unary::Negate,
+            Some("~") => Unary::Complement,
+            _ => return self.primary(),
+        };
+
+        self.next += 1;
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self.take()?;
+
+        if token == "(" {
+            let node = self.expression(0)?;
+            self.expect(")")?;
+            return Ok(node);
+        }
+        if token.starts_with(|c: char| c.is_ascii_digit()) {
+            let value = match token.strip_prefix("0x") {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => token.parse(),
+            };
+            return value
+                .map(Node::Number)
+                .map_err(|_| format!("invalid number '{}'", token));
+        }
+
+        let variable = match token.to_ascii_lowercase().as_str() {
+            "mem" => {
+                self.expect("[")?;
+                let address = self.expression(0)?;
+                self.expect("]")?;
+                return Ok(Node::Memory(Box::new(address)));
+            }
+            "i" => Variable::I,
+            "pc" => Variable::Pc,
+            "sp" => Variable::Sp,
+            "dt" => Variable::DelayTimer,
+            "st" => Variable::SoundTimer,
+            "hits" => Variable::Hits,
+            name => match name
+                .strip_prefix('v')
+                .map(|digit| u8::from_str_radix(digit, 16))
+            {
+                Some(Ok(idx)) if name.len() == 2 => Variable::V(idx),
+                _ => return Err(format!("unknown value '{}'", token)),
+            },
+        };
+
+        Ok(Node::Variable(variable))
+    }
+}
+
+/// A message logged by a log-point, with expressions in braces replaced by their values, e.g.
+/// `v3={v3} at {pc:x}`. Values are written in decimal, or in hexadecimal with `:x`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Value(Expr, bool),
+}
+
+impl Message {
+    /// Parses a message
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The message, with expressions in braces
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parts = vec![];
+        let mut rest = source;
+
+        while let Some(open) = rest.find('{') {
+            let close = rest[open..]
+                .find('}')
+                .map(|close| open + close)
+                .ok_or_else(|| String::from("unclosed '{' in message"))?;
+            let (expr, hex) = match rest[open + 1..close].strip_suffix(":x") {
+                Some(expr) => (expr, true),
+                None => (&rest[open + 1..close], false),
+            };
+
+            if open > 0 {
+                parts.push(Part::Text(rest[..open].to_string()));
+            }
+            parts.push(Part::Value(Expr::parse(expr)?, hex));
+            rest = &rest[close + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        Ok(Message {
+            source: source.to_string(),
+            parts,
+        })
+    }
+
+    /// Returns the message with the current values filled in
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The system to read values from
+    /// * `hits` - How many times the log-point was reached
+    pub fn format(&self, system: &System, hits: u64) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Value(expr, true) => format!("{:#x}", expr.eval(system, hits)),
+                Part::Value(expr, false) => expr.eval(system, hits).to_string(),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval() {
+        let mut system = System::new();
+        system.set_v(3, 0x10);
+        system.set_i(0x300);
+        system.get_memory_mut()[0x3F0] = 7;
+
+        let check = |source: &str, hits: u64| Expr::parse(source).unwrap().eval(&system, hits);
+
+        assert_eq!(check("v3 == 0x10 && i >= 0x300", 0), 1);
+        assert_eq!(check("v3 == 0x10 && i > 0x300", 0), 0);
+        assert_eq!(check("mem[0x3F0] != 0", 0), 1);
+        assert_eq!(check("mem[i + 0xF0] * 2", 0), 14);
+        assert_eq!(check("dt == 0 || pc == 1", 0), 1);
+        assert_eq!(check("hits > 100", 101), 1);
+        assert_eq!(check("1 + 2 * 3 == 7 && !(2 < 1)", 0), 1);
+        assert_eq!(check("-VF + ~0 / 0", 0), 0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Expr::parse("v3 ==").is_err());
+        assert!(Expr::parse("vg == 1").is_err());
+        assert!(Expr::parse("mem[1").is_err());
+        assert!(Expr::parse("1 2").is_err());
+        assert!(Expr::parse("v1 = 2").is_err());
+    }
+
+    #[test]
+    fn test_message() {
+        let mut system = System::new();
+        system.set_v(3, 42);
+
+        let message = Message::parse("v3={v3} pc={pc:x} #{hits}").unwrap();
+        assert_eq!(message.format(&system, 2), "v3=42 pc=0x200 #2");
+        assert!(Message::parse("v3={v3").is_err());
+    }
+}
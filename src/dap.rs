@@ -0,0 +1,955 @@
+use crate::asm::{assemble, Assembly, Target};
+use crate::breakpoints::{Break, Rule, Trigger};
+use crate::condition::{Expr, Message};
+use crate::debugger::set_register;
+use crate::disasm::Instruction;
+use crate::headless::parse_number;
+use crate::json::Json;
+use crate::symbols::Symbols;
+use crate::system::System;
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// How many instructions `run_slice` runs before the caller checks for new requests
+pub const SLICE: usize = 4096;
+
+/// The only thread, as DAP requires at least one
+const THREAD_ID: usize = 1;
+
+/// The `variablesReference` of each scope
+const REGISTERS: u64 = 1;
+const TIMERS: u64 = 2;
+const MEMORY: u64 = 3;
+
+/// How many bytes each variable of the memory scope shows
+const MEMORY_ROW: usize = 16;
+
+/// Where a run started by `continue`, `next` or `stepOut` stops, besides breakpoints
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Goal {
+    Continue,
+    /// Return from the call being stepped over, to `pc` with the stack back at `sp`
+    Return {
+        sp: usize,
+        pc: usize,
+    },
+    /// Return from the current subroutine
+    Finish {
+        sp: usize,
+    },
+}
+
+/// A breakpoint requested by the client, at the address it resolved to
+type Requested = (usize, Option<Rule>);
+
+/// A Debug Adapter Protocol adapter driving a system, independent of how messages are carried,
+/// see `read_message` and `write_message`.
+///
+/// `launch` takes the `program` to run, either a ROM or Octo source that is assembled first, in
+/// which case breakpoints can be set on source lines and labels. ROMs can be given labels by a
+/// symbol map in `symbols`, see `Symbols::load`. Frames of the stack trace are the
+/// program counter followed by the call sites of the return addresses on the stack.
+#[derive(Default)]
+pub struct Adapter {
+    seq: u64,
+    system: Option<System>,
+    assembly: Option<Assembly>,
+    source: Option<PathBuf>,
+    stop_on_entry: bool,
+    /// Where the running program stops, `None` while it is stopped
+    goal: Option<Goal>,
+    source_breakpoints: Vec<Requested>,
+    instruction_breakpoints: Vec<Requested>,
+    function_breakpoints: Vec<Requested>,
+    /// The addresses last set on the system, to be removed when breakpoints change
+    installed: Vec<usize>,
+    done: bool,
+}
+
+impl Adapter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns whether the program is running, in which case the caller should keep calling
+    /// `run_slice` between requests
+    pub fn is_running(&self) -> bool {
+        self.goal.is_some()
+    }
+
+    /// Returns whether the client disconnected
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Handles a request and returns the messages to send: its response, then any events
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request, e.g. `{"seq":1,"type":"request","command":"initialize"}`
+    pub fn handle(&mut self, request: &Json) -> Vec<Json> {
+        let command = request.get("command").and_then(Json::as_str).unwrap_or("");
+        let empty = Json::Object(vec![]);
+        let arguments = request.get("arguments").unwrap_or(&empty);
+        let mut events = vec![];
+
+        let result = self.dispatch(command, arguments, &mut events);
+        let mut fields = vec![
+            ("type", Json::from("response")),
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("success", Json::from(result.is_ok())),
+            ("command", Json::from(command)),
+        ];
+        match result {
+            Ok(Some(body)) => fields.push(("body", body)),
+            Ok(None) => {}
+            Err(message) => fields.push(("message", Json::from(message))),
+        }
+
+        let mut messages = vec![self.message(fields)];
+        messages.extend(
+            events
+                .into_iter()
+                .map(|(event, body)| self.event(event, body)),
+        );
+
+        messages
+    }
+
+    /// Runs the program for up to `SLICE` instructions, returning the events to send when it
+    /// stopped or logged a message
+    pub fn run_slice(&mut self) -> Vec<Json> {
+        let (goal, system) = match (self.goal, self.system.as_mut()) {
+            (Some(goal), Some(system)) => (goal, system),
+            _ => return vec![],
+        };
+        let mut stop = None;
+
+        for _ in 0..SLICE {
+            if system.is_halted() {
+                stop = Some(("pause", Some(String::from("Program halted"))));
+                break;
+            }
+
+            if let Some(hit) = system.tick() {
+                let text = hit.describe(system.get_symbols());
+                stop = Some((stop_reason(&hit), Some(text)));
+                break;
+            }
+
+            let reached = match goal {
+                Goal::Continue => false,
+                Goal::Return { sp, pc } => system.get_sp() == sp && system.get_pc() == pc,
+                Goal::Finish { sp } => system.get_sp() < sp,
+            };
+            if reached {
+                stop = Some(("step", None));
+                break;
+            }
+        }
+
+        let mut events = output_events(system);
+        if let Some((reason, description)) = stop {
+            self.goal = None;
+            events.push(("stopped", stopped(reason, description)));
+        }
+
+        events
+            .into_iter()
+            .map(|(event, body)| self.event(event, body))
+            .collect()
+    }
+
+    fn dispatch(
+        &mut self,
+        command: &str,
+        arguments: &Json,
+        events: &mut Vec<(&'static str, Json)>,
+    ) -> Result<Option<Json>, String> {
+        match command {
+            "initialize" => return Ok(Some(capabilities())),
+            "launch" => {
+                self.launch(arguments)?;
+                events.push(("initialized", Json::Object(vec![])));
+                return Ok(None);
+            }
+            "disconnect" => {
+                self.done = true;
+                return Ok(None);
+            }
+            "terminate" => {
+                self.done = true;
+                events.push(("terminated", Json::Object(vec![])));
+                return Ok(None);
+            }
+            "threads" => {
+                let thread = Json::object(vec![
+                    ("id", Json::from(THREAD_ID)),
+                    ("name", Json::from("CHIP-8")),
+                ]);
+                return Ok(Some(Json::object(vec![(
+                    "threads",
+                    Json::from(vec![thread]),
+                )])));
+            }
+            "setExceptionBreakpoints" => return Ok(None),
+            _ => {}
+        }
+
+        if self.system.is_none() {
+            return Err(String::from("No program was launched"));
+        }
+
+        match command {
+            "setBreakpoints" => self.set_source_breakpoints(arguments).map(Some),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments).map(Some),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(arguments).map(Some),
+            "configurationDone" => {
+                let system = self.system.as_mut().unwrap();
+                let hit = system.check_breakpoints();
+                events.extend(output_events(system));
+
+                if let Some(hit) = hit {
+                    let text = hit.describe(system.get_symbols());
+                    events.push(("stopped", stopped(stop_reason(&hit), Some(text))));
+                } else if self.stop_on_entry {
+                    events.push(("stopped", stopped("entry", None)));
+                } else {
+                    self.goal = Some(Goal::Continue);
+                }
+                Ok(None)
+            }
+            "continue" => {
+                self.goal = Some(Goal::Continue);
+                Ok(Some(Json::object(vec![(
+                    "allThreadsContinued",
+                    Json::from(true),
+                )])))
+            }
+            "next" => {
+                let system = self.system.as_ref().unwrap();
+                match Instruction::decode(system.get_opcode(system.get_pc())) {
+                    Some(Instruction::Call(_)) => {
+                        self.goal = Some(Goal::Return {
+                            sp: system.get_sp(),
+                            pc: system.get_pc() + 2,
+                        })
+                    }
+                    _ => self.step(events),
+                }
+                Ok(None)
+            }
+            "stepIn" => {
+                self.step(events);
+                Ok(None)
+            }
+            "stepOut" => {
+                let sp = self.system.as_ref().unwrap().get_sp();
+                if sp == 0 {
+                    return Err(String::from("Not in a subroutine"));
+                }
+                self.goal = Some(Goal::Finish { sp });
+                Ok(None)
+            }
+            "pause" => {
+                if self.goal.take().is_some() {
+                    events.push(("stopped", stopped("pause", None)));
+                }
+                Ok(None)
+            }
+            "stackTrace" => Ok(Some(self.stack_trace())),
+            "scopes" => Ok(Some(scopes())),
+            "variables" => self.variables(arguments).map(Some),
+            "setVariable" => self.set_variable(arguments).map(Some),
+            "evaluate" => {
+                let expression = arguments
+                    .get("expression")
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                let value = Expr::parse(expression)?.eval(self.system.as_ref().unwrap(), 0);
+
+                Ok(Some(Json::object(vec![
+                    ("result", Json::from(format!("{} ({:#x})", value, value))),
+                    ("variablesReference", Json::from(0u64)),
+                ])))
+            }
+            _ => Err(format!("Unsupported request '{}'", command)),
+        }
+    }
+
+    /// Loads the program, assembling it first if it is Octo source
+    fn launch(&mut self, arguments: &Json) -> Result<(), String> {
+        let program = arguments
+            .get("program")
+            .and_then(Json::as_str)
+            .map(PathBuf::from)
+            .ok_or_else(|| String::from("The launch configuration has no 'program'"))?;
+        let error = |err: &dyn std::fmt::Display| format!("{}: {}", program.display(), err);
+
+        let mut system = if program
+            .extension()
+            .is_some_and(|extension| extension == "8o")
+        {
+            let source = fs::read_to_string(&program).map_err(|err| error(&err))?;
+            let assembly = assemble(&source, Target::Chip8).map_err(|err| error(&err))?;
+            let mut system = System::new();
+
+            system.write_rom(assembly.bytes.clone());
+            system.set_symbols(Symbols::from_assembly(&assembly));
+            self.assembly = Some(assembly);
+            self.source = Some(program.clone());
+            system
+        } else {
+            System::load_rom(&program).map_err(|err| error(&err))?
+        };
+
+        if let Some(path) = arguments.get("symbols").and_then(Json::as_str) {
+            system.set_symbols(Symbols::load(path)?);
+        }
+
+        self.system = Some(system);
+        self.stop_on_entry = arguments
+            .get("stopOnEntry")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+
+        Ok(())
+    }
+
+    /// Runs one instruction
+    fn step(&mut self, events: &mut Vec<(&'static str, Json)>) {
+        let system = self.system.as_mut().unwrap();
+        let hit = system.tick();
+
+        events.extend(output_events(system));
+        let text = hit.map(|hit| hit.describe(system.get_symbols()));
+        events.push(("stopped", stopped("step", text)));
+    }
+
+    fn set_source_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let path = arguments
+            .get("source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str)
+            .map(PathBuf::from);
+        let assembly = match (&self.assembly, &self.source, path) {
+            (Some(assembly), Some(source), Some(path)) if same_file(source, &path) => {
+                Some(assembly)
+            }
+            _ => None,
+        };
+        let mut requested = vec![];
+        let mut results = vec![];
+
+        for breakpoint in list(arguments, "breakpoints") {
+            let line = breakpoint.get("line").and_then(Json::as_u64).unwrap_or(0) as usize;
+            let address = assembly.and_then(|assembly| assembly.get_address(line));
+            let rule = parse_rule(breakpoint);
+
+            results.push(match (address, rule) {
+                (Some(address), Ok(rule)) => {
+                    requested.push((address, rule));
+                    verified(address, Some(line))
+                }
+                (None, _) if assembly.is_none() => {
+                    unverified("Only the launched Octo source has line information")
+                }
+                (None, _) => unverified("No code was assembled from this line"),
+                (_, Err(err)) => unverified(&err),
+            });
+        }
+
+        self.source_breakpoints = requested;
+        self.install_breakpoints();
+
+        Ok(Json::object(vec![("breakpoints", Json::from(results))]))
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let mut requested = vec![];
+        let mut results = vec![];
+
+        for breakpoint in list(arguments, "breakpoints") {
+            let offset = match breakpoint.get("offset") {
+                Some(Json::Number(offset)) => *offset as i64,
+                _ => 0,
+            };
+            let address = breakpoint
+                .get("instructionReference")
+                .and_then(Json::as_str)
+                .and_then(parse_number)
+                .map(|address| address as i64 + offset)
+                .filter(|address| (0..0xFFF).contains(address));
+
+            results.push(match (address, parse_rule(breakpoint)) {
+                (Some(address), Ok(rule)) => {
+                    requested.push((address as usize, rule));
+                    verified(address as usize, None)
+                }
+                (None, _) => unverified("Invalid instruction reference"),
+                (_, Err(err)) => unverified(&err),
+            });
+        }
+
+        self.instruction_breakpoints = requested;
+        self.install_breakpoints();
+
+        Ok(Json::object(vec![("breakpoints", Json::from(results))]))
+    }
+
+    fn set_function_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let mut requested = vec![];
+        let mut results = vec![];
+
+        for breakpoint in list(arguments, "breakpoints") {
+            let name = breakpoint.get("name").and_then(Json::as_str).unwrap_or("");
+            let address = self
+                .system
+                .as_ref()
+                .and_then(|system| system.get_symbols().get_address(name));
+
+            results.push(match (address, parse_rule(breakpoint)) {
+                (Some(address), Ok(rule)) => {
+                    requested.push((address, rule));
+                    verified(address, self.line_of(address))
+                }
+                (None, _) => unverified(&format!("No label named '{}'", name)),
+                (_, Err(err)) => unverified(&err),
+            });
+        }
+
+        self.function_breakpoints = requested;
+        self.install_breakpoints();
+
+        Ok(Json::object(vec![("breakpoints", Json::from(results))]))
+    }
+
+    /// Replaces the system's address breakpoints with every requested one
+    fn install_breakpoints(&mut self) {
+        let breakpoints = self.system.as_mut().unwrap().get_breakpoints_mut();
+
+        for address in self.installed.drain(..) {
+            breakpoints.remove_address(address);
+        }
+
+        for (address, rule) in self
+            .source_breakpoints
+            .iter()
+            .chain(self.instruction_breakpoints.iter())
+            .chain(self.function_breakpoints.iter())
+        {
+            breakpoints.add_address(*address);
+            if let Some(rule) = rule {
+                breakpoints.set_rule(Trigger::Address(*address), rule.clone());
+            }
+            self.installed.push(*address);
+        }
+    }
+
+    fn stack_trace(&self) -> Json {
+        let system = self.system.as_ref().unwrap();
+
+        // The stack grows from 1, so stack[0] never holds a return address
+        let addresses = std::iter::once(system.get_pc()).chain(
+            (1..=system.get_sp())
+                .rev()
+                .map(|idx| system.get_stack()[idx].saturating_sub(2)),
+        );
+        let frames: Vec<Json> = addresses
+            .enumerate()
+            .map(|(id, address)| {
+                let line = self.line_of(address);
+                let mut fields = vec![
+                    ("id", Json::from(id)),
+                    ("name", Json::from(self.frame_name(address))),
+                    ("line", Json::from(line.unwrap_or(0))),
+                    ("column", Json::from(line.map_or(0usize, |_| 1))),
+                    (
+                        "instructionPointerReference",
+                        Json::from(format!("{:#05x}", address)),
+                    ),
+                ];
+
+                if let (Some(_), Some(source)) = (line, self.source.as_ref()) {
+                    fields.push(("source", source_json(source)));
+                }
+
+                Json::object(fields)
+            })
+            .collect();
+
+        Json::object(vec![
+            ("totalFrames", Json::from(frames.len())),
+            ("stackFrames", Json::from(frames)),
+        ])
+    }
+
+    fn line_of(&self, address: usize) -> Option<usize> {
+        self.assembly
+            .as_ref()
+            .and_then(|assembly| assembly.get_line(address))
+    }
+
+    /// Names a frame after the label at or before its address, e.g. `draw+0x4`
+    fn frame_name(&self, address: usize) -> String {
+        self.system.as_ref().unwrap().get_symbols().format(address)
+    }
+
+    fn variables(&self, arguments: &Json) -> Result<Json, String> {
+        let system = self.system.as_ref().unwrap();
+        let mut variables: Vec<(String, String)> = vec![];
+
+        match arguments.get("variablesReference").and_then(Json::as_u64) {
+            Some(REGISTERS) => {
+                for (idx, value) in system.get_v().iter().enumerate() {
+                    variables.push((format!("V{:X}", idx), format!("{:#04x}", value)));
+                }
+                variables.push((String::from("I"), format!("{:#05x}", system.get_i())));
+                variables.push((String::from("PC"), format!("{:#05x}", system.get_pc())));
+                variables.push((String::from("SP"), system.get_sp().to_string()));
+            }
+            Some(TIMERS) => {
+                variables.push((String::from("DT"), system.get_delay_timer().to_string()));
+                variables.push((String::from("ST"), system.get_sound_timer().to_string()));
+            }
+            Some(MEMORY) => {
+                let rows = system.get_memory().chunks(MEMORY_ROW).enumerate();
+                let start = arguments.get("start").and_then(Json::as_u64).unwrap_or(0);
+                let count = match arguments.get("count").and_then(Json::as_u64) {
+                    Some(0) | None => usize::MAX,
+                    Some(count) => count as usize,
+                };
+
+                for (idx, row) in rows.skip(start as usize).take(count) {
+                    let bytes: Vec<String> = row.iter().map(|b| format!("{:02X}", b)).collect();
+                    variables.push((format!("{:#05x}", idx * MEMORY_ROW), bytes.join(" ")));
+                }
+            }
+            _ => return Err(String::from("Unknown variables reference")),
+        }
+
+        let variables: Vec<Json> = variables
+            .into_iter()
+            .map(|(name, value)| {
+                Json::object(vec![
+                    ("name", Json::from(name)),
+                    ("value", Json::from(value)),
+                    ("variablesReference", Json::from(0u64)),
+                ])
+            })
+            .collect();
+
+        Ok(Json::object(vec![("variables", Json::from(variables))]))
+    }
+
+    fn set_variable(&mut self, arguments: &Json) -> Result<Json, String> {
+        let name = arguments.get("name").and_then(Json::as_str).unwrap_or("");
+        let value = arguments.get("value").and_then(Json::as_str).unwrap_or("");
+
+        match arguments.get("variablesReference").and_then(Json::as_u64) {
+            Some(REGISTERS) | Some(TIMERS) => {
+                set_register(self.system.as_mut().unwrap(), name, value)?
+            }
+            _ => return Err(String::from("Only registers and timers can be set")),
+        }
+
+        Ok(Json::object(vec![("value", Json::from(value))]))
+    }
+
+    /// Builds a message of the given fields, numbering it
+    fn message(&mut self, fields: Vec<(&str, Json)>) -> Json {
+        self.seq += 1;
+
+        let mut message = vec![("seq", Json::from(self.seq))];
+        message.extend(fields);
+        Json::object(message)
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> Json {
+        self.message(vec![
+            ("type", Json::from("event")),
+            ("event", Json::from(event)),
+            ("body", body),
+        ])
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object(
+        [
+            "supportsConfigurationDoneRequest",
+            "supportsConditionalBreakpoints",
+            "supportsLogPoints",
+            "supportsFunctionBreakpoints",
+            "supportsInstructionBreakpoints",
+            "supportsSetVariable",
+            "supportsTerminateRequest",
+        ]
+        .iter()
+        .map(|capability| (*capability, Json::from(true)))
+        .collect(),
+    )
+}
+
+fn scopes() -> Json {
+    let scope = |name: &str, reference: u64, expensive: bool| {
+        Json::object(vec![
+            ("name", Json::from(name)),
+            ("variablesReference", Json::from(reference)),
+            ("expensive", Json::from(expensive)),
+        ])
+    };
+
+    Json::object(vec![(
+        "scopes",
+        Json::from(vec![
+            scope("Registers", REGISTERS, false),
+            scope("Timers", TIMERS, false),
+            scope("Memory", MEMORY, true),
+        ]),
+    )])
+}
+
+fn stopped(reason: &str, description: Option<String>) -> Json {
+    let mut fields = vec![
+        ("reason", Json::from(reason)),
+        ("threadId", Json::from(THREAD_ID)),
+        ("allThreadsStopped", Json::from(true)),
+    ];
+    if let Some(description) = description {
+        fields.push(("description", Json::from(description)));
+    }
+
+    Json::object(fields)
+}
+
+fn stop_reason(hit: &Break) -> &'static str {
+    match hit {
+        Break::Address(_) | Break::Opcode { .. } => "breakpoint",
+        Break::Memory { .. } | Break::Register { .. } => "data breakpoint",
+    }
+}
+
+/// Turns the messages of log-points into `output` events
+fn output_events(system: &mut System) -> Vec<(&'static str, Json)> {
+    system
+        .take_logs()
+        .into_iter()
+        .map(|log| {
+            let body = Json::object(vec![
+                ("category", Json::from("console")),
+                ("output", Json::from(log + "\n")),
+            ]);
+            ("output", body)
+        })
+        .collect()
+}
+
+fn list<'a>(arguments: &'a Json, key: &str) -> &'a [Json] {
+    arguments.get(key).and_then(Json::as_array).unwrap_or(&[])
+}
+
+/// Parses the `condition` and `logMessage` of a breakpoint
+fn parse_rule(breakpoint: &Json) -> Result<Option<Rule>, String> {
+    let condition = match breakpoint.get("condition").and_then(Json::as_str) {
+        Some(condition) if !condition.trim().is_empty() => Some(Expr::parse(condition)?),
+        _ => None,
+    };
+    let message = match breakpoint.get("logMessage").and_then(Json::as_str) {
+        Some(message) => Some(Message::parse(message)?),
+        None => None,
+    };
+
+    if condition.is_none() && message.is_none() {
+        Ok(None)
+    } else {
+        Ok(Some(Rule::new(condition, message)))
+    }
+}
+
+fn verified(address: usize, line: Option<usize>) -> Json {
+    let mut fields = vec![
+        ("verified", Json::from(true)),
+        (
+            "instructionReference",
+            Json::from(format!("{:#05x}", address)),
+        ),
+    ];
+    if let Some(line) = line {
+        fields.push(("line", Json::from(line)));
+    }
+
+    Json::object(fields)
+}
+
+fn unverified(message: &str) -> Json {
+    Json::object(vec![
+        ("verified", Json::from(false)),
+        ("message", Json::from(message)),
+    ])
+}
+
+fn source_json(path: &Path) -> Json {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    Json::object(vec![
+        ("name", Json::from(name)),
+        ("path", Json::from(path.to_string_lossy().into_owned())),
+    ])
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Reads a message framed by a `Content-Length` header, or `None` at the end of input
+///
+/// # Arguments
+///
+/// * `reader` - The client's output, e.g. stdin
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Json>> {
+    let mut len = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() && len.is_some() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            len = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0; len.unwrap_or(0)];
+    reader.read_exact(&mut body)?;
+
+    Json::parse(&String::from_utf8_lossy(&body))
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Writes a message with its `Content-Length` header
+///
+/// # Arguments
+///
+/// * `writer` - The client's input, e.g. stdout
+/// * `message` - The message
+pub fn write_message<W: Write>(writer: &mut W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn request(adapter: &mut Adapter, command: &str, arguments: &str) -> Vec<Json> {
+        let text = format!(
+            r#"{{"seq":1,"type":"request","command":"{}","arguments":{}}}"#,
+            command, arguments
+        );
+        adapter.handle(&Json::parse(&text).unwrap())
+    }
+
+    fn body<'a>(messages: &'a [Json], key: &str) -> &'a Json {
+        messages[0]
+            .get("body")
+            .and_then(|body| body.get(key))
+            .unwrap()
+    }
+
+    fn events(messages: &[Json]) -> Vec<String> {
+        messages
+            .iter()
+            .filter_map(|message| message.get("event").and_then(Json::as_str))
+            .map(String::from)
+            .collect()
+    }
+
+    /// Runs until the program stops, returning the stopped event
+    fn run(adapter: &mut Adapter) -> Json {
+        while adapter.is_running() {
+            let events = adapter.run_slice();
+            if let Some(event) = events
+                .into_iter()
+                .find(|event| event.get("event").and_then(Json::as_str) == Some("stopped"))
+            {
+                return event.get("body").unwrap().clone();
+            }
+        }
+        panic!("the program did not stop");
+    }
+
+    #[test]
+    fn test_session() {
+        let path = env::temp_dir().join(format!("chip8-dap-{}.8o", std::process::id()));
+        fs::write(
+            &path,
+            ": main\n  v0 := 1\n  draw\n  jump main\n: draw\n  v1 += 1\n  return\n",
+        )
+        .unwrap();
+        let mut adapter = Adapter::new();
+
+        let messages = request(&mut adapter, "initialize", "{}");
+        assert_eq!(body(&messages, "supportsLogPoints"), &Json::Bool(true));
+        assert!(request(&mut adapter, "threads", "{}")[0]
+            .get("body")
+            .is_some());
+        assert_eq!(
+            request(&mut adapter, "stackTrace", "{}")[0].get("success"),
+            Some(&Json::Bool(false))
+        );
+
+        let launch = format!(r#"{{"program":"{}","stopOnEntry":true}}"#, path.display());
+        assert_eq!(
+            events(&request(&mut adapter, "launch", &launch)),
+            ["initialized"]
+        );
+
+        let breakpoints = format!(
+            r#"{{"source":{{"path":"{}"}},"breakpoints":[{{"line":6,"logMessage":"v1={{v1}}"}},{{"line":7,"condition":"v1 == 2"}},{{"line":1}}]}}"#,
+            path.display()
+        );
+        let results = request(&mut adapter, "setBreakpoints", &breakpoints);
+        let results = body(&results, "breakpoints").as_array().unwrap();
+        assert_eq!(results[0].get("verified"), Some(&Json::Bool(true)));
+        assert_eq!(
+            results[1]
+                .get("instructionReference")
+                .and_then(Json::as_str),
+            Some("0x208")
+        );
+        assert_eq!(results[2].get("verified"), Some(&Json::Bool(false)));
+
+        let messages = request(&mut adapter, "configurationDone", "{}");
+        assert_eq!(events(&messages), ["stopped"]);
+
+        // The first call logs and runs on, the second stops at the return
+        request(&mut adapter, "continue", "{}");
+        let mut logs = vec![];
+        let stop = loop {
+            let events = adapter.run_slice();
+            for event in events.iter() {
+                if let Some(output) = event.get("body").and_then(|body| body.get("output")) {
+                    logs.push(output.as_str().unwrap().to_string());
+                }
+            }
+            if !adapter.is_running() {
+                break events.last().unwrap().get("body").unwrap().clone();
+            }
+        };
+        assert_eq!(
+            stop.get("reason").and_then(Json::as_str),
+            Some("breakpoint")
+        );
+        assert_eq!(logs, ["v1=0\n", "v1=1\n"]);
+
+        let messages = request(&mut adapter, "stackTrace", "{}");
+        let frames = body(&messages, "stackFrames").as_array().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            frames[0].get("name").and_then(Json::as_str),
+            Some("draw+0x2")
+        );
+        assert_eq!(frames[0].get("line").and_then(Json::as_u64), Some(7));
+        assert_eq!(
+            frames[1].get("name").and_then(Json::as_str),
+            Some("main+0x2")
+        );
+        assert_eq!(frames[1].get("line").and_then(Json::as_u64), Some(3));
+
+        let messages = request(&mut adapter, "variables", r#"{"variablesReference":1}"#);
+        let registers = body(&messages, "variables").as_array().unwrap();
+        assert_eq!(
+            registers[1].get("value").and_then(Json::as_str),
+            Some("0x02")
+        );
+        let messages = request(
+            &mut adapter,
+            "variables",
+            r#"{"variablesReference":3,"start":32,"count":1}"#,
+        );
+        let memory = body(&messages, "variables").as_array().unwrap();
+        assert_eq!(memory[0].get("name").and_then(Json::as_str), Some("0x200"));
+        assert!(memory[0]
+            .get("value")
+            .and_then(Json::as_str)
+            .unwrap()
+            .starts_with("60 01 22 06"));
+
+        request(
+            &mut adapter,
+            "setVariable",
+            r#"{"variablesReference":1,"name":"V3","value":"0x10"}"#,
+        );
+        let messages = request(&mut adapter, "evaluate", r#"{"expression":"v3 * 2"}"#);
+        assert_eq!(body(&messages, "result").as_str(), Some("32 (0x20)"));
+
+        request(&mut adapter, "stepOut", "{}");
+        assert_eq!(
+            run(&mut adapter).get("reason").and_then(Json::as_str),
+            Some("step")
+        );
+        assert_eq!(adapter.system.as_ref().unwrap().get_pc(), 0x204);
+
+        request(&mut adapter, "disconnect", "{}");
+        assert!(adapter.is_done());
+
+        // A breakpoint on the first instruction stops before it runs
+        let mut adapter = Adapter::new();
+        let launch = format!(r#"{{"program":"{}","stopOnEntry":false}}"#, path.display());
+        request(&mut adapter, "launch", &launch);
+        let breakpoints = format!(
+            r#"{{"source":{{"path":"{}"}},"breakpoints":[{{"line":2}}]}}"#,
+            path.display()
+        );
+        request(&mut adapter, "setBreakpoints", &breakpoints);
+        let messages = request(&mut adapter, "configurationDone", "{}");
+        assert_eq!(events(&messages), ["stopped"]);
+        assert_eq!(
+            messages[1]
+                .get("body")
+                .and_then(|body| body.get("reason"))
+                .and_then(Json::as_str),
+            Some("breakpoint")
+        );
+        assert!(!adapter.is_running());
+        let system = adapter.system.as_ref().unwrap();
+        assert_eq!((system.get_pc(), system.get_v()[0]), (0x200, 0));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_framing() {
+        let message = Json::object(vec![("seq", Json::from(1u64))]);
+        let mut buffer = vec![];
+        write_message(&mut buffer, &message).unwrap();
+        write_message(&mut buffer, &message).unwrap();
+        assert!(buffer.starts_with(b"Content-Length: 9\r\n\r\n{\"seq\":1}"));
+
+        let mut reader = &buffer[..];
+        assert_eq!(read_message(&mut reader).unwrap(), Some(message.clone()));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(message));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+}
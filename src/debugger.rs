@@ -0,0 +1,990 @@
+use crate::breakpoints::{
+    Break, Breakpoints, OpcodePattern, Register, Rule, Trigger, Watch, WatchKind,
+};
+use crate::condition::{Expr, Message};
+use crate::disasm::{disassemble_at, Instruction, Syntax};
+use crate::headless::parse_number;
+use crate::symbols::Symbols;
+use crate::system::{System, TICKS_PER_FRAME};
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+/// The most instructions `continue`, `next` and `finish` run before giving control back, one
+/// minute of emulated time, so a program that never reaches a breakpoint cannot hang the prompt
+const RUN_LIMIT: u64 = 60 * 60 * TICKS_PER_FRAME as u64;
+
+/// How many instructions apart snapshots are taken for `reverse-step` and `reverse-continue`,
+/// one second of emulated time. Going back replays at most this many instructions per snapshot.
+const SNAPSHOT_INTERVAL: u64 = 60 * TICKS_PER_FRAME as u64;
+
+/// The most snapshots kept, ten minutes of emulated time, after which the oldest are dropped
+const MAX_SNAPSHOTS: usize = 600;
+
+/// How many instructions `disasm` lists
+const DISASM_LINES: usize = 10;
+
+/// How many bytes `mem` shows when no length is given
+const MEM_LENGTH: usize = 64;
+
+pub const DEBUGGER_HELP: &str = "\
+Commands:
+    step [N]                   Run N instructions (default: 1)
+    next                       Run one instruction, running calls until they return
+    finish                     Run until the current subroutine returns
+    continue                   Run until a breakpoint or the program halts
+    frame                      Run until the start of the next frame
+    reverse-step [N]           Go back N instructions (default: 1)
+    reverse-continue           Go back to the previous breakpoint or watchpoint hit
+    break [ADDR|OPCODE]        Set a breakpoint at ADDR or on every opcode matching OPCODE, e.g.
+                               DXYN, or list the breakpoints and watchpoints
+    watch ADDR [LEN] [r|w|rw]  Stop when LEN bytes from ADDR are read or written (default: 1, w)
+    watch REG                  Stop when v0-vf or i changes
+    log ADDR|OPCODE MESSAGE    Print MESSAGE instead of stopping, with {EXPR} replaced by its
+                               value, or {EXPR:x} in hex, e.g. log 0x208 v3={v3} i={i:x}
+    delete [ADDR|OPCODE|REG]   Remove breakpoints and watchpoints, or all of them
+    regs                       Show the registers and timers
+    mem ADDR [LEN]             Show LEN bytes of memory from ADDR (default: 64)
+    stack                      Show the return addresses on the stack
+    disasm [ADDR]              List the instructions from ADDR (default: pc)
+    symbols                    List the symbols loaded for the program
+    set REG VALUE              Set v0-vf, i, pc, sp, dt or st
+    keys [KEYS]                Hold keys, e.g. 5+A, or none to release them all
+    help                       Show this message
+    quit                       Exit
+
+Breakpoints and watchpoints take a condition after `if`, e.g. `break 0x208 if v3 == 0x10 && i >=
+0x300`, and only stop when it is true. Conditions use v0-vf, i, pc, sp, dt, st, mem[ADDR], hits
+(times reached) and the operators of C.
+
+Going back restores a snapshot of the system, taken every second of emulated time for the last ten
+minutes, and replays the instructions from it. Breakpoint hit counts are not rewound.
+
+Commands may be shortened to their first letter where unambiguous (s, n, c, b, r), the reverse
+commands to rs and rc, and an empty line repeats the last command. Addresses may be given as a
+label from the symbols, e.g. `break draw_paddle` or `mem score+0x2`.";
+
+/// Why execution stopped
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stop {
+    /// The requested instructions ran
+    Done,
+    /// A breakpoint or watchpoint was hit
+    Break(Break),
+    /// The program jumps to itself or ran into empty memory
+    Halted,
+    /// `RUN_LIMIT` instructions ran without stopping
+    Limit,
+    /// Going back reached the oldest snapshot
+    Start,
+}
+
+/// An interactive debugger driving a system one command at a time. Commands return the text to
+/// show, so the same debugger can sit behind a terminal prompt or any other frontend. Breakpoints
+/// and watchpoints are kept by the system, see `System::get_breakpoints`.
+///
+/// Running takes periodic snapshots of the system, so it can be taken back to any earlier
+/// instruction by restoring the snapshot before it and running forward again. `CXNN` is
+/// replayed exactly, as its generator is part of the save state.
+#[derive(Clone, Debug, Default)]
+pub struct Debugger {
+    cycles: u64,
+    last_command: String,
+    /// The cycle each snapshot was taken at and its save state, oldest first
+    history: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how many instructions have run under the debugger
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Runs a command and returns its output. An empty line repeats the previous command.
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The system being debugged
+    /// * `line` - The command, e.g. `step 5` or `set v3 0x10`
+    pub fn execute(&mut self, system: &mut System, line: &str) -> Result<String, String> {
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            line.trim().to_string()
+        };
+        self.last_command = line.clone();
+
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(String::new()),
+        };
+        let args: Vec<&str> = words.collect();
+
+        let stop = match command {
+            "step" | "s" => {
+                let count = match args.first() {
+                    Some(count) => count
+                        .parse()
+                        .map_err(|_| format!("invalid count '{}'", count))?,
+                    None => 1,
+                };
+                self.run(system, count, |_| false)
+            }
+            "next" | "n" => match Instruction::decode(system.get_opcode(system.get_pc())) {
+                Some(Instruction::Call(_)) => {
+                    let (sp, pc) = (system.get_sp(), system.get_pc() + 2);
+                    self.run(system, RUN_LIMIT, |system| {
+                        system.get_sp() == sp && system.get_pc() == pc
+                    })
+                }
+                _ => self.run(system, 1, |_| false),
+            },
+            "finish" => {
+                let sp = system.get_sp();
+                if sp == 0 {
+                    return Err(String::from("not in a subroutine"));
+                }
+                self.run(system, RUN_LIMIT, |system| system.get_sp() < sp)
+            }
+            "continue" | "c" => self.run(system, RUN_LIMIT, |_| false),
+            "frame" => {
+                let remaining = TICKS_PER_FRAME as u64 - self.cycles % TICKS_PER_FRAME as u64;
+                self.run(system, remaining, |_| false)
+            }
+            "reverse-step" | "rs" => {
+                let count = match args.first() {
+                    Some(count) => count
+                        .parse()
+                        .map_err(|_| format!("invalid count '{}'", count))?,
+                    None => 1,
+                };
+                self.reverse_step(system, count)?
+            }
+            "reverse-continue" | "rc" => self.reverse_continue(system)?,
+            _ => return self.inspect(system, command, &args),
+        };
+
+        let mut output: String = system
+            .take_logs()
+            .into_iter()
+            .map(|log| log + "\n")
+            .collect();
+        match stop {
+            Stop::Done => {}
+            Stop::Break(hit) => writeln!(output, "{}", hit.describe(system.get_symbols())).unwrap(),
+            Stop::Halted => output.push_str("Program halted\n"),
+            Stop::Limit => {
+                writeln!(output, "Still running after {} instructions", RUN_LIMIT).unwrap()
+            }
+            Stop::Start => output.push_str("Reached the oldest snapshot\n"),
+        }
+        output.push_str(&self.location(system));
+
+        Ok(output)
+    }
+
+    /// Runs commands that only look at or change the system's state
+    fn inspect(
+        &mut self,
+        system: &mut System,
+        command: &str,
+        args: &[&str],
+    ) -> Result<String, String> {
+        let mut output = String::new();
+
+        match (command, args) {
+            ("break" | "b", []) => output = format_breakpoints(system),
+            ("break" | "b", [target, rest @ ..]) => {
+                let rule = parse_condition(rest)?.map(|condition| Rule::new(Some(condition), None));
+                let trigger = parse_trigger(target, system.get_symbols())?;
+
+                add_trigger(system.get_breakpoints_mut(), trigger, rule);
+                writeln!(output, "{}", format_trigger(system, trigger)).unwrap();
+            }
+            ("log", [target, message @ ..]) if !message.is_empty() => {
+                let rule = Rule::new(None, Some(Message::parse(&message.join(" "))?));
+                let trigger = parse_trigger(target, system.get_symbols())?;
+
+                add_trigger(system.get_breakpoints_mut(), trigger, Some(rule));
+                writeln!(output, "{}", format_trigger(system, trigger)).unwrap();
+            }
+            ("watch" | "w", [target, ..]) => {
+                let split = args
+                    .iter()
+                    .position(|arg| *arg == "if")
+                    .unwrap_or(args.len());
+                let rule = parse_condition(&args[split..])?
+                    .map(|condition| Rule::new(Some(condition), None));
+
+                let trigger = if let (Some(register), 1) = (Register::parse(target), split) {
+                    Trigger::Register(register)
+                } else {
+                    let watch = parse_watch(&args[..split], system.get_symbols())?;
+                    system.get_breakpoints_mut().add_watch(watch);
+                    Trigger::Watch(watch.start)
+                };
+
+                add_trigger(system.get_breakpoints_mut(), trigger, rule);
+                writeln!(output, "{}", format_trigger(system, trigger)).unwrap();
+            }
+            ("delete", []) => system.get_breakpoints_mut().clear(),
+            ("delete", [target]) => {
+                let address = parse_address(target, system.get_symbols());
+                let breakpoints = system.get_breakpoints_mut();
+                let removed = if let Ok(address) = address {
+                    // Both rather than either, so a breakpoint and a watchpoint at the same
+                    // address go together
+                    breakpoints.remove_address(address) | breakpoints.remove_watch(address)
+                } else if let Some(register) = Register::parse(target) {
+                    breakpoints.remove_register(register)
+                } else if let Some(pattern) = OpcodePattern::parse(target) {
+                    breakpoints.remove_opcode(pattern)
+                } else {
+                    return Err(format!("invalid breakpoint '{}'", target));
+                };
+
+                if !removed {
+                    return Err(format!("no breakpoint or watchpoint at '{}'", target));
+                }
+            }
+            ("regs" | "r", []) => output = format_registers(system),
+            ("mem", [address]) | ("mem", [address, _]) => {
+                let start = parse_address(address, system.get_symbols())?;
+                let len = match args.get(1) {
+                    Some(len) => parse_number(len)
+                        .ok_or_else(|| format!("invalid length '{}'", len))?
+                        as usize,
+                    None => MEM_LENGTH,
+                };
+                let memory = system.get_memory();
+                let end = (start + len).min(memory.len());
+
+                for (idx, row) in memory[start..end].chunks(16).enumerate() {
+                    let bytes: Vec<String> = row.iter().map(|b| format!("{:02X}", b)).collect();
+                    writeln!(output, "{:#05x}  {}", start + idx * 16, bytes.join(" ")).unwrap();
+                }
+            }
+            ("stack", []) => {
+                if system.get_sp() == 0 {
+                    output.push_str("The stack is empty\n");
+                }
+                for idx in (1..=system.get_sp()).rev() {
+                    let address = system.get_stack()[idx];
+                    write!(output, "#{:<2} {:#05x}", idx, address).unwrap();
+                    match system.get_symbols().lookup(address) {
+                        Some(name) => writeln!(output, "  <{}>", name).unwrap(),
+                        None => output.push('\n'),
+                    }
+                }
+            }
+            ("disasm", []) | ("disasm", [_]) => {
+                let symbols = system.get_symbols();
+                let address = match args.first() {
+                    Some(address) => parse_address(address, symbols)?,
+                    None => system.get_pc(),
+                };
+
+                for line in disassemble_at(system.get_memory(), address, DISASM_LINES) {
+                    let marker = if line.address == system.get_pc() {
+                        "=>"
+                    } else {
+                        "  "
+                    };
+                    if let Some(name) = symbols.get_name(line.address) {
+                        writeln!(output, "{}:", name).unwrap();
+                    }
+                    writeln!(
+                        output,
+                        "{} {}",
+                        marker,
+                        line.display_with_symbols(Syntax::Cowgod, symbols)
+                    )
+                    .unwrap();
+                }
+            }
+            ("symbols", []) => {
+                let symbols = system.get_symbols();
+                if symbols.is_empty() {
+                    output.push_str("No symbols\n");
+                }
+                for (address, name) in symbols.iter() {
+                    writeln!(output, "{:#05x}  {}", address, name).unwrap();
+                }
+            }
+            ("set", [register, value]) => {
+                set_register(system, register, value)?;
+                self.rewrite_history(system);
+            }
+            ("keys", []) => {
+                writeln!(output, "Held keys: {}", format_keys(system.get_keys())).unwrap()
+            }
+            ("keys", [keys]) => {
+                let mask = parse_keys(keys).ok_or_else(|| format!("invalid keys '{}'", keys))?;
+                system.set_keys(mask);
+                self.rewrite_history(system);
+                writeln!(output, "Held keys: {}", format_keys(mask)).unwrap();
+            }
+            ("help" | "h", []) => {
+                output.push_str(DEBUGGER_HELP);
+                output.push('\n');
+            }
+            _ => {
+                return Err(format!(
+                    "unknown command '{}', type help for a list of commands",
+                    std::iter::once(command)
+                        .chain(args.iter().copied())
+                        .collect::<Vec<&str>>()
+                        .join(" ")
+                ))
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Runs up to `count` instructions, stopping early at a breakpoint, when the program halts
+    /// or when `done` returns true after an instruction
+    fn run<F: Fn(&System) -> bool>(&mut self, system: &mut System, count: u64, done: F) -> Stop {
+        if self.history.is_empty() {
+            self.snapshot(system);
+        }
+
+        for _ in 0..count {
+            if system.is_halted() {
+                return Stop::Halted;
+            }
+
+            let ran = !system.is_break_deferred();
+            let hit = system.tick();
+            if ran {
+                self.cycles += 1;
+            }
+
+            if let Some(hit) = hit {
+                return Stop::Break(hit);
+            }
+            // Snapshots are only taken after instructions that did not stop, as the breakpoint
+            // the system holds back after a watchpoint is not part of its save state. Ones
+            // already taken are kept when running forward again after going back.
+            if self.cycles.is_multiple_of(SNAPSHOT_INTERVAL)
+                && self
+                    .history
+                    .back()
+                    .is_none_or(|(cycle, _)| *cycle < self.cycles)
+            {
+                self.snapshot(system);
+            }
+            if done(system) {
+                return Stop::Done;
+            }
+        }
+
+        if count == RUN_LIMIT {
+            Stop::Limit
+        } else {
+            Stop::Done
+        }
+    }
+
+    /// Goes back `count` instructions, or to the oldest snapshot if they were not all recorded
+    fn reverse_step(&mut self, system: &mut System, count: u64) -> Result<Stop, String> {
+        let oldest = match self.history.front() {
+            Some((cycle, _)) => *cycle,
+            None => return Err(String::from("nothing has run yet")),
+        };
+        let (target, stop) = match self.cycles.checked_sub(count) {
+            Some(target) if target >= oldest => (target, Stop::Done),
+            _ => (oldest, Stop::Start),
+        };
+        let idx = self
+            .history
+            .iter()
+            .rposition(|(cycle, _)| *cycle <= target)
+            .unwrap();
+
+        self.replay(system, idx, target);
+        Ok(stop)
+    }
+
+    /// Goes back to just after the latest breakpoint or watchpoint hit before the current
+    /// instruction, searching back one snapshot at a time
+    fn reverse_continue(&mut self, system: &mut System) -> Result<Stop, String> {
+        if self.history.is_empty() {
+            return Err(String::from("nothing has run yet"));
+        }
+
+        // Hits found by replaying the snapshot before `end` up to it, ending where they stopped.
+        // `end` starts before the current instruction, so the hit stopped at now is skipped.
+        let mut end = self.cycles.saturating_sub(1);
+        for idx in (0..self.history.len()).rev() {
+            let cycle = self.history[idx].0;
+            if cycle >= end {
+                continue;
+            }
+
+            if let Some((at, hit)) = self.replay(system, idx, end) {
+                self.replay(system, idx, at);
+                return Ok(Stop::Break(hit));
+            }
+            end = cycle;
+        }
+
+        let oldest = self.history[0].0;
+        self.replay(system, 0, oldest);
+        Ok(Stop::Start)
+    }
+
+    /// Restores a snapshot and runs forward to the cycle `target`, returning the last
+    /// breakpoint or watchpoint hit on the way with the cycle it stopped at. Log-points print
+    /// nothing and hit counts are left as they were.
+    fn replay(&mut self, system: &mut System, idx: usize, target: u64) -> Option<(u64, Break)> {
+        let (cycle, state) = &self.history[idx];
+        let breakpoints = system.get_breakpoints().clone();
+        system
+            .load_state(state)
+            .expect("snapshots are valid save states");
+        self.cycles = *cycle;
+
+        let mut last = None;
+        while self.cycles < target {
+            // A breakpoint held back after a watchpoint is reached by the next tick without
+            // running anything, so it is not counted as a cycle
+            let ran = !system.is_break_deferred();
+            let hit = system.tick();
+            if ran {
+                self.cycles += 1;
+            }
+
+            if let Some(hit) = hit {
+                last = Some((self.cycles, hit));
+            }
+        }
+
+        *system.get_breakpoints_mut() = breakpoints;
+        system.take_logs();
+
+        last
+    }
+
+    fn snapshot(&mut self, system: &System) {
+        self.history.push_back((self.cycles, system.save_state()));
+        if self.history.len() > MAX_SNAPSHOTS {
+            self.history.pop_front();
+        }
+    }
+
+    /// Replaces the snapshots from the current cycle on after the system was changed, as
+    /// replaying them would no longer lead to the same state
+    fn rewrite_history(&mut self, system: &System) {
+        while self
+            .history
+            .back()
+            .is_some_and(|(cycle, _)| *cycle >= self.cycles)
+        {
+            self.history.pop_back();
+        }
+
+        self.snapshot(system);
+    }
+
+    /// Describes where execution is, with the next instruction to run
+    fn location(&self, system: &System) -> String {
+        let line = &disassemble_at(system.get_memory(), system.get_pc(), 1)[0];
+
+        format!(
+            "{}    cycle {} frame {}\n",
+            line.display_with_symbols(Syntax::Cowgod, system.get_symbols()),
+            self.cycles,
+            self.cycles / TICKS_PER_FRAME as u64
+        )
+    }
+}
+
+/// Parses an address, a number or a label from the symbols
+fn parse_address(text: &str, symbols: &Symbols) -> Result<usize, String> {
+    symbols
+        .resolve(text)
+        .filter(|address| *address < 0x1000)
+        .ok_or_else(|| format!("invalid address '{}'", text))
+}
+
+/// Parses the arguments of `watch ADDR [LEN] [r|w|rw]`
+fn parse_watch(args: &[&str], symbols: &Symbols) -> Result<Watch, String> {
+    let mut watch = Watch {
+        start: parse_address(args[0], symbols)?,
+        len: 1,
+        kind: WatchKind::Write,
+    };
+
+    for arg in args[1..].iter() {
+        if let Some(kind) = WatchKind::parse(arg) {
+            watch.kind = kind;
+        } else {
+            watch.len = parse_number(arg)
+                .filter(|len| *len > 0)
+                .ok_or_else(|| format!("invalid length '{}'", arg))?
+                as usize;
+        }
+    }
+
+    Ok(watch)
+}
+
+fn format_watch(watch: &Watch, symbols: &Symbols) -> String {
+    let kind = match watch.kind {
+        WatchKind::Read => "reads",
+        WatchKind::Write => "writes",
+        WatchKind::ReadWrite => "reads and writes",
+    };
+
+    format!(
+        "Watching {} of {}-{}",
+        kind,
+        symbols.format(watch.start),
+        symbols.format(watch.start + watch.len - 1)
+    )
+}
+
+/// Parses a breakpoint's target, an address or an opcode class
+fn parse_trigger(target: &str, symbols: &Symbols) -> Result<Trigger, String> {
+    if let Ok(address) = parse_address(target, symbols) {
+        Ok(Trigger::Address(address))
+    } else if let Some(pattern) = OpcodePattern::parse(target) {
+        Ok(Trigger::Opcode(pattern))
+    } else {
+        Err(format!("invalid address or opcode '{}'", target))
+    }
+}
+
+/// Parses the optional `if CONDITION` ending a `break` or `watch`
+fn parse_condition(args: &[&str]) -> Result<Option<Expr>, String> {
+    match args {
+        [] => Ok(None),
+        ["if", condition @ ..] if !condition.is_empty() => {
+            Expr::parse(&condition.join(" ")).map(Some)
+        }
+        _ => Err(format!(
+            "expected 'if CONDITION', found '{}'",
+            args.join(" ")
+        )),
+    }
+}
+
+/// Sets a breakpoint, or watches a register, replacing the rule of an existing one. Watchpoints
+/// on memory are added by the caller, which has their length.
+fn add_trigger(breakpoints: &mut Breakpoints, trigger: Trigger, rule: Option<Rule>) {
+    match trigger {
+        Trigger::Address(address) => breakpoints.add_address(address),
+        Trigger::Opcode(pattern) => breakpoints.add_opcode(pattern),
+        Trigger::Register(register) => breakpoints.add_register(register),
+        Trigger::Watch(_) => {}
+    }
+
+    match rule {
+        Some(rule) => breakpoints.set_rule(trigger, rule),
+        None => {
+            breakpoints.remove_rule(trigger);
+        }
+    }
+}
+
+/// Describes a breakpoint or watchpoint with its condition or log message
+fn format_trigger(system: &System, trigger: Trigger) -> String {
+    let (breakpoints, symbols) = (system.get_breakpoints(), system.get_symbols());
+    let rule = breakpoints.get_rule(trigger);
+    let mut output = match (trigger, rule.and_then(|rule| rule.message.as_ref())) {
+        (Trigger::Address(address), Some(_)) => {
+            format!("Log-point at {}", symbols.format(address))
+        }
+        (Trigger::Address(address), None) => {
+            format!("Breakpoint at {}", symbols.format(address))
+        }
+        (Trigger::Opcode(pattern), Some(_)) => format!("Log-point on {}", pattern),
+        (Trigger::Opcode(pattern), None) => format!("Breakpoint on {}", pattern),
+        (Trigger::Register(register), _) => format!("Watching {}", register),
+        (Trigger::Watch(start), _) => breakpoints
+            .get_watches()
+            .iter()
+            .filter(|watch| watch.start == start)
+            .map(|watch| format_watch(watch, symbols))
+            .collect::<Vec<String>>()
+            .join(", "),
+    };
+
+    if let Some(rule) = rule {
+        if let Some(condition) = rule.condition.as_ref() {
+            write!(output, " if {}", condition).unwrap();
+        }
+        if let Some(message) = rule.message.as_ref() {
+            write!(output, ": {}", message).unwrap();
+        }
+        if rule.get_hits() > 0 {
+            write!(output, " ({} hits)", rule.get_hits()).unwrap();
+        }
+    }
+
+    output
+}
+
+fn format_breakpoints(system: &System) -> String {
+    let breakpoints = system.get_breakpoints();
+    let mut triggers: Vec<Trigger> = vec![];
+
+    triggers.extend(
+        breakpoints
+            .get_addresses()
+            .iter()
+            .map(|address| Trigger::Address(*address)),
+    );
+    triggers.extend(
+        breakpoints
+            .get_opcodes()
+            .iter()
+            .map(|pattern| Trigger::Opcode(*pattern)),
+    );
+    for watch in breakpoints.get_watches() {
+        if !triggers.contains(&Trigger::Watch(watch.start)) {
+            triggers.push(Trigger::Watch(watch.start));
+        }
+    }
+    triggers.extend(
+        breakpoints
+            .get_registers()
+            .iter()
+            .map(|register| Trigger::Register(*register)),
+    );
+
+    if triggers.is_empty() {
+        return String::from("No breakpoints or watchpoints\n");
+    }
+
+    triggers
+        .into_iter()
+        .map(|trigger| format_trigger(system, trigger) + "\n")
+        .collect()
+}
+
+/// Parses held keys written as hex digits joined by `+`, e.g. `5+A`, or `none`
+fn parse_keys(text: &str) -> Option<u16> {
+    if text == "none" {
+        return Some(0);
+    }
+
+    text.split('+').try_fold(0, |mask, key| {
+        let key = u8::from_str_radix(key, 16).ok().filter(|key| *key < 16)?;
+        Some(mask | 1 << key)
+    })
+}
+
+fn format_keys(mask: u16) -> String {
+    let keys: Vec<String> = (0..16)
+        .filter(|key| mask & (1 << key) != 0)
+        .map(|key| format!("{:X}", key))
+        .collect();
+
+    if keys.is_empty() {
+        String::from("none")
+    } else {
+        keys.join("+")
+    }
+}
+
+fn format_registers(system: &System) -> String {
+    let mut output = String::new();
+
+    for (row, registers) in system.get_v().chunks(8).enumerate() {
+        let values: Vec<String> = registers
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| format!("V{:X}={:02X}", row * 8 + idx, value))
+            .collect();
+        writeln!(output, "{}", values.join(" ")).unwrap();
+    }
+
+    writeln!(
+        output,
+        "I={:03X} PC={:03X} SP={:X} DT={:02X} ST={:02X}",
+        system.get_i(),
+        system.get_pc(),
+        system.get_sp(),
+        system.get_delay_timer(),
+        system.get_sound_timer()
+    )
+    .unwrap();
+
+    output
+}
+
+/// Sets a register by name, e.g. `v3` or `pc`, checking the value fits. The value may be a label
+/// from the symbols, e.g. to point `I` at a sprite.
+pub(crate) fn set_register(system: &mut System, register: &str, value: &str) -> Result<(), String> {
+    let number = system
+        .get_symbols()
+        .resolve(value)
+        .ok_or_else(|| format!("invalid value '{}'", value))?;
+    let byte = || u8::try_from(number).map_err(|_| format!("{} does not fit in a byte", value));
+
+    match register.to_ascii_lowercase().as_str() {
+        "i" => {
+            system.set_i(u16::try_from(number).map_err(|_| format!("{} does not fit in I", value))?)
+        }
+        "pc" => system.set_pc(parse_address(value, system.get_symbols())?.min(0xFFE)),
+        "sp" if number < 16 => system.set_sp(number),
+        "sp" => return Err(String::from("the stack pointer must be below 16")),
+        "dt" => system.set_delay_timer(byte()?),
+        "st" => system.set_sound_timer(byte()?),
+        name => match name
+            .strip_prefix('v')
+            .map(|digit| usize::from_str_radix(digit, 16))
+        {
+            Some(Ok(idx)) if idx < 16 => system.set_v(idx, byte()?),
+            _ => return Err(format!("unknown register '{}'", register)),
+        },
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_system(rom: Vec<u8>) -> System {
+        let mut system = System::new();
+        system.write_rom(rom);
+        system
+    }
+
+    #[test]
+    fn test_step_and_break() {
+        // v0 += 1, call 0x208, jump 0x200, padding, then the subroutine: v1 += 1, return
+        let mut system = build_system(vec![
+            0x70, 0x01, 0x22, 0x08, 0x12, 0x00, 0x00, 0x00, 0x71, 0x01, 0x00, 0xEE,
+        ]);
+        let mut debugger = Debugger::new();
+
+        let output = debugger.execute(&mut system, "step").unwrap();
+        assert!(output.starts_with("0x202  2208  CALL 0x208"));
+        assert_eq!(system.get_v()[0], 1);
+
+        debugger.execute(&mut system, "next").unwrap();
+        assert_eq!(system.get_pc(), 0x204);
+        assert_eq!(system.get_v()[1], 1);
+
+        debugger.execute(&mut system, "break 0x20a").unwrap();
+        let output = debugger.execute(&mut system, "continue").unwrap();
+        assert!(output.starts_with("Breakpoint at 0x20a"));
+        assert_eq!(system.get_sp(), 1);
+
+        debugger.execute(&mut system, "finish").unwrap();
+        assert_eq!((system.get_pc(), system.get_sp()), (0x204, 0));
+        assert!(debugger.execute(&mut system, "finish").is_err());
+
+        // An empty line repeats the last command
+        debugger.execute(&mut system, "s").unwrap();
+        debugger.execute(&mut system, "").unwrap();
+        assert_eq!(system.get_pc(), 0x202);
+        assert_eq!(debugger.get_cycles(), 11);
+    }
+
+    #[test]
+    fn test_watch_and_delete() {
+        // i := 0x300, v0 := 5, save v0 (stores nothing, the count is exclusive), bcd v0
+        let mut system = build_system(vec![0xA3, 0x00, 0x60, 0x05, 0xF0, 0x55, 0xF0, 0x33]);
+        let mut debugger = Debugger::new();
+
+        debugger.execute(&mut system, "watch v0").unwrap();
+        debugger.execute(&mut system, "watch 0x301 2 rw").unwrap();
+        debugger.execute(&mut system, "break FX33").unwrap();
+        assert_eq!(
+            debugger.execute(&mut system, "break").unwrap(),
+            "Breakpoint on FX33\nWatching reads and writes of 0x301-0x302\nWatching V0\n"
+        );
+
+        let output = debugger.execute(&mut system, "continue").unwrap();
+        assert!(output.starts_with("V0 changed from 0x00 to 0x05 by 0x202"));
+        let output = debugger.execute(&mut system, "continue").unwrap();
+        assert!(output.starts_with("Breakpoint on FX33 at 0x206"));
+        let output = debugger.execute(&mut system, "continue").unwrap();
+        assert!(output.starts_with("0x301 written by 0x206"));
+
+        debugger.execute(&mut system, "delete v0").unwrap();
+        assert!(debugger.execute(&mut system, "delete v0").is_err());
+        debugger.execute(&mut system, "delete").unwrap();
+        assert!(system.get_breakpoints().is_empty());
+    }
+
+    #[test]
+    fn test_deferred_break() {
+        // v0 := 1, v1 := 2, then loop
+        let mut system = build_system(vec![0x60, 0x01, 0x61, 0x02, 0x12, 0x04]);
+        let mut debugger = Debugger::new();
+
+        debugger.execute(&mut system, "watch v0").unwrap();
+        debugger.execute(&mut system, "break 0x202").unwrap();
+        let output = debugger.execute(&mut system, "continue").unwrap();
+        assert!(output.starts_with("V0 changed from 0x00 to 0x01 by 0x200"));
+        assert_eq!(debugger.get_cycles(), 1);
+
+        // The breakpoint held back by the watchpoint runs nothing
+        let output = debugger.execute(&mut system, "continue").unwrap();
+        assert!(output.starts_with("Breakpoint at 0x202"));
+        assert_eq!((debugger.get_cycles(), system.get_pc()), (1, 0x202));
+
+        debugger.execute(&mut system, "step").unwrap();
+        assert_eq!((debugger.get_cycles(), system.get_pc()), (2, 0x204));
+        debugger.execute(&mut system, "rs 1").unwrap();
+        assert_eq!((debugger.get_cycles(), system.get_pc()), (1, 0x202));
+        assert_eq!(system.get_v()[1], 0);
+    }
+
+    #[test]
+    fn test_conditions_and_log_points() {
+        // v0 += 1, then loop back
+        let mut system = build_system(vec![0x70, 0x01, 0x12, 0x00]);
+        let mut debugger = Debugger::new();
+
+        assert_eq!(
+            debugger
+                .execute(&mut system, "break 0x202 if v0 == 3")
+                .unwrap(),
+            "Breakpoint at 0x202 if v0 == 3\n"
+        );
+        debugger
+            .execute(&mut system, "log 0x200 v0={v0:x} #{hits}")
+            .unwrap();
+
+        let output = debugger.execute(&mut system, "continue").unwrap();
+        assert!(output.starts_with("v0=0x1 #1\nv0=0x2 #2\nBreakpoint at 0x202\n"));
+        assert_eq!(system.get_v()[0], 3);
+        assert_eq!(
+            debugger.execute(&mut system, "break").unwrap(),
+            "Log-point at 0x200: v0={v0:x} #{hits} (2 hits)\n\
+             Breakpoint at 0x202 if v0 == 3 (3 hits)\n"
+        );
+
+        debugger
+            .execute(&mut system, "watch v0 if hits % 4 == 0")
+            .unwrap();
+        debugger.execute(&mut system, "delete 0x202").unwrap();
+        let output = debugger.execute(&mut system, "continue").unwrap();
+        assert!(output.contains("V0 changed from 0x06 to 0x07 by 0x200"));
+
+        assert!(debugger
+            .execute(&mut system, "break 0x202 when v0")
+            .is_err());
+        assert!(debugger
+            .execute(&mut system, "break 0x202 if v0 ==")
+            .is_err());
+        assert!(debugger.execute(&mut system, "log 0x200").is_err());
+    }
+
+    #[test]
+    fn test_inspect_and_set() {
+        let mut system = build_system(vec![0x60, 0x05]);
+        let mut debugger = Debugger::new();
+
+        debugger.execute(&mut system, "set v3 0x10").unwrap();
+        debugger.execute(&mut system, "set i 0x300").unwrap();
+        assert_eq!(system.get_v()[3], 0x10);
+        assert_eq!(system.get_i(), 0x300);
+        assert!(debugger.execute(&mut system, "set v3 0x100").is_err());
+        assert!(debugger.execute(&mut system, "set vg 1").is_err());
+
+        let regs = debugger.execute(&mut system, "regs").unwrap();
+        assert!(regs.contains("V3=10"));
+        assert!(regs.contains("I=300 PC=200"));
+
+        assert_eq!(
+            debugger.execute(&mut system, "mem 0x200 4").unwrap(),
+            "0x200  60 05 00 00\n"
+        );
+        assert!(debugger
+            .execute(&mut system, "disasm")
+            .unwrap()
+            .starts_with("=> 0x200  6005  LD V0, 0x05"));
+
+        debugger.execute(&mut system, "keys 5+A").unwrap();
+        assert_eq!(system.get_keys(), 1 << 0x5 | 1 << 0xA);
+        assert!(debugger.execute(&mut system, "keys 5+G").is_err());
+        assert!(debugger.execute(&mut system, "bogus").is_err());
+    }
+
+    #[test]
+    fn test_symbols() {
+        // call 0x206, jump 0x200, padding, then the subroutine: i := 0x20C, return
+        let mut system = build_system(vec![
+            0x22, 0x06, 0x12, 0x00, 0x00, 0x00, 0xA2, 0x0C, 0x00, 0xEE,
+        ]);
+        system.set_symbols(Symbols::parse("0x200 main\n0x206 draw\n0x20C sprite\n").unwrap());
+        let mut debugger = Debugger::new();
+
+        assert_eq!(
+            debugger.execute(&mut system, "break draw+0x2").unwrap(),
+            "Breakpoint at draw+0x2\n"
+        );
+        let output = debugger.execute(&mut system, "continue").unwrap();
+        assert!(output.starts_with("Breakpoint at draw+0x2\n0x208  00EE  RET"));
+        assert_eq!(
+            debugger.execute(&mut system, "stack").unwrap(),
+            "#1  0x202  <main+0x2>\n"
+        );
+        assert!(debugger
+            .execute(&mut system, "disasm draw")
+            .unwrap()
+            .starts_with("draw:\n   0x206  A20C  LD I, sprite\n=> 0x208"));
+
+        debugger.execute(&mut system, "set i sprite").unwrap();
+        assert_eq!(system.get_i(), 0x20C);
+        debugger.execute(&mut system, "delete draw+0x2").unwrap();
+        assert!(debugger.execute(&mut system, "break nowhere").is_err());
+    }
+
+    #[test]
+    fn test_reverse() {
+        // Count v0 up, saving it to 0x300 whenever it is 5
+        let mut system = build_system(vec![
+            0xA3, 0x00, 0x70, 0x01, 0x40, 0x05, 0xF1, 0x55, 0x12, 0x00,
+        ]);
+        let mut debugger = Debugger::new();
+        assert!(debugger.execute(&mut system, "reverse-step").is_err());
+
+        debugger.execute(&mut system, "step 5").unwrap();
+        debugger.execute(&mut system, "reverse-step 2").unwrap();
+        assert_eq!(debugger.get_cycles(), 3);
+        assert_eq!((system.get_pc(), system.get_v()[0]), (0x208, 1));
+        let output = debugger.execute(&mut system, "rs 10").unwrap();
+        assert!(output.starts_with("Reached the oldest snapshot\n0x200"));
+        assert_eq!(debugger.get_cycles(), 0);
+
+        // Writes end at cycles 20, 1045 and 2070, across several snapshots
+        debugger.execute(&mut system, "step 2500").unwrap();
+        debugger.execute(&mut system, "watch 0x300").unwrap();
+        for cycles in [2070, 1045, 20] {
+            let output = debugger.execute(&mut system, "reverse-continue").unwrap();
+            assert!(output.starts_with("0x300 written by 0x206\n0x208"));
+            assert_eq!(debugger.get_cycles(), cycles);
+        }
+        let output = debugger.execute(&mut system, "rc").unwrap();
+        assert!(output.starts_with("Reached the oldest snapshot"));
+        assert_eq!(system.get_memory()[0x300], 0);
+
+        // Running forward again repeats the same run
+        debugger.execute(&mut system, "continue").unwrap();
+        assert_eq!(debugger.get_cycles(), 20);
+        debugger.execute(&mut system, "continue").unwrap();
+        assert_eq!(debugger.get_cycles(), 1045);
+
+        // Changing the system forgets the snapshots after it
+        debugger.execute(&mut system, "set v0 0x10").unwrap();
+        debugger.execute(&mut system, "step 3").unwrap();
+        debugger.execute(&mut system, "reverse-step 3").unwrap();
+        assert_eq!((debugger.get_cycles(), system.get_v()[0]), (1045, 0x10));
+    }
+}
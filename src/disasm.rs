@@ -0,0 +1,580 @@
+use crate::symbols::Symbols;
+use crate::system::{combine_words, get_op_group, get_op_words, split_op};
+
+use std::fmt;
+use std::io::{self, Write};
+
+/// The address programs are loaded at
+pub const PROGRAM_START: usize = 0x200;
+
+/// The assembly dialects instructions can be written in
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Syntax {
+    /// The mnemonics from Cowgod's technical reference, e.g. `LD V1, 0x20`
+    #[default]
+    Cowgod,
+    /// The syntax of the Octo assembler, e.g. `v1 := 0x20`
+    Octo,
+}
+
+impl Syntax {
+    /// Parses a syntax name, either `cowgod` or `octo`
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the syntax, case insensitive
+    pub fn parse(name: &str) -> Option<Syntax> {
+        match name.to_ascii_lowercase().as_str() {
+            "cowgod" => Some(Syntax::Cowgod),
+            "octo" => Some(Syntax::Octo),
+            _ => None,
+        }
+    }
+}
+
+/// A decoded instruction. Registers are numbered `0x0` to `0xF` and addresses are 12 bits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    /// `00E0`: clear the screen
+    Cls,
+    /// `00EE`: return from a subroutine
+    Ret,
+    /// `0NNN`: call a machine code routine, ignored by the interpreter
+    Sys(u16),
+    /// `1NNN`: jump to an address
+    Jump(u16),
+    /// `2NNN`: call a subroutine
+    Call(u16),
+    /// `3XNN`: skip the next instruction if `VX == NN`
+    SkipEqByte(u8, u8),
+    /// `4XNN`: skip the next instruction if `VX != NN`
+    SkipNeByte(u8, u8),
+    /// `5XY0`: skip the next instruction if `VX == VY`
+    SkipEqReg(u8, u8),
+    /// `6XNN`: `VX = NN`
+    LoadByte(u8, u8),
+    /// `7XNN`: `VX += NN`, without carry
+    AddByte(u8, u8),
+    /// `8XY0`: `VX = VY`
+    LoadReg(u8, u8),
+    /// `8XY1`: `VX |= VY`
+    Or(u8, u8),
+    /// `8XY2`: `VX &= VY`
+    And(u8, u8),
+    /// `8XY3`: `VX ^= VY`
+    Xor(u8, u8),
+    /// `8XY4`: `VX += VY`, with carry in `VF`
+    AddReg(u8, u8),
+    /// `8XY5`: `VX -= VY`, with no borrow in `VF`
+    Sub(u8, u8),
+    /// `8XY6`: `VX >>= 1`, with the shifted out bit in `VF`
+    Shr(u8, u8),
+    /// `8XY7`: `VX = VY - VX`, with no borrow in `VF`
+    SubN(u8, u8),
+    /// `8XYE`: `VX <<= 1`, with the shifted out bit in `VF`
+    Shl(u8, u8),
+    /// `9XY0`: skip the next instruction if `VX != VY`
+    SkipNeReg(u8, u8),
+    /// `ANNN`: `I = NNN`
+    LoadI(u16),
+    /// `BNNN`: jump to `NNN + V0`
+    JumpV0(u16),
+    /// `CXNN`: `VX = random & NN`
+    Random(u8, u8),
+    /// `DXYN`: draw an `N` byte sprite from `I` at `VX`, `VY`
+    Draw(u8, u8, u8),
+    /// `EX9E`: skip the next instruction if key `VX` is held
+    SkipKey(u8),
+    /// `EXA1`: skip the next instruction if key `VX` is not held
+    SkipNotKey(u8),
+    /// `FX07`: `VX = delay timer`
+    LoadDelay(u8),
+    /// `FX0A`: wait for a key press and store it in `VX`
+    WaitKey(u8),
+    /// `FX15`: `delay timer = VX`
+    SetDelay(u8),
+    /// `FX18`: `sound timer = VX`
+    SetSound(u8),
+    /// `FX1E`: `I += VX`
+    AddI(u8),
+    /// `FX29`: point `I` at the font sprite for digit `VX`
+    LoadFont(u8),
+    /// `FX33`: store the decimal digits of `VX` at `I`
+    Bcd(u8),
+    /// `FX55`: store `V0` to `VX` at `I`
+    Store(u8),
+    /// `FX65`: load `V0` to `VX` from `I`
+    Load(u8),
+}
+
+impl Instruction {
+    /// Decodes an opcode the way the interpreter executes it, returning `None` for opcodes it
+    /// ignores. Like the interpreter, sub-operations are told apart by their low byte or
+    /// nibble only, e.g. `01E0` clears the screen just like `00E0`.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The opcode to decode
+    pub fn decode(op: u16) -> Option<Instruction> {
+        use Instruction::*;
+
+        let words = get_op_words(op);
+        let (x, y, n) = (words[1], words[2], words[3]);
+        let byte = combine_words(y, n);
+        let address = op & 0x0FFF;
+
+        let instruction = match get_op_group(op) {
+            0x0 => match split_op(op).1 {
+                0xE0 => Cls,
+                0xEE => Ret,
+                _ => Sys(address),
+            },
+            0x1 => Jump(address),
+            0x2 => Call(address),
+            0x3 => SkipEqByte(x, byte),
+            0x4 => SkipNeByte(x, byte),
+            0x5 => SkipEqReg(x, y),
+            0x6 => LoadByte(x, byte),
+            0x7 => AddByte(x, byte),
+            0x8 => match n {
+                0x0 => LoadReg(x, y),
+                0x1 => Or(x, y),
+                0x2 => And(x, y),
+                0x3 => Xor(x, y),
+                0x4 => AddReg(x, y),
+                0x5 => Sub(x, y),
+                0x6 => Shr(x, y),
+                0x7 => SubN(x, y),
+                0xE => Shl(x, y),
+                _ => return None,
+            },
+            0x9 => SkipNeReg(x, y),
+            0xA => LoadI(address),
+            0xB => JumpV0(address),
+            0xC => Random(x, byte),
+            0xD => Draw(x, y, n),
+            0xE => match byte {
+                0x9E => SkipKey(x),
+                0xA1 => SkipNotKey(x),
+                _ => return None,
+            },
+            _ => match byte {
+                0x07 => LoadDelay(x),
+                0x0A => WaitKey(x),
+                0x15 => SetDelay(x),
+                0x18 => SetSound(x),
+                0x1E => AddI(x),
+                0x29 => LoadFont(x),
+                0x33 => Bcd(x),
+                0x55 => Store(x),
+                0x65 => Load(x),
+                _ => return None,
+            },
+        };
+
+        Some(instruction)
+    }
+
+    /// Returns the canonical opcode of the instruction. It differs from the decoded opcode when
+    /// the interpreter ignored some of its bits, e.g. `5XY1` encodes back to `5XY0`.
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+
+        let xy =
+            |group: u16, x: u8, y: u8, n: u16| group << 12 | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xnn = |group: u16, x: u8, byte: u8| group << 12 | (x as u16) << 8 | byte as u16;
+
+        match *self {
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            Sys(address) => address,
+            Jump(address) => 0x1000 | address,
+            Call(address) => 0x2000 | address,
+            SkipEqByte(x, byte) => xnn(0x3, x, byte),
+            SkipNeByte(x, byte) => xnn(0x4, x, byte),
+            SkipEqReg(x, y) => xy(0x5, x, y, 0x0),
+            LoadByte(x, byte) => xnn(0x6, x, byte),
+            AddByte(x, byte) => xnn(0x7, x, byte),
+            LoadReg(x, y) => xy(0x8, x, y, 0x0),
+            Or(x, y) => xy(0x8, x, y, 0x1),
+            And(x, y) => xy(0x8, x, y, 0x2),
+            Xor(x, y) => xy(0x8, x, y, 0x3),
+            AddReg(x, y) => xy(0x8, x, y, 0x4),
+            Sub(x, y) => xy(0x8, x, y, 0x5),
+            Shr(x, y) => xy(0x8, x, y, 0x6),
+            SubN(x, y) => xy(0x8, x, y, 0x7),
+            Shl(x, y) => xy(0x8, x, y, 0xE),
+            SkipNeReg(x, y) => xy(0x9, x, y, 0x0),
+            LoadI(address) => 0xA000 | address,
+            JumpV0(address) => 0xB000 | address,
+            Random(x, byte) => xnn(0xC, x, byte),
+            Draw(x, y, n) => xy(0xD, x, y, n as u16),
+            SkipKey(x) => xnn(0xE, x, 0x9E),
+            SkipNotKey(x) => xnn(0xE, x, 0xA1),
+            LoadDelay(x) => xnn(0xF, x, 0x07),
+            WaitKey(x) => xnn(0xF, x, 0x0A),
+            SetDelay(x) => xnn(0xF, x, 0x15),
+            SetSound(x) => xnn(0xF, x, 0x18),
+            AddI(x) => xnn(0xF, x, 0x1E),
+            LoadFont(x) => xnn(0xF, x, 0x29),
+            Bcd(x) => xnn(0xF, x, 0x33),
+            Store(x) => xnn(0xF, x, 0x55),
+            Load(x) => xnn(0xF, x, 0x65),
+        }
+    }
+
+    /// Returns whether the instruction may skip the one after it
+    pub fn is_skip(&self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            SkipEqByte(..)
+                | SkipNeByte(..)
+                | SkipEqReg(..)
+                | SkipNeReg(..)
+                | SkipKey(_)
+                | SkipNotKey(_)
+        )
+    }
+
+    /// Writes the instruction in an assembly syntax
+    ///
+    /// # Arguments
+    ///
+    /// * `syntax` - The syntax to write
+    /// * `address` - How to write the address operand of jumps, calls and `I` loads, e.g. as a
+    ///   label. Numbers are written when `None`.
+    pub fn format(&self, syntax: Syntax, address: Option<&str>) -> String {
+        let target = |nnn: u16| match address {
+            Some(label) => label.to_string(),
+            None => format!("{:#05x}", nnn),
+        };
+
+        match syntax {
+            Syntax::Cowgod => self.format_cowgod(target),
+            Syntax::Octo => self.format_octo(target),
+        }
+    }
+
+    fn format_cowgod<F: Fn(u16) -> String>(&self, target: F) -> String {
+        use Instruction::*;
+
+        match *self {
+            Cls => String::from("CLS"),
+            Ret => String::from("RET"),
+            Sys(nnn) => format!("SYS {}", target(nnn)),
+            Jump(nnn) => format!("JP {}", target(nnn)),
+            Call(nnn) => format!("CALL {}", target(nnn)),
+            SkipEqByte(x, byte) => format!("SE V{:X}, {:#04x}", x, byte),
+            SkipNeByte(x, byte) => format!("SNE V{:X}, {:#04x}", x, byte),
+            SkipEqReg(x, y) => format!("SE V{:X}, V{:X}", x, y),
+            LoadByte(x, byte) => format!("LD V{:X}, {:#04x}", x, byte),
+            AddByte(x, byte) => format!("ADD V{:X}, {:#04x}", x, byte),
+            LoadReg(x, y) => format!("LD V{:X}, V{:X}", x, y),
+            Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+            And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+            AddReg(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+            Shr(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+            SubN(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+            Shl(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+            SkipNeReg(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+            LoadI(nnn) => format!("LD I, {}", target(nnn)),
+            JumpV0(nnn) => format!("JP V0, {}", target(nnn)),
+            Random(x, byte) => format!("RND V{:X}, {:#04x}", x, byte),
+            Draw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipKey(x) => format!("SKP V{:X}", x),
+            SkipNotKey(x) => format!("SKNP V{:X}", x),
+            LoadDelay(x) => format!("LD V{:X}, DT", x),
+            WaitKey(x) => format!("LD V{:X}, K", x),
+            SetDelay(x) => format!("LD DT, V{:X}", x),
+            SetSound(x) => format!("LD ST, V{:X}", x),
+            AddI(x) => format!("ADD I, V{:X}", x),
+            LoadFont(x) => format!("LD F, V{:X}", x),
+            Bcd(x) => format!("LD B, V{:X}", x),
+            Store(x) => format!("LD [I], V{:X}", x),
+            Load(x) => format!("LD V{:X}, [I]", x),
+        }
+    }
+
+    fn format_octo<F: Fn(u16) -> String>(&self, target: F) -> String {
+        use Instruction::*;
+
+        match *self {
+            Cls => String::from("clear"),
+            Ret => String::from("return"),
+            // Octo has no mnemonic for machine code calls, so they are written as raw bytes
+            Sys(nnn) => format!("{:#04x} {:#04x}", nnn >> 8, nnn & 0xFF),
+            Jump(nnn) => format!("jump {}", target(nnn)),
+            Call(nnn) => format!(":call {}", target(nnn)),
+            // Octo's conditionals name the case where the next instruction runs, the opposite
+            // of the case where it is skipped
+            SkipEqByte(x, byte) => format!("if v{:x} != {:#04x} then", x, byte),
+            SkipNeByte(x, byte) => format!("if v{:x} == {:#04x} then", x, byte),
+            SkipEqReg(x, y) => format!("if v{:x} != v{:x} then", x, y),
+            LoadByte(x, byte) => format!("v{:x} := {:#04x}", x, byte),
+            AddByte(x, byte) => format!("v{:x} += {:#04x}", x, byte),
+            LoadReg(x, y) => format!("v{:x} := v{:x}", x, y),
+            Or(x, y) => format!("v{:x} |= v{:x}", x, y),
+            And(x, y) => format!("v{:x} &= v{:x}", x, y),
+            Xor(x, y) => format!("v{:x} ^= v{:x}", x, y),
+            AddReg(x, y) => format!("v{:x} += v{:x}", x, y),
+            Sub(x, y) => format!("v{:x} -= v{:x}", x, y),
+            Shr(x, y) => format!("v{:x} >>= v{:x}", x, y),
+            SubN(x, y) => format!("v{:x} =- v{:x}", x, y),
+            Shl(x, y) => format!("v{:x} <<= v{:x}", x, y),
+            SkipNeReg(x, y) => format!("if v{:x} == v{:x} then", x, y),
+            LoadI(nnn) => format!("i := {}", target(nnn)),
+            JumpV0(nnn) => format!("jump0 {}", target(nnn)),
+            Random(x, byte) => format!("v{:x} := random {:#04x}", x, byte),
+            Draw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+            SkipKey(x) => format!("if v{:x} -key then", x),
+            SkipNotKey(x) => format!("if v{:x} key then", x),
+            LoadDelay(x) => format!("v{:x} := delay", x),
+            WaitKey(x) => format!("v{:x} := key", x),
+            SetDelay(x) => format!("delay := v{:x}", x),
+            SetSound(x) => format!("buzzer := v{:x}", x),
+            AddI(x) => format!("i += v{:x}", x),
+            LoadFont(x) => format!("i := hex v{:x}", x),
+            Bcd(x) => format!("bcd v{:x}", x),
+            Store(x) => format!("save v{:x}", x),
+            Load(x) => format!("load v{:x}", x),
+        }
+    }
+}
+
+/// A line of a disassembly listing: an instruction, or a data byte where no instruction could
+/// be decoded
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    pub instruction: Option<Instruction>,
+}
+
+impl Line {
+    /// Returns the text of the line without its address and bytes, e.g. `LD V1, 0x20`
+    ///
+    /// # Arguments
+    ///
+    /// * `syntax` - The syntax to write
+    pub fn text(&self, syntax: Syntax) -> String {
+        self.text_with_symbols(syntax, &Symbols::new())
+    }
+
+    /// Returns the text of the line like `text`, naming the address operands of jumps, calls and
+    /// `I` loads by symbols where possible, e.g. `CALL draw_paddle`
+    ///
+    /// # Arguments
+    ///
+    /// * `syntax` - The syntax to write
+    /// * `symbols` - The names of addresses
+    pub fn text_with_symbols(&self, syntax: Syntax, symbols: &Symbols) -> String {
+        match (self.instruction, syntax) {
+            (Some(instruction), _) => {
+                let target = symbols.lookup(instruction.encode() as usize & 0x0FFF);
+                instruction.format(syntax, target.as_deref())
+            }
+            (None, Syntax::Cowgod) => {
+                let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:#04x}", b)).collect();
+                format!("DB {}", bytes.join(", "))
+            }
+            (None, Syntax::Octo) => {
+                let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:#04x}", b)).collect();
+                bytes.join(" ")
+            }
+        }
+    }
+
+    /// Returns a displayable form of the line with its address and raw bytes, e.g.
+    /// `0x200  6120  LD V1, 0x20`
+    ///
+    /// # Arguments
+    ///
+    /// * `syntax` - The syntax to write
+    pub fn display(&self, syntax: Syntax) -> DisplayLine<'_> {
+        DisplayLine {
+            line: self,
+            syntax,
+            symbols: None,
+        }
+    }
+
+    /// Returns a displayable form of the line like `display`, with operands named by symbols
+    ///
+    /// # Arguments
+    ///
+    /// * `syntax` - The syntax to write
+    /// * `symbols` - The names of addresses
+    pub fn display_with_symbols<'a>(
+        &'a self,
+        syntax: Syntax,
+        symbols: &'a Symbols,
+    ) -> DisplayLine<'a> {
+        DisplayLine {
+            line: self,
+            syntax,
+            symbols: Some(symbols),
+        }
+    }
+}
+
+/// A `Line` formatted in a syntax, see `Line::display`
+pub struct DisplayLine<'a> {
+    line: &'a Line,
+    syntax: Syntax,
+    symbols: Option<&'a Symbols>,
+}
+
+impl fmt::Display for DisplayLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: String = self
+            .line
+            .bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+
+        let text = match self.symbols {
+            Some(symbols) => self.line.text_with_symbols(self.syntax, symbols),
+            None => self.line.text(self.syntax),
+        };
+
+        write!(f, "{:#05x}  {:<4}  {}", self.line.address, bytes, text)
+    }
+}
+
+/// Disassembles memory linearly, treating every pair of bytes as an instruction. Pairs that do
+/// not decode, and a trailing odd byte, become data lines.
+///
+/// # Arguments
+///
+/// * `memory` - The bytes to disassemble
+/// * `origin` - The address of the first byte, e.g. `PROGRAM_START` for a ROM file
+pub fn disassemble(memory: &[u8], origin: usize) -> Vec<Line> {
+    memory
+        .chunks(2)
+        .enumerate()
+        .map(|(idx, bytes)| {
+            let instruction = match bytes {
+                [high, low] => Instruction::decode((*high as u16) << 8 | *low as u16),
+                _ => None,
+            };
+
+            Line {
+                address: origin + idx * 2,
+                bytes: bytes.to_vec(),
+                instruction,
+            }
+        })
+        .collect()
+}
+
+/// Disassembles a number of instructions of a running system's memory, e.g. around the program
+/// counter in a debugger. The listing stops early at the end of memory.
+///
+/// # Arguments
+///
+/// * `memory` - The system's memory, as returned by `System::get_memory`
+/// * `address` - The address of the first instruction
+/// * `count` - How many instructions to list
+pub fn disassemble_at(memory: &[u8], address: usize, count: usize) -> Vec<Line> {
+    let start = address.min(memory.len());
+    let end = (start + count * 2).min(memory.len());
+
+    disassemble(&memory[start..end], start)
+}
+
+/// Writes a disassembly listing, one line per instruction, with a label line before every
+/// address that has a symbol
+///
+/// # Arguments
+///
+/// * `writer` - Where the listing is written
+/// * `lines` - The lines to write, e.g. from `disassemble`
+/// * `syntax` - The syntax to write
+/// * `symbols` - The names of addresses, used for labels and operands
+pub fn write_listing<W: Write>(
+    mut writer: W,
+    lines: &[Line],
+    syntax: Syntax,
+    symbols: &Symbols,
+) -> io::Result<()> {
+    for line in lines {
+        if let Some(name) = symbols.get_name(line.address) {
+            match syntax {
+                Syntax::Cowgod => writeln!(writer, "{}:", name)?,
+                Syntax::Octo => writeln!(writer, ": {}", name)?,
+            }
+        }
+        writeln!(writer, "{}", line.display_with_symbols(syntax, symbols))?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_round_trip() {
+        for op in 0..=0xFFFFu16 {
+            if let Some(instruction) = Instruction::decode(op) {
+                assert_eq!(Instruction::decode(instruction.encode()), Some(instruction));
+            }
+        }
+
+        assert_eq!(Instruction::decode(0x01E0), Some(Instruction::Cls));
+        assert_eq!(Instruction::decode(0x01E0).unwrap().encode(), 0x00E0);
+        assert_eq!(Instruction::decode(0x8008), None);
+        assert_eq!(Instruction::decode(0xF0FF), None);
+    }
+
+    #[test]
+    fn test_format() {
+        let draw = Instruction::decode(0xD015).unwrap();
+        assert_eq!(draw.format(Syntax::Cowgod, None), "DRW V0, V1, 5");
+        assert_eq!(draw.format(Syntax::Octo, None), "sprite v0 v1 5");
+
+        let load = Instruction::decode(0x6120).unwrap();
+        assert_eq!(load.format(Syntax::Cowgod, None), "LD V1, 0x20");
+        assert_eq!(load.format(Syntax::Octo, None), "v1 := 0x20");
+
+        let jump = Instruction::decode(0x1208).unwrap();
+        assert_eq!(jump.format(Syntax::Cowgod, None), "JP 0x208");
+        assert_eq!(jump.format(Syntax::Octo, Some("main")), "jump main");
+    }
+
+    #[test]
+    fn test_listing() {
+        let lines = disassemble(&[0x61, 0x20, 0xFF, 0xFF, 0x12], PROGRAM_START);
+        let mut listing = vec![];
+        write_listing(&mut listing, &lines, Syntax::Cowgod, &Symbols::new()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(listing).unwrap(),
+            "0x200  6120  LD V1, 0x20\n0x202  FFFF  DB 0xff, 0xff\n0x204  12    DB 0x12\n"
+        );
+
+        let lines = disassemble(&[0x22, 0x06, 0xA2, 0x0A, 0x12, 0x00], PROGRAM_START);
+        let symbols = Symbols::parse("0x200 main\n0x206 draw").unwrap();
+        let mut listing = vec![];
+        write_listing(&mut listing, &lines, Syntax::Octo, &symbols).unwrap();
+
+        assert_eq!(
+            String::from_utf8(listing).unwrap(),
+            ": main\n0x200  2206  :call draw\n0x202  A20A  i := draw+0x4\n0x204  1200  jump main\n"
+        );
+
+        let memory = [0x00, 0xE0, 0xD0, 0x15];
+        let lines = disassemble_at(&memory, 2, 4);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(
+            lines[0].display(Syntax::Octo).to_string(),
+            "0x002  D015  sprite v0 v1 5"
+        );
+    }
+}
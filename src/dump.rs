@@ -0,0 +1,109 @@
+use crate::system::{System, HEIGHT, WIDTH};
+
+use std::io::{self, Write};
+
+/// Writes the machine state of a system as a JSON object: registers, timers, stack, memory (as a
+/// hex string) and the framebuffer (one string of `0`s and `1`s per row)
+///
+/// # Arguments
+///
+/// * `writer` - Where the JSON will be written
+/// * `system` - The system to dump
+pub fn write_json<W: Write>(mut writer: W, system: &System) -> io::Result<()> {
+    let join = |values: Vec<String>| values.join(", ");
+
+    writeln!(writer, "{{")?;
+    writeln!(writer, "  \"pc\": {},", system.get_pc())?;
+    writeln!(writer, "  \"i\": {},", system.get_i())?;
+    writeln!(writer, "  \"sp\": {},", system.get_sp())?;
+    writeln!(
+        writer,
+        "  \"v\": [{}],",
+        join(system.get_v().iter().map(|v| v.to_string()).collect())
+    )?;
+    writeln!(
+        writer,
+        "  \"stack\": [{}],",
+        join(system.get_stack().iter().map(|s| s.to_string()).collect())
+    )?;
+    writeln!(writer, "  \"delay_timer\": {},", system.get_delay_timer())?;
+    writeln!(writer, "  \"sound_timer\": {},", system.get_sound_timer())?;
+    writeln!(writer, "  \"keys\": {},", system.get_keys())?;
+
+    write!(writer, "  \"memory\": \"")?;
+    for byte in system.get_memory() {
+        write!(writer, "{:02x}", byte)?;
+    }
+    writeln!(writer, "\",")?;
+
+    writeln!(writer, "  \"framebuffer\": [")?;
+    for y in 0..HEIGHT {
+        let row: String = (0..WIDTH)
+            .map(|x| if system.get_pixel(x, y) { '1' } else { '0' })
+            .collect();
+        let separator = if y + 1 < HEIGHT { "," } else { "" };
+
+        writeln!(writer, "    \"{}\"{}", row, separator)?;
+    }
+    writeln!(writer, "  ]")?;
+
+    writeln!(writer, "}}")?;
+    writer.flush()
+}
+
+/// Writes the framebuffer as a plain (ASCII) PBM image, which is easy to diff against golden files
+///
+/// # Arguments
+///
+/// * `writer` - Where the image will be written
+/// * `system` - The system whose framebuffer is written
+pub fn write_pbm<W: Write>(mut writer: W, system: &System) -> io::Result<()> {
+    writeln!(writer, "P1\n{} {}", WIDTH, HEIGHT)?;
+
+    for y in 0..HEIGHT {
+        let row: Vec<&str> = (0..WIDTH)
+            .map(|x| if system.get_pixel(x, y) { "1" } else { "0" })
+            .collect();
+
+        writeln!(writer, "{}", row.join(" "))?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pbm() {
+        let mut system = System::new();
+        // Draw the top-left corner of the "0" glyph
+        system.write_rom(vec![0xD0, 0x01]);
+        system.tick();
+
+        let mut pbm = vec![];
+        write_pbm(&mut pbm, &system).unwrap();
+        let pbm = String::from_utf8(pbm).unwrap();
+        let lines: Vec<&str> = pbm.lines().collect();
+
+        assert_eq!(lines[0], "P1");
+        assert_eq!(lines[1], format!("{} {}", WIDTH, HEIGHT));
+        assert!(lines[2].starts_with("1 1 1 1 0 "));
+        assert_eq!(lines.len(), HEIGHT + 2);
+    }
+
+    #[test]
+    fn test_json_memory() {
+        let mut system = System::new();
+        system.write_rom(vec![0x12, 0x00]);
+
+        let mut json = vec![];
+        write_json(&mut json, &system).unwrap();
+        let json = String::from_utf8(json).unwrap();
+
+        assert!(json.contains("\"pc\": 512,"));
+        assert!(json.contains("\"memory\": \"f0909090f0"));
+        assert!(json.contains(&format!("{}1200", "00".repeat(0x200 - 0x50))));
+    }
+}
@@ -0,0 +1,451 @@
+use crate::disasm::{Instruction, Syntax};
+use crate::headless::parse_number;
+use crate::symbols::Symbols;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+/// The most data bytes written on one line of source
+const DATA_PER_LINE: usize = 8;
+
+/// What a byte of a program was found to be
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    /// Never reached by the traversal or referenced by an instruction
+    Unknown,
+    /// The first byte of a reachable instruction
+    Code,
+    /// The second byte of a reachable instruction
+    Operand,
+    /// The start of data an `ANNN` instruction points at
+    Data,
+}
+
+/// Why an address was given a label. Addresses with several reasons keep the first one listed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LabelKind {
+    /// Where execution starts
+    Entry,
+    /// The target of a `2NNN` call
+    Subroutine,
+    /// The base address of a `BNNN` jump table
+    Table,
+    /// The target of a `1NNN` jump
+    Branch,
+    /// The target of an `ANNN` load
+    Data,
+}
+
+impl LabelKind {
+    fn prefix(&self) -> &'static str {
+        match self {
+            LabelKind::Entry => "main",
+            LabelKind::Subroutine => "sub",
+            LabelKind::Table => "table",
+            LabelKind::Branch => "label",
+            LabelKind::Data => "data",
+        }
+    }
+}
+
+/// A program split into code and data by following its control flow from the entry point,
+/// rather than reading every pair of bytes as an instruction like `disasm::disassemble`.
+///
+/// Jumps, calls and both sides of skips are followed. Addresses loaded into `I` are taken to be
+/// data. `BNNN` jumps can land anywhere, so only the table's base and the run of `1NNN` jumps
+/// directly after it are followed. Anything not reached is kept as data, so the source written
+/// by `write_source` assembles back to the same bytes.
+#[derive(Clone, Debug)]
+pub struct Program {
+    origin: usize,
+    bytes: Vec<u8>,
+    kinds: Vec<Kind>,
+    labels: BTreeMap<usize, LabelKind>,
+    symbols: Symbols,
+}
+
+impl Program {
+    /// Traces a program from its first byte
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The program, e.g. the contents of a ROM file
+    /// * `origin` - The address the program is loaded and started at
+    /// * `coverage` - Addresses known to be executed, e.g. from `Coverage`, which are followed
+    ///   as well. They find code only reached through computed jumps.
+    pub fn analyze(bytes: &[u8], origin: usize, coverage: &[usize]) -> Self {
+        let mut program = Program {
+            origin,
+            bytes: bytes.to_vec(),
+            kinds: vec![Kind::Unknown; bytes.len()],
+            labels: BTreeMap::new(),
+            symbols: Symbols::new(),
+        };
+
+        program.labels.insert(origin, LabelKind::Entry);
+
+        let mut pending = vec![origin];
+        pending.extend(coverage.iter().rev());
+
+        while let Some(address) = pending.pop() {
+            let instruction = match program.claim(address) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            let next = address + 2;
+
+            match instruction {
+                Instruction::Ret => {}
+                Instruction::Jump(target) => {
+                    let target = target as usize;
+                    if target != address {
+                        program.label(target, LabelKind::Branch);
+                        pending.push(target);
+                    }
+                }
+                Instruction::Call(target) => {
+                    program.label(target as usize, LabelKind::Subroutine);
+                    pending.push(next);
+                    pending.push(target as usize);
+                }
+                Instruction::JumpV0(base) => {
+                    let base = base as usize;
+                    program.label(base, LabelKind::Table);
+
+                    let mut entry = base;
+                    while let Some(Instruction::Jump(_)) = program.decode(entry) {
+                        pending.push(entry);
+                        entry += 2;
+                    }
+                    pending.push(base);
+                }
+                // Empty memory, which the interpreter stops at
+                Instruction::Sys(0) => {}
+                skip if skip.is_skip() => {
+                    pending.push(next + 2);
+                    pending.push(next);
+                }
+                Instruction::LoadI(target) => {
+                    let target = target as usize;
+                    program.label(target, LabelKind::Data);
+                    if let Some(idx) = program.index(target) {
+                        if program.kinds[idx] == Kind::Unknown {
+                            program.kinds[idx] = Kind::Data;
+                        }
+                    }
+                    pending.push(next);
+                }
+                _ => pending.push(next),
+            }
+        }
+
+        // Labels can only be written before an instruction or data, not inside an instruction
+        let kinds = &program.kinds;
+        let origin = program.origin;
+        program.labels.retain(|address, _| {
+            address
+                .checked_sub(origin)
+                .and_then(|idx| kinds.get(idx))
+                .is_some_and(|kind| *kind != Kind::Operand)
+        });
+
+        program
+    }
+
+    /// Names the program's labels after symbols instead of their addresses. Symbols at the start
+    /// of an instruction or data the traversal found no other reason to label are labelled too.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbols` - The names of the program's addresses
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        for (address, _) in symbols.iter() {
+            match self.get_kind(address) {
+                Some(Kind::Code) => self.label(address, LabelKind::Branch),
+                Some(Kind::Unknown) | Some(Kind::Data) => self.label(address, LabelKind::Data),
+                _ => {}
+            }
+        }
+
+        self.symbols = symbols;
+    }
+
+    /// Returns what the byte at an address was found to be, or `None` outside the program
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the byte
+    pub fn get_kind(&self, address: usize) -> Option<Kind> {
+        self.index(address).map(|idx| self.kinds[idx])
+    }
+
+    /// Returns the labels of the program by address
+    pub fn get_labels(&self) -> BTreeMap<usize, String> {
+        self.labels
+            .keys()
+            .map(|address| (*address, self.label_name(*address).unwrap()))
+            .collect()
+    }
+
+    /// Writes the program as assembly source that assembles back to the same bytes. Code is
+    /// written as instructions with labels for their targets, and everything else as data.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where the source is written
+    /// * `syntax` - The syntax to write
+    pub fn write_source<W: Write>(&self, mut writer: W, syntax: Syntax) -> io::Result<()> {
+        let comment = match syntax {
+            Syntax::Cowgod => ";",
+            Syntax::Octo => "#",
+        };
+
+        if syntax == Syntax::Octo && self.origin != 0x200 {
+            writeln!(writer, ":org {:#05x}", self.origin)?;
+        }
+
+        let mut idx = 0;
+        while idx < self.bytes.len() {
+            let address = self.origin + idx;
+
+            if let Some(name) = self.label_name(address) {
+                match syntax {
+                    Syntax::Cowgod => writeln!(writer, "{}:", name)?,
+                    Syntax::Octo => writeln!(writer, ": {}", name)?,
+                }
+            }
+
+            if self.kinds[idx] == Kind::Code {
+                let op = (self.bytes[idx] as u16) << 8 | self.bytes[idx + 1] as u16;
+                let instruction = Instruction::decode(op).unwrap();
+                let target = self.label_name(op as usize & 0x0FFF);
+                let text = instruction.format(syntax, target.as_deref());
+
+                if instruction.encode() == op {
+                    writeln!(writer, "\t{:<24}{} {:#05x}", text, comment, address)?;
+                } else {
+                    // The interpreter ignores some bits of this opcode, which would be lost by
+                    // assembling the instruction, so the original bytes are kept
+                    let data = data_line(syntax, &self.bytes[idx..idx + 2]);
+                    writeln!(
+                        writer,
+                        "\t{:<24}{} {:#05x} {}",
+                        data, comment, address, text
+                    )?;
+                }
+
+                idx += 2;
+                continue;
+            }
+
+            // Data runs until the next code or label
+            let mut end = idx + 1;
+            while end < self.bytes.len()
+                && end - idx < DATA_PER_LINE
+                && self.kinds[end] != Kind::Code
+                && !self.labels.contains_key(&(self.origin + end))
+            {
+                end += 1;
+            }
+
+            let data = data_line(syntax, &self.bytes[idx..end]);
+            writeln!(writer, "\t{:<24}{} {:#05x}", data, comment, address)?;
+            idx = end;
+        }
+
+        writer.flush()
+    }
+
+    fn index(&self, address: usize) -> Option<usize> {
+        address
+            .checked_sub(self.origin)
+            .filter(|idx| *idx < self.bytes.len())
+    }
+
+    fn decode(&self, address: usize) -> Option<Instruction> {
+        let idx = self.index(address)?;
+        let low = *self.bytes.get(idx + 1)?;
+
+        Instruction::decode((self.bytes[idx] as u16) << 8 | low as u16)
+    }
+
+    /// Marks the instruction at an address as code, returning it if it was not already claimed
+    /// and does not overlap another instruction
+    fn claim(&mut self, address: usize) -> Option<Instruction> {
+        let instruction = self.decode(address)?;
+        let idx = self.index(address)?;
+
+        let free = |kind: Kind| kind == Kind::Unknown || kind == Kind::Data;
+        if !free(self.kinds[idx]) || !free(self.kinds[idx + 1]) {
+            return None;
+        }
+
+        self.kinds[idx] = Kind::Code;
+        self.kinds[idx + 1] = Kind::Operand;
+
+        Some(instruction)
+    }
+
+    fn label(&mut self, address: usize, kind: LabelKind) {
+        if self.index(address).is_some() {
+            let label = self.labels.entry(address).or_insert(kind);
+            *label = (*label).min(kind);
+        }
+    }
+
+    fn label_name(&self, address: usize) -> Option<String> {
+        let kind = self.labels.get(&address)?;
+        if let Some(name) = self.symbols.get_name(address) {
+            return Some(name.to_string());
+        }
+
+        Some(match kind {
+            LabelKind::Entry => kind.prefix().to_string(),
+            _ => format!("{}_{:03x}", kind.prefix(), address),
+        })
+    }
+}
+
+/// Formats bytes as a line of data
+fn data_line(syntax: Syntax, bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:#04x}", b)).collect();
+
+    match syntax {
+        Syntax::Cowgod => format!("DB {}", bytes.join(", ")),
+        Syntax::Octo => bytes.join(" "),
+    }
+}
+
+/// The addresses a program executed, used to guide `Program::analyze` to code that can only be
+/// found by running it. Its text form has one address per line, and `#` starts a comment.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Coverage {
+    addresses: BTreeSet<usize>,
+}
+
+impl Coverage {
+    /// Parses the text form of a coverage trace
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - One address per line, in decimal or `0x` prefixed hexadecimal
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut coverage = Self::default();
+
+        for (idx, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let address = parse_number(line)
+                .ok_or_else(|| format!("line {}: invalid address '{}'", idx + 1, line))?;
+            coverage.addresses.insert(address as usize);
+        }
+
+        Ok(coverage)
+    }
+
+    /// Records an executed address
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the instruction, e.g. `System::get_pc` before a tick
+    pub fn add(&mut self, address: usize) {
+        self.addresses.insert(address);
+    }
+
+    /// Returns the executed addresses, in ascending order
+    pub fn get_addresses(&self) -> Vec<usize> {
+        self.addresses.iter().copied().collect()
+    }
+
+    /// Writes the text form of the trace
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where the trace is written
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for address in self.addresses.iter() {
+            writeln!(writer, "{:#05x}", address)?;
+        }
+
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0x200: call 0x20A, 0x202: skip, 0x204: jump back, 0x206: jump 0x206 (halt),
+    // 0x208: sprite data, 0x20A: i := 0x208, 0x20C: return
+    const ROM: [u8; 14] = [
+        0x22, 0x0A, 0x30, 0x01, 0x12, 0x00, 0x12, 0x06, 0xF0, 0x90, 0xA2, 0x08, 0x00, 0xEE,
+    ];
+
+    #[test]
+    fn test_analyze() {
+        let program = Program::analyze(&ROM, 0x200, &[]);
+
+        assert_eq!(program.get_kind(0x200), Some(Kind::Code));
+        assert_eq!(program.get_kind(0x201), Some(Kind::Operand));
+        assert_eq!(program.get_kind(0x206), Some(Kind::Code));
+        assert_eq!(program.get_kind(0x208), Some(Kind::Data));
+        assert_eq!(program.get_kind(0x209), Some(Kind::Unknown));
+        assert_eq!(program.get_kind(0x20C), Some(Kind::Code));
+        assert_eq!(program.get_kind(0x20E), None);
+
+        let labels = program.get_labels();
+        assert_eq!(labels[&0x200], "main");
+        assert_eq!(labels[&0x208], "data_208");
+        assert_eq!(labels[&0x20A], "sub_20a");
+        assert_eq!(labels.len(), 3);
+    }
+
+    #[test]
+    fn test_write_source() {
+        let program = Program::analyze(&ROM, 0x200, &[]);
+        let mut source = vec![];
+        program.write_source(&mut source, Syntax::Octo).unwrap();
+        let source = String::from_utf8(source).unwrap();
+        let lines: Vec<&str> = source.lines().map(|line| line.trim_end()).collect();
+
+        let line = |text: &str, address: usize| format!("\t{:<24}# {:#05x}", text, address);
+
+        assert_eq!(lines[0], ": main");
+        assert_eq!(lines[1], line(":call sub_20a", 0x200));
+        assert_eq!(lines[2], line("if v0 != 0x01 then", 0x202));
+        assert_eq!(lines[3], line("jump main", 0x204));
+        assert_eq!(lines[5], ": data_208");
+        assert_eq!(lines[6], line("0xf0 0x90", 0x208));
+        assert_eq!(lines[8], line("i := data_208", 0x20A));
+
+        let mut program = Program::analyze(&ROM, 0x200, &[]);
+        program.set_symbols(Symbols::parse("0x20A draw\n0x209 sprite_row\n0x201 none").unwrap());
+        let labels = program.get_labels();
+        assert_eq!(labels[&0x20A], "draw");
+        assert_eq!(labels[&0x209], "sprite_row");
+        assert!(!labels.contains_key(&0x201));
+    }
+
+    #[test]
+    fn test_jump_table_and_coverage() {
+        // v0 := 2, jump0 0x206, two table entries, then code only reached through the table
+        let rom = [
+            0x60, 0x02, 0xB2, 0x06, 0x00, 0x00, 0x12, 0x0A, 0x12, 0x0C, 0x00, 0xEE, 0x00, 0xEE,
+            0x00, 0xE0,
+        ];
+        let program = Program::analyze(&rom, 0x200, &[]);
+
+        assert_eq!(program.get_labels()[&0x206], "table_206");
+        assert_eq!(program.get_kind(0x208), Some(Kind::Code));
+        assert_eq!(program.get_kind(0x20C), Some(Kind::Code));
+        assert_eq!(program.get_kind(0x20E), Some(Kind::Unknown));
+
+        let coverage = Coverage::parse("0x20E # reached at runtime\n").unwrap();
+        let program = Program::analyze(&rom, 0x200, &coverage.get_addresses());
+        assert_eq!(program.get_kind(0x20E), Some(Kind::Code));
+        assert!(Coverage::parse("pc").is_err());
+    }
+}
@@ -0,0 +1,626 @@
+use crate::breakpoints::{Access, Break, Watch, WatchKind};
+use crate::system::System;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+/// The size in bytes of each register in `g` packets: V0 to VF, I, PC, SP, DT and ST. Registers
+/// wider than a byte are little-endian.
+const REGISTER_SIZES: [usize; 21] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 1, 1, 1,
+];
+
+/// How many instructions `c` runs between checks for an interrupt from the debugger
+const INTERRUPT_INTERVAL: u64 = 1024;
+
+/// The target description sent to debuggers, naming the registers in the order of `g` packets
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// What to do after a packet was handled
+#[derive(Clone, Debug, PartialEq)]
+pub enum Response {
+    /// Send a reply, which may be empty for unsupported packets
+    Reply(String),
+    /// Send the reply, if any, then end the session
+    Close(Option<String>),
+}
+
+/// The protocol side of a GDB remote serial protocol stub, independent of the connection, see
+/// `serve`. Supports registers (`g`, `G`, `p`, `P`), memory (`m`, `M`), `s`, `c`, breakpoints
+/// (`Z0`, `Z1`), watchpoints (`Z2` to `Z4`), `?` and the target description.
+#[derive(Clone, Debug)]
+pub struct GdbStub {
+    /// The reply to `?`, describing why the target last stopped
+    stop: String,
+    no_ack: bool,
+}
+
+impl Default for GdbStub {
+    fn default() -> Self {
+        Self {
+            stop: String::from("S05"),
+            no_ack: false,
+        }
+    }
+}
+
+impl GdbStub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns whether the debugger turned off acknowledgements with `QStartNoAckMode`
+    pub fn is_no_ack(&self) -> bool {
+        self.no_ack
+    }
+
+    /// Handles a packet and returns what to reply
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The system being debugged
+    /// * `packet` - The packet's data, without the framing or checksum
+    /// * `interrupted` - Called while continuing, returns whether the debugger asked to stop
+    pub fn handle<F: FnMut() -> bool>(
+        &mut self,
+        system: &mut System,
+        packet: &str,
+        interrupted: F,
+    ) -> Response {
+        let reply = match packet.split_at(packet.len().min(1)) {
+            ("?", _) => self.stop.clone(),
+            ("g", "") => to_hex(&read_registers(system)),
+            ("G", data) => {
+                ok_or_error(from_hex(data).and_then(|bytes| write_registers(system, &bytes)))
+            }
+            ("p", register) => match usize::from_str_radix(register, 16) {
+                Ok(register) if register < REGISTER_SIZES.len() => {
+                    let offset: usize = REGISTER_SIZES[..register].iter().sum();
+                    to_hex(&read_registers(system)[offset..offset + REGISTER_SIZES[register]])
+                }
+                _ => String::from("E01"),
+            },
+            ("P", assignment) => ok_or_error(write_register(system, assignment)),
+            ("m", range) => match parse_range(range, system) {
+                Some((start, end)) => to_hex(&system.get_memory()[start..end]),
+                None => String::from("E01"),
+            },
+            ("M", write) => ok_or_error(write_memory(system, write)),
+            ("s", address) => {
+                if let Err(reply) = resume_at(system, address) {
+                    return Response::Reply(reply);
+                }
+                let hit = system.tick();
+                self.stop = stop_reply(hit);
+                self.stop.clone()
+            }
+            ("c", address) => {
+                if let Err(reply) = resume_at(system, address) {
+                    return Response::Reply(reply);
+                }
+                self.stop = run(system, interrupted);
+                self.stop.clone()
+            }
+            ("Z", breakpoint) => ok_or_error(set_breakpoint(system, breakpoint, true)),
+            ("z", breakpoint) => ok_or_error(set_breakpoint(system, breakpoint, false)),
+            ("H", _) | ("T", _) => String::from("OK"),
+            ("k", _) => return Response::Close(None),
+            ("D", _) => return Response::Close(Some(String::from("OK"))),
+            _ => self.query(packet),
+        };
+
+        Response::Reply(reply)
+    }
+
+    /// Handles the named `q`, `Q` and `v` packets
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return String::from("PacketSize=4000;qXfer:features:read+;QStartNoAckMode+");
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, len) = match range.split_once(',').and_then(|(offset, len)| {
+                Some((
+                    usize::from_str_radix(offset, 16).ok()?,
+                    usize::from_str_radix(len, 16).ok()?,
+                ))
+            }) {
+                Some(range) => range,
+                None => return String::from("E01"),
+            };
+            let start = offset.min(TARGET_XML.len());
+            let end = offset.saturating_add(len).min(TARGET_XML.len());
+            let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+
+            return format!("{}{}", more, &TARGET_XML[start..end]);
+        }
+
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                String::from("OK")
+            }
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+}
+
+fn ok_or_error(result: Option<()>) -> String {
+    match result {
+        Some(()) => String::from("OK"),
+        None => String::from("E01"),
+    }
+}
+
+/// Returns the registers in the order and sizes of `REGISTER_SIZES`
+fn read_registers(system: &System) -> Vec<u8> {
+    let mut bytes = system.get_v().to_vec();
+
+    bytes.extend_from_slice(&system.get_i().to_le_bytes());
+    bytes.extend_from_slice(&(system.get_pc() as u16).to_le_bytes());
+    bytes.extend_from_slice(&[
+        system.get_sp() as u8,
+        system.get_delay_timer(),
+        system.get_sound_timer(),
+    ]);
+
+    bytes
+}
+
+/// Sets every register from the bytes of a `G` packet, rejecting an invalid PC or SP
+fn write_registers(system: &mut System, bytes: &[u8]) -> Option<()> {
+    if bytes.len() != REGISTER_SIZES.iter().sum::<usize>() {
+        return None;
+    }
+
+    let pc = u16::from_le_bytes([bytes[18], bytes[19]]) as usize;
+    if pc >= system.get_memory().len() - 1 || bytes[20] >= 16 {
+        return None;
+    }
+
+    for (register, value) in bytes[..16].iter().enumerate() {
+        system.set_v(register, *value);
+    }
+    system.set_i(u16::from_le_bytes([bytes[16], bytes[17]]));
+    system.set_pc(pc);
+    system.set_sp(bytes[20] as usize);
+    system.set_delay_timer(bytes[21]);
+    system.set_sound_timer(bytes[22]);
+
+    Some(())
+}
+
+/// Sets one register from a `P` packet, written `n=value`
+fn write_register(system: &mut System, assignment: &str) -> Option<()> {
+    let (register, value) = assignment.split_once('=')?;
+    let register = usize::from_str_radix(register, 16).ok()?;
+    let value = from_hex(value)?;
+
+    if register >= REGISTER_SIZES.len() || value.len() != REGISTER_SIZES[register] {
+        return None;
+    }
+
+    let offset: usize = REGISTER_SIZES[..register].iter().sum();
+    let mut bytes = read_registers(system);
+    bytes[offset..offset + value.len()].copy_from_slice(&value);
+
+    write_registers(system, &bytes)
+}
+
+/// Parses the `addr,length` of an `m` packet into a range of memory, shortened to the end of
+/// memory
+fn parse_range(range: &str, system: &System) -> Option<(usize, usize)> {
+    let (start, len) = range.split_once(',')?;
+    let start = usize::from_str_radix(start, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    let memory = system.get_memory().len();
+
+    if start < memory {
+        Some((start, start.saturating_add(len).min(memory)))
+    } else {
+        None
+    }
+}
+
+/// Writes memory from an `M` packet, written `addr,length:data`
+fn write_memory(system: &mut System, write: &str) -> Option<()> {
+    let (range, data) = write.split_once(':')?;
+    let (start, end) = parse_range(range, system)?;
+    let bytes = from_hex(data)?;
+
+    if bytes.len() != end - start {
+        return None;
+    }
+
+    system.get_memory_mut()[start..end].copy_from_slice(&bytes);
+    Some(())
+}
+
+/// Moves the program counter to the optional address of an `s` or `c` packet
+fn resume_at(system: &mut System, address: &str) -> Result<(), String> {
+    if address.is_empty() {
+        return Ok(());
+    }
+
+    match usize::from_str_radix(address, 16) {
+        Ok(address) if address < system.get_memory().len() - 1 => {
+            system.set_pc(address);
+            Ok(())
+        }
+        _ => Err(String::from("E01")),
+    }
+}
+
+/// Adds or removes the breakpoint or watchpoint of a `Z` or `z` packet, written `type,addr,kind`
+fn set_breakpoint(system: &mut System, breakpoint: &str, add: bool) -> Option<()> {
+    let mut fields = breakpoint.split(',');
+    let kind = fields.next()?;
+    let address = usize::from_str_radix(fields.next()?, 16).ok()?;
+    let len = usize::from_str_radix(fields.next()?.split(';').next()?, 16).ok()?;
+
+    if address >= system.get_memory().len() {
+        return None;
+    }
+
+    let breakpoints = system.get_breakpoints_mut();
+    let watch = |kind| Watch {
+        start: address,
+        len: len.max(1),
+        kind,
+    };
+
+    let watch = match kind {
+        "0" | "1" => {
+            if add {
+                breakpoints.add_address(address);
+            } else {
+                breakpoints.remove_address(address);
+            }
+            return Some(());
+        }
+        "2" => watch(WatchKind::Write),
+        "3" => watch(WatchKind::Read),
+        "4" => watch(WatchKind::ReadWrite),
+        _ => return None,
+    };
+
+    if add {
+        breakpoints.add_watch(watch);
+    } else {
+        breakpoints.remove_watch_exact(watch);
+    }
+
+    Some(())
+}
+
+/// Runs until a breakpoint, the program halts or the debugger interrupts, returning the stop
+/// reply
+fn run<F: FnMut() -> bool>(system: &mut System, mut interrupted: F) -> String {
+    for ticks in 1.. {
+        if system.is_halted() {
+            break;
+        }
+
+        let hit = system.tick();
+        if hit.is_some() {
+            return stop_reply(hit);
+        }
+        if ticks % INTERRUPT_INTERVAL == 0 && interrupted() {
+            return String::from("S02");
+        }
+    }
+
+    String::from("S05")
+}
+
+/// Describes why the target stopped, as a `T` packet naming the watched address for
+/// watchpoints and a plain `SIGTRAP` otherwise
+fn stop_reply(hit: Option<Break>) -> String {
+    match hit {
+        Some(Break::Memory {
+            address,
+            access: Access::Write,
+            ..
+        }) => format!("T05watch:{:x};", address),
+        Some(Break::Memory {
+            address,
+            access: Access::Read,
+            ..
+        }) => format!("T05rwatch:{:x};", address),
+        _ => String::from("S05"),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(text.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+/// Serves one debugger connection until it detaches, kills the target or disconnects
+///
+/// # Arguments
+///
+/// * `stream` - The connection, e.g. accepted from `target remote localhost:PORT`
+/// * `system` - The system to debug
+pub fn serve(stream: TcpStream, system: &mut System) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut stub = GdbStub::new();
+
+    while let Some(packet) = read_packet(&mut reader, &mut writer, stub.is_no_ack())? {
+        let interrupted = || poll_interrupt(&mut reader).unwrap_or(true);
+
+        match stub.handle(system, &packet, interrupted) {
+            Response::Reply(reply) => write_packet(&mut writer, &reply)?,
+            Response::Close(reply) => {
+                if let Some(reply) = reply {
+                    write_packet(&mut writer, &reply)?;
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the next packet, acknowledging it unless acknowledgements are off. Acknowledgements
+/// and interrupts received while stopped are skipped. Returns `None` when the connection closes.
+fn read_packet<R: BufRead, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    no_ack: bool,
+) -> io::Result<Option<String>> {
+    loop {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        if byte[0] != b'$' {
+            continue;
+        }
+
+        let mut data = vec![];
+        reader.read_until(b'#', &mut data)?;
+        if data.pop() != Some(b'#') {
+            return Ok(None);
+        }
+
+        let mut checksum = [0; 2];
+        reader.read_exact(&mut checksum)?;
+        let valid = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+            == Some(data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)));
+
+        if !no_ack {
+            writer.write_all(if valid { b"+" } else { b"-" })?;
+        }
+        if valid {
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+}
+
+/// Writes a packet, escaping the characters that frame packets
+fn write_packet<W: Write>(writer: &mut W, data: &str) -> io::Result<()> {
+    let mut escaped = vec![];
+
+    for byte in data.bytes() {
+        match byte {
+            b'$' | b'#' | b'}' | b'*' => escaped.extend_from_slice(&[b'}', byte ^ 0x20]),
+            _ => escaped.push(byte),
+        }
+    }
+
+    let checksum = escaped
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+    writer.write_all(b"$")?;
+    writer.write_all(&escaped)?;
+    write!(writer, "#{:02x}", checksum)?;
+    writer.flush()
+}
+
+/// Returns whether the debugger sent an interrupt, without waiting for one. A closed connection
+/// counts as an interrupt so a running target stops.
+fn poll_interrupt(reader: &mut BufReader<TcpStream>) -> io::Result<bool> {
+    if reader.buffer().is_empty() {
+        reader.get_ref().set_nonblocking(true)?;
+        let filled = reader.fill_buf().map(|buffer| buffer.len());
+        reader.get_ref().set_nonblocking(false)?;
+
+        match filled {
+            Ok(0) => return Ok(true),
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            Err(err) => return Err(err),
+        }
+    }
+
+    while let Some(byte) = reader.buffer().first().copied() {
+        match byte {
+            b'+' | b'-' => reader.consume(1),
+            0x03 => {
+                reader.consume(1);
+                return Ok(true);
+            }
+            _ => break,
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn build_system(rom: Vec<u8>) -> System {
+        let mut system = System::new();
+        system.write_rom(rom);
+        system
+    }
+
+    fn reply(stub: &mut GdbStub, system: &mut System, packet: &str) -> String {
+        match stub.handle(system, packet, || false) {
+            Response::Reply(reply) => reply,
+            Response::Close(_) => panic!("unexpected close"),
+        }
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let mut system = build_system(vec![0x61, 0x20]);
+        let mut stub = GdbStub::new();
+        system.set_i(0x345);
+
+        assert_eq!(
+            reply(&mut stub, &mut system, "g"),
+            format!("{}45030002000000", "00".repeat(16))
+        );
+        assert_eq!(reply(&mut stub, &mut system, "p11"), "0002");
+        assert_eq!(reply(&mut stub, &mut system, "P3=7f"), "OK");
+        assert_eq!(system.get_v()[3], 0x7F);
+        assert_eq!(reply(&mut stub, &mut system, "P11=ff0f"), "E01");
+
+        let registers = format!("{}01000402010203", "11".repeat(16));
+        assert_eq!(
+            reply(&mut stub, &mut system, &format!("G{}", registers)),
+            "OK"
+        );
+        assert_eq!(system.get_pc(), 0x204);
+        assert_eq!(system.get_sp(), 1);
+        assert_eq!(system.get_sound_timer(), 3);
+
+        assert_eq!(reply(&mut stub, &mut system, "m200,2"), "6120");
+        assert_eq!(reply(&mut stub, &mut system, "mffe,4"), "0000");
+        assert_eq!(reply(&mut stub, &mut system, "m1000,1"), "E01");
+        assert_eq!(reply(&mut stub, &mut system, "M300,2:abcd"), "OK");
+        assert_eq!(&system.get_memory()[0x300..0x302], &[0xAB, 0xCD]);
+        assert_eq!(reply(&mut stub, &mut system, "M300,2:ab"), "E01");
+
+        assert!(reply(
+            &mut stub,
+            &mut system,
+            "qXfer:features:read:target.xml:0,40"
+        )
+        .starts_with("m<?xml"));
+        assert_eq!(reply(&mut stub, &mut system, "qUnknown"), "");
+    }
+
+    #[test]
+    fn test_breakpoints_and_watchpoints() {
+        // i := 0x300, v0 += 1, bcd v0, jump 0x202
+        let mut system = build_system(vec![0xA3, 0x00, 0x70, 0x01, 0xF0, 0x33, 0x12, 0x02]);
+        let mut stub = GdbStub::new();
+
+        assert_eq!(reply(&mut stub, &mut system, "s"), "S05");
+        assert_eq!(system.get_pc(), 0x202);
+
+        assert_eq!(reply(&mut stub, &mut system, "Z0,206,2"), "OK");
+        assert_eq!(reply(&mut stub, &mut system, "c"), "S05");
+        assert_eq!(system.get_pc(), 0x206);
+        assert_eq!(reply(&mut stub, &mut system, "z0,206,2"), "OK");
+
+        assert_eq!(reply(&mut stub, &mut system, "Z2,302,1"), "OK");
+        assert_eq!(reply(&mut stub, &mut system, "c"), "T05watch:302;");
+        assert_eq!(reply(&mut stub, &mut system, "?"), "T05watch:302;");
+        assert_eq!(system.get_pc(), 0x206);
+        assert_eq!(reply(&mut stub, &mut system, "z2,302,1"), "OK");
+        assert!(system.get_breakpoints().is_empty());
+
+        assert_eq!(reply(&mut stub, &mut system, "Z9,200,2"), "E01");
+    }
+
+    #[test]
+    fn test_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            // Two jumps between each other, which never halt
+            let mut system = build_system(vec![0x12, 0x02, 0x12, 0x00]);
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, &mut system).unwrap();
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut exchange = |packet: &str| {
+            write_packet(&mut writer, packet).unwrap();
+            let mut ack = [0];
+            reader.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+            read_packet(&mut reader, &mut Vec::new(), false)
+                .unwrap()
+                .unwrap()
+        };
+
+        assert!(exchange("qSupported:swbreak+").contains("qXfer:features:read+"));
+        assert_eq!(exchange("?"), "S05");
+        assert_eq!(exchange("m200,4"), "12021200");
+
+        write_packet(&mut writer, "c").unwrap();
+        writer.write_all(&[0x03]).unwrap();
+        let mut ack = [0];
+        reader.read_exact(&mut ack).unwrap();
+        assert_eq!(
+            read_packet(&mut reader, &mut Vec::new(), false).unwrap(),
+            Some(String::from("S02"))
+        );
+
+        write_packet(&mut writer, "D").unwrap();
+        reader.read_exact(&mut ack).unwrap();
+        assert_eq!(
+            read_packet(&mut reader, &mut Vec::new(), false).unwrap(),
+            Some(String::from("OK"))
+        );
+        server.join().unwrap();
+    }
+}
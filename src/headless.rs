@@ -0,0 +1,279 @@
+use crate::breakpoints::Break;
+use crate::system::{System, TICKS_PER_FRAME};
+
+use std::convert::TryFrom;
+
+/// Key presses to feed a system while it runs without a frontend. Each entry replaces the state
+/// of every key, starting at the given frame.
+///
+/// The text format has one `<frame> <mask>` pair per line, where the mask is a 16 bit number with
+/// bit `n` set while key `n` is held, written in decimal or with a `0x` prefix. Empty lines and
+/// anything after a `#` are ignored.
+///
+/// ```text
+/// # Hold key 5 for a second, then release it
+/// 0    0x0020
+/// 60   0x0000
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyScript {
+    entries: Vec<(u64, u16)>,
+}
+
+impl KeyScript {
+    /// Parses a key script
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The text of the script
+    pub fn parse(source: &str) -> Result<KeyScript, String> {
+        let mut entries = vec![];
+
+        for (idx, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |message: &str| format!("line {}: {}", idx + 1, message);
+            let mut fields = line.split_whitespace();
+
+            let frame = fields
+                .next()
+                .and_then(|frame| frame.parse::<u64>().ok())
+                .ok_or_else(|| error("expected a frame number"))?;
+            let mask = fields
+                .next()
+                .and_then(parse_number)
+                .and_then(|mask| u16::try_from(mask).ok())
+                .ok_or_else(|| error("expected a 16 bit key mask"))?;
+
+            if fields.next().is_some() {
+                return Err(error("unexpected text after the key mask"));
+            }
+
+            entries.push((frame, mask));
+        }
+
+        entries.sort_by_key(|(frame, _)| *frame);
+
+        Ok(KeyScript { entries })
+    }
+
+    /// Returns the key mask that takes effect at a frame, if the script changes keys there
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - The frame about to be run, starting at `0`
+    pub fn mask_at(&self, frame: u64) -> Option<u16> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(start, _)| *start == frame)
+            .map(|(_, mask)| *mask)
+    }
+}
+
+/// Parses an unsigned number written in decimal or with a `0x` prefix
+///
+/// # Arguments
+///
+/// * `value` - The number to parse
+pub fn parse_number(value: &str) -> Option<u32> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Why a headless run stopped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    /// The frame limit was reached
+    Frames,
+    /// The program counter reached the requested address
+    Pc,
+    /// The program stopped making progress, see `System::is_halted`
+    Halt,
+    /// A breakpoint or watchpoint set on the system was hit, see `System::tick`
+    Break(Break),
+}
+
+impl StopReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StopReason::Frames => "frames",
+            StopReason::Pc => "pc",
+            StopReason::Halt => "halt",
+            StopReason::Break(_) => "break",
+        }
+    }
+}
+
+/// Runs a system without a frontend until a frame limit or a stop condition is reached
+#[derive(Clone, Debug, Default)]
+pub struct Runner {
+    pub max_frames: u64,
+    pub until_pc: Option<usize>,
+    pub until_halt: bool,
+    pub keys: KeyScript,
+    /// Addresses to stop at, added to the system's breakpoints when a run starts
+    pub breakpoints: Vec<usize>,
+}
+
+impl Runner {
+    /// Runs the system and returns why it stopped along with the number of frames completed.
+    /// Stop conditions are checked before every instruction, and the run also stops at the
+    /// system's breakpoints and watchpoints.
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The system to run
+    /// * `on_frame` - Called with the system after every completed frame
+    pub fn run<F: FnMut(&System)>(&self, system: &mut System, on_frame: F) -> (StopReason, u64) {
+        self.run_traced(system, |_| {}, on_frame)
+    }
+
+    /// Runs the system like `run`, also calling `on_tick` before every instruction
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The system to run
+    /// * `on_tick` - Called with the system before every instruction, e.g. to record coverage
+    /// * `on_frame` - Called with the system after every completed frame
+    pub fn run_traced<T: FnMut(&System), F: FnMut(&System)>(
+        &self,
+        system: &mut System,
+        mut on_tick: T,
+        mut on_frame: F,
+    ) -> (StopReason, u64) {
+        for address in self.breakpoints.iter() {
+            system.get_breakpoints_mut().add_address(*address);
+        }
+        // `tick` checks the instruction after the one it runs, so the first is checked here
+        if let Some(hit) = system.check_breakpoints() {
+            return (StopReason::Break(hit), 0);
+        }
+
+        for frame in 0..self.max_frames {
+            if let Some(mask) = self.keys.mask_at(frame) {
+                system.set_keys(mask);
+            }
+
+            for _ in 0..TICKS_PER_FRAME {
+                if let Some(reason) = self.check(system) {
+                    return (reason, frame);
+                }
+
+                on_tick(system);
+                if let Some(hit) = system.tick() {
+                    return (StopReason::Break(hit), frame);
+                }
+            }
+
+            on_frame(system);
+        }
+
+        match self.check(system) {
+            Some(reason) => (reason, self.max_frames),
+            None => (StopReason::Frames, self.max_frames),
+        }
+    }
+
+    fn check(&self, system: &System) -> Option<StopReason> {
+        if self.until_pc == Some(system.get_pc()) {
+            Some(StopReason::Pc)
+        } else if self.until_halt && system.is_halted() {
+            Some(StopReason::Halt)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_script() {
+        let script = KeyScript::parse("# comment\n\n60 0x0\n0 0x0020 # hold 5\n").unwrap();
+
+        assert_eq!(script.mask_at(0), Some(0x0020));
+        assert_eq!(script.mask_at(30), None);
+        assert_eq!(script.mask_at(60), Some(0x0000));
+        assert!(KeyScript::parse("0 0x10000").is_err());
+        assert!(KeyScript::parse("x 0x1").is_err());
+    }
+
+    #[test]
+    fn test_run_until_pc() {
+        let mut system = System::new();
+        system.write_rom(vec![0x60, 0x01, 0x61, 0x02, 0x12, 0x04]);
+
+        let runner = Runner {
+            max_frames: 10,
+            until_pc: Some(0x204),
+            ..Runner::default()
+        };
+
+        assert_eq!(runner.run(&mut system, |_| {}), (StopReason::Pc, 0));
+        assert_eq!(system.get_v()[1], 0x02);
+    }
+
+    #[test]
+    fn test_run_until_halt() {
+        let mut system = System::new();
+        system.write_rom(vec![0x12, 0x00]);
+
+        let runner = Runner {
+            max_frames: 10,
+            until_halt: true,
+            ..Runner::default()
+        };
+        let mut frames = 0;
+
+        assert_eq!(
+            runner.run(&mut system, |_| frames += 1),
+            (StopReason::Halt, 0)
+        );
+        assert_eq!(frames, 0);
+    }
+
+    #[test]
+    fn test_run_until_break() {
+        let mut system = System::new();
+        system.write_rom(vec![0x60, 0x01, 0x61, 0x02, 0x12, 0x04]);
+
+        let runner = Runner {
+            max_frames: 10,
+            breakpoints: vec![0x202],
+            ..Runner::default()
+        };
+
+        assert_eq!(
+            runner.run(&mut system, |_| {}),
+            (StopReason::Break(Break::Address(0x202)), 0)
+        );
+        assert_eq!((system.get_v()[0], system.get_v()[1]), (0x01, 0x00));
+
+        // A breakpoint on the first instruction stops before it runs
+        let mut system = System::new();
+        system.write_rom(vec![0x60, 0x01, 0x12, 0x02]);
+
+        let runner = Runner {
+            max_frames: 10,
+            breakpoints: vec![0x200],
+            ..Runner::default()
+        };
+
+        assert_eq!(
+            runner.run(&mut system, |_| {}),
+            (StopReason::Break(Break::Address(0x200)), 0)
+        );
+        assert_eq!(system.get_v()[0], 0x00);
+    }
+}
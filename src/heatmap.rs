@@ -0,0 +1,322 @@
+use crate::breakpoints::Access;
+use crate::osd::{self, Canvas, FRAME_HEIGHT};
+use crate::palette::Palette;
+use crate::screenshot::{encode_png, Image};
+
+use std::io::{self, Write};
+
+/// The number of addresses counted, the whole of memory
+pub const HEATMAP_SIZE: usize = 4096;
+
+/// The width of the heatmap panel drawn beside the game, in overlay pixels. Every address is a
+/// pixel, in rows of `PANEL_WIDTH` addresses.
+pub const PANEL_WIDTH: usize = HEATMAP_SIZE / FRAME_HEIGHT;
+
+/// How many bytes a row of the hex view shows
+const ROW: usize = 16;
+
+/// How many image pixels make up a pixel of the glyphs and heatmap cells of an exported image
+const IMAGE_SCALE: usize = 2;
+
+/// The color of addresses that were never accessed
+const COLD: [u8; 3] = [0x10, 0x10, 0x10];
+
+/// The colors of the addresses and untouched bytes in the hex view of an exported image
+const LABEL: [u8; 3] = [0xC0, 0xC0, 0xC0];
+const UNTOUCHED: [u8; 3] = [0x60, 0x60, 0x60];
+
+/// Counts the reads, writes and executions of every address of memory, see
+/// `System::set_heatmap`. Instruction fetches count as executions, and the memory accessed by
+/// `DXYN`, `FX33`, `FX55` and `FX65` as reads or writes.
+///
+/// Counts are multiplied by a decay factor every frame, so with a factor below `1.0` the heatmap
+/// shows recent activity and cools down where the program stopped looking.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heatmap {
+    reads: Vec<f32>,
+    writes: Vec<f32>,
+    executes: Vec<f32>,
+    decay: f32,
+}
+
+impl Heatmap {
+    /// Creates an empty heatmap
+    ///
+    /// # Arguments
+    ///
+    /// * `decay` - The factor counts are multiplied by every frame, `1.0` to keep them
+    pub fn new(decay: f32) -> Self {
+        Self {
+            reads: vec![0.0; HEATMAP_SIZE],
+            writes: vec![0.0; HEATMAP_SIZE],
+            executes: vec![0.0; HEATMAP_SIZE],
+            decay,
+        }
+    }
+
+    /// Counts an access to a range of memory
+    ///
+    /// # Arguments
+    ///
+    /// * `access` - Whether the memory was read or written
+    /// * `start` - The first address accessed
+    /// * `len` - The number of bytes accessed
+    pub fn record(&mut self, access: Access, start: usize, len: usize) {
+        let counts = match access {
+            Access::Read => &mut self.reads,
+            Access::Write => &mut self.writes,
+        };
+
+        for address in start..start + len {
+            if let Some(count) = counts.get_mut(address) {
+                *count += 1.0;
+            }
+        }
+    }
+
+    /// Counts the fetch of the instruction at an address
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the instruction's first byte
+    pub fn record_execute(&mut self, address: usize) {
+        for address in address..address + 2 {
+            if let Some(count) = self.executes.get_mut(address) {
+                *count += 1.0;
+            }
+        }
+    }
+
+    /// Applies a frame's decay to every count
+    pub fn decay(&mut self) {
+        if self.decay >= 1.0 {
+            return;
+        }
+
+        for counts in [&mut self.reads, &mut self.writes, &mut self.executes].iter_mut() {
+            for count in counts.iter_mut() {
+                *count *= self.decay;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new(self.decay);
+    }
+
+    /// Returns the read, write and execute counts of an address, after decay
+    pub fn get(&self, address: usize) -> (f32, f32, f32) {
+        (
+            self.reads[address],
+            self.writes[address],
+            self.executes[address],
+        )
+    }
+
+    /// Returns the color of an address: green for reads, red for writes and blue for executions,
+    /// each brighter the more it was accessed compared to the hottest address
+    pub fn color(&self, address: usize) -> [u8; 3] {
+        self.color_scaled(address, self.get_max())
+    }
+
+    /// Returns the highest read, write and execute counts
+    fn get_max(&self) -> (f32, f32, f32) {
+        let max = |counts: &[f32]| counts.iter().copied().fold(0.0, f32::max);
+
+        (max(&self.reads), max(&self.writes), max(&self.executes))
+    }
+
+    /// Returns the color of an address, given the highest counts from `get_max`
+    fn color_scaled(&self, address: usize, max: (f32, f32, f32)) -> [u8; 3] {
+        let (reads, writes, executes) = self.get(address);
+        if reads + writes + executes < 0.5 {
+            return COLD;
+        }
+
+        let heat = |count: f32, max: f32| {
+            if count < 0.5 {
+                return 0;
+            }
+            // Logarithmic, so data touched once still shows next to a hot loop
+            let heat = (1.0 + count).ln() / (1.0 + max).ln();
+            (0x40 as f32 + heat * 0xBF as f32) as u8
+        };
+
+        [
+            heat(writes, max.1),
+            heat(reads, max.0),
+            heat(executes, max.2),
+        ]
+    }
+
+    /// Returns how an address was used, for the hex view: `X` executed, `W` written, `R` read,
+    /// `.` untouched, by that precedence
+    fn usage(&self, address: usize) -> char {
+        let (reads, writes, executes) = self.get(address);
+
+        if executes >= 0.5 {
+            'X'
+        } else if writes >= 0.5 {
+            'W'
+        } else if reads >= 0.5 {
+            'R'
+        } else {
+            '.'
+        }
+    }
+
+    /// Writes a hex view of memory, with how each byte was used after every row, e.g.
+    /// `0x200  60 01 ...  XXXX....RRRRR...`. Rows of untouched zeroes are left out.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where to write the view
+    /// * `memory` - The memory the heatmap counted, see `System::get_memory`
+    pub fn write_hex<W: Write>(&self, mut writer: W, memory: &[u8]) -> io::Result<()> {
+        for (idx, row) in memory.chunks(ROW).enumerate() {
+            let start = idx * ROW;
+            let usage: String = (start..start + row.len())
+                .map(|address| self.usage(address))
+                .collect();
+
+            if usage.chars().all(|c| c == '.') && row.iter().all(|b| *b == 0) {
+                continue;
+            }
+
+            let bytes: Vec<String> = row.iter().map(|b| format!("{:02X}", b)).collect();
+            writeln!(writer, "{:#05x}  {}  {}", start, bytes.join(" "), usage)?;
+        }
+
+        writer.flush()
+    }
+
+    /// Renders memory as an image, one row of the hex view per line: the heatmap cells of the
+    /// row's bytes followed by the address and the bytes in the color of their heat. Returns the
+    /// width, height and RGB pixels.
+    ///
+    /// # Arguments
+    ///
+    /// * `memory` - The memory the heatmap counted, see `System::get_memory`
+    pub fn render(&self, memory: &[u8]) -> (usize, usize, Vec<u8>) {
+        let line = osd::GLYPH_HEIGHT + 2;
+        let rows = memory.len().div_ceil(ROW);
+        let max = self.get_max();
+        // Cells, then `000:` and the bytes with a space before each
+        let columns = ROW * line + osd::GLYPH_ADVANCE * (6 + ROW * 3 + 1);
+        let mut image = Image::new(columns, rows * line, IMAGE_SCALE, [0; 3]);
+
+        for (idx, bytes) in memory.chunks(ROW).enumerate() {
+            let start = idx * ROW;
+            let y = idx * line;
+
+            image.text(ROW * line + 1, y + 1, &format!("{:03X}:", start), 1, LABEL);
+
+            for (offset, byte) in bytes.iter().enumerate() {
+                let color = self.color_scaled(start + offset, max);
+                image.fill(offset * line, y, line - 1, line - 1, color);
+
+                // Untouched bytes are dimmed so the accessed ones stand out in the hex view
+                let text = if color == COLD { UNTOUCHED } else { color };
+                let x = ROW * line + osd::GLYPH_ADVANCE * (7 + offset * 3);
+                image.text(x, y + 1, &format!("{:02X}", byte), 1, text);
+            }
+        }
+
+        (image.width, image.height, image.rgb)
+    }
+
+    /// Writes the image drawn by `render` as a PNG
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where to write the PNG
+    /// * `memory` - The memory the heatmap counted, see `System::get_memory`
+    pub fn write_png<W: Write>(&self, writer: W, memory: &[u8]) -> io::Result<()> {
+        let (width, height, rgb) = self.render(memory);
+
+        encode_png(writer, width, height, &rgb)
+    }
+}
+
+/// Draws the heatmap panel beside the game, one pixel per address in rows of `PANEL_WIDTH`
+///
+/// # Arguments
+///
+/// * `frame` - The RGBA frame, `FRAME_HEIGHT` tall and wide enough for the panel
+/// * `palette` - The palette of the frame
+/// * `heatmap` - The heatmap to draw
+/// * `left` - The column the panel starts at
+pub fn draw(frame: &mut [u8], palette: &Palette, heatmap: &Heatmap, left: usize) {
+    let mut canvas = Canvas::new(frame, palette);
+    let max = heatmap.get_max();
+
+    for address in 0..HEATMAP_SIZE {
+        let (x, y) = (address % PANEL_WIDTH, address / PANEL_WIDTH);
+        canvas.set_color(left + x, y, heatmap.color_scaled(address, max));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::System;
+
+    #[test]
+    fn test_counts() {
+        let mut system = System::new();
+        // v0 = 0x05, i = 0x300, store bcd, load registers from i, jump to self
+        system.write_rom(vec![
+            0x60, 0x05, 0xA3, 0x00, 0xF0, 0x33, 0xF2, 0x65, 0x12, 0x08,
+        ]);
+        system.set_heatmap(Some(Heatmap::new(1.0)));
+
+        for _ in 0..6 {
+            system.tick();
+        }
+
+        let heatmap = system.get_heatmap().unwrap();
+        assert_eq!(heatmap.get(0x200), (0.0, 0.0, 1.0));
+        assert_eq!(heatmap.get(0x209), (0.0, 0.0, 2.0));
+        assert_eq!(heatmap.get(0x300), (1.0, 1.0, 0.0));
+        assert_eq!(heatmap.get(0x302), (0.0, 1.0, 0.0));
+        assert_eq!(heatmap.get(0x303), (0.0, 0.0, 0.0));
+        assert_eq!(heatmap.color(0x303), COLD);
+        assert_eq!(heatmap.color(0x208)[2], 0xFF);
+
+        let mut hex = vec![];
+        heatmap.write_hex(&mut hex, system.get_memory()).unwrap();
+        let hex = String::from_utf8(hex).unwrap();
+        assert!(hex.contains(
+            "0x200  60 05 A3 00 F0 33 F2 65 12 08 00 00 00 00 00 00  XXXXXXXXXX......\n"
+        ));
+        assert!(hex.contains(
+            "0x300  00 00 05 00 00 00 00 00 00 00 00 00 00 00 00 00  WWW.............\n"
+        ));
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut heatmap = Heatmap::new(0.5);
+        heatmap.record(Access::Write, 0x200, 2);
+        heatmap.record(Access::Write, 0x200, 1);
+        heatmap.decay();
+
+        assert_eq!(heatmap.get(0x200), (0.0, 1.0, 0.0));
+        assert_eq!(heatmap.get(0x201), (0.0, 0.5, 0.0));
+
+        // Accesses past the end of memory are ignored
+        heatmap.record(Access::Read, HEATMAP_SIZE - 1, 4);
+        heatmap.clear();
+        assert_eq!(heatmap.get(0x200), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_render() {
+        let heatmap = Heatmap::new(1.0);
+        let (width, height, rgb) = heatmap.render(&[0; 64]);
+
+        assert_eq!(height, 4 * 7 * IMAGE_SCALE);
+        assert_eq!(rgb.len(), width * height * 3);
+        assert_eq!(&rgb[..3], &COLD);
+    }
+}
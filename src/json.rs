@@ -0,0 +1,379 @@
+use std::fmt;
+
+/// A JSON value, enough to read and write the messages of protocols like DAP. Objects keep their
+/// keys in order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parses a JSON document
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The document
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            next: 0,
+        };
+        let value = parser.value()?;
+
+        parser.skip_whitespace();
+        match parser.chars.get(parser.next) {
+            Some(c) => Err(format!("unexpected '{}' after the value", c)),
+            None => Ok(value),
+        }
+    }
+
+    /// Builds an object from its fields
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - The keys and values, in order
+    pub fn object<K: Into<String>>(fields: Vec<(K, Json)>) -> Json {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+
+    /// Returns the value of a field, if this is an object that has it
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a whole, non-negative number
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(value) if *value >= 0.0 && value.fract() == 0.0 => Some(*value as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(value: u64) -> Self {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(values: Vec<Json>) -> Self {
+        Json::Array(values)
+    }
+}
+
+/// Writes the value on one line, without spaces
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) if value.fract() == 0.0 && value.abs() < 1e15 => {
+                write!(f, "{}", *value as i64)
+            }
+            Json::Number(value) if value.is_finite() => write!(f, "{}", value),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(text) => write_string(f, text),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (idx, value) in values.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (idx, (key, value)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+
+    write!(f, "\"")
+}
+
+struct Parser {
+    chars: Vec<char>,
+    next: usize,
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .get(self.next)
+            .is_some_and(|c| c.is_ascii_whitespace())
+        {
+            self.next += 1;
+        }
+    }
+
+    fn take(&mut self) -> Result<char, String> {
+        let c = self
+            .chars
+            .get(self.next)
+            .copied()
+            .ok_or_else(|| String::from("unexpected end of JSON"))?;
+        self.next += 1;
+
+        Ok(c)
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        for expected in word.chars() {
+            if self.take()? != expected {
+                return Err(format!("expected '{}'", word));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+
+        match self.chars.get(self.next).copied() {
+            Some('n') => self.expect("null").map(|_| Json::Null),
+            Some('t') => self.expect("true").map(|_| Json::Bool(true)),
+            Some('f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => {
+                self.next += 1;
+                let mut values = vec![];
+
+                self.skip_whitespace();
+                if self.chars.get(self.next) == Some(&']') {
+                    self.next += 1;
+                    return Ok(Json::Array(values));
+                }
+
+                loop {
+                    values.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.take()? {
+                        ',' => {}
+                        ']' => return Ok(Json::Array(values)),
+                        c => return Err(format!("expected ',' or ']', found '{}'", c)),
+                    }
+                }
+            }
+            Some('{') => {
+                self.next += 1;
+                let mut fields = vec![];
+
+                self.skip_whitespace();
+                if self.chars.get(self.next) == Some(&'}') {
+                    self.next += 1;
+                    return Ok(Json::Object(fields));
+                }
+
+                loop {
+                    self.skip_whitespace();
+                    if self.chars.get(self.next) != Some(&'"') {
+                        return Err(String::from("expected a string key"));
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(":")?;
+                    fields.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.take()? {
+                        ',' => {}
+                        '}' => return Ok(Json::Object(fields)),
+                        c => return Err(format!("expected ',' or '}}', found '{}'", c)),
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let start = self.next;
+                while self
+                    .chars
+                    .get(self.next)
+                    .is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(*c))
+                {
+                    self.next += 1;
+                }
+
+                let number: String = self.chars[start..self.next].iter().collect();
+                number
+                    .parse()
+                    .map(Json::Number)
+                    .map_err(|_| format!("invalid number '{}'", number))
+            }
+            Some(c) => Err(format!("unexpected '{}'", c)),
+            None => Err(String::from("unexpected end of JSON")),
+        }
+    }
+
+    /// Parses a string, starting at its opening quote
+    fn string(&mut self) -> Result<String, String> {
+        let mut text = String::new();
+        self.next += 1;
+
+        loop {
+            match self.take()? {
+                '"' => return Ok(text),
+                '\\' => match self.take()? {
+                    'n' => text.push('\n'),
+                    'r' => text.push('\r'),
+                    't' => text.push('\t'),
+                    'b' => text.push('\u{8}'),
+                    'f' => text.push('\u{c}'),
+                    'u' => {
+                        let mut code = self.hex4()?;
+                        // Characters outside the basic plane come as a surrogate pair. A high
+                        // surrogate without its low one is replaced, and whatever follows it is
+                        // read on its own.
+                        if (0xD800..0xDC00).contains(&code)
+                            && self.chars[self.next..].starts_with(&['\\', 'u'])
+                        {
+                            self.next += 2;
+                            let low = self.hex4()?;
+                            if (0xDC00..0xE000).contains(&low) {
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            } else {
+                                self.next -= 6;
+                            }
+                        }
+                        text.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
+                    c => text.push(c),
+                },
+                c => text.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits: String = (0..4).map(|_| self.take()).collect::<Result<_, _>>()?;
+
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("invalid escape '\\u{}'", digits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = r#"{"seq":1,"type":"request","arguments":{"lines":[1,-2.5,true,null],"path":"a\"b\\c\n"}}"#;
+        let json = Json::parse(text).unwrap();
+
+        assert_eq!(json.to_string(), text);
+        assert_eq!(json.get("seq").and_then(Json::as_u64), Some(1));
+        assert_eq!(
+            json.get("arguments")
+                .and_then(|arguments| arguments.get("path"))
+                .and_then(Json::as_str),
+            Some("a\"b\\c\n")
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let json =
+            Json::parse(" { \"a\" : [ ] , \"b\" : { } , \"c\": \"\\u00e9\\ud83d\\ude00\" } ")
+                .unwrap();
+
+        assert_eq!(json.get("a"), Some(&Json::Array(vec![])));
+        assert_eq!(json.get("b"), Some(&Json::Object(vec![])));
+        assert_eq!(json.get("c").and_then(Json::as_str), Some("é😀"));
+
+        assert!(Json::parse("{\"a\" 1}").is_err());
+        assert_eq!(
+            Json::parse("\"x\\ud83d\\u0041\\ud83d\"").unwrap(),
+            Json::String(String::from("x\u{FFFD}A\u{FFFD}"))
+        );
+
+        assert!(Json::parse("[1,]").is_err());
+        assert!(Json::parse("1 2").is_err());
+        assert!(Json::parse("\"open").is_err());
+    }
+}
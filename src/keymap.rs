@@ -0,0 +1,124 @@
+use std::fmt;
+
+/// The layouts `Keymap::next` switches between: the hex digits themselves, and the 4x4 block
+/// below `1`-`4` on a QWERTY keyboard, laid out like the COSMAC VIP's keypad
+const LAYOUTS: [&str; 2] = ["0123456789abcdef", "x123qweasdzc4rfv"];
+
+/// Maps keyboard characters onto the 16 keys of the CHIP-8 hex keypad. Every frontend translates
+/// its own key events through the same map so layouts stay consistent between them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keymap([char; 16]);
+
+impl Keymap {
+    /// Parses a keymap from the 16 characters bound to keys `0x0` through `0xF`, in order
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - 16 distinct ASCII letters or digits, e.g. `x123qweasdzc4rfv`
+    pub fn parse(value: &str) -> Option<Keymap> {
+        let mut keys = [' '; 16];
+        let mut count = 0;
+
+        for c in value.chars().map(|c| c.to_ascii_lowercase()) {
+            if count == 16 || !c.is_ascii_alphanumeric() || keys[..count].contains(&c) {
+                return None;
+            }
+
+            keys[count] = c;
+            count += 1;
+        }
+
+        if count == 16 {
+            Some(Keymap(keys))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the character bound to a CHIP-8 key
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The CHIP-8 key, from `0x0` to `0xF`
+    pub fn char_for(&self, key: u8) -> char {
+        self.0[key as usize]
+    }
+
+    /// Returns the CHIP-8 key bound to a character, if any
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - The character typed on the keyboard
+    pub fn key_for(&self, c: char) -> Option<u8> {
+        let c = c.to_ascii_lowercase();
+
+        self.0
+            .iter()
+            .position(|bound| *bound == c)
+            .map(|key| key as u8)
+    }
+}
+
+impl Keymap {
+    /// Returns the built-in layout after this one, or the first for a custom keymap
+    pub fn next(&self) -> Keymap {
+        let current = LAYOUTS
+            .iter()
+            .position(|layout| Keymap::parse(layout) == Some(*self));
+        let next = current.map_or(0, |idx| (idx + 1) % LAYOUTS.len());
+
+        Keymap::parse(LAYOUTS[next]).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Keymap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|c| write!(f, "{}", c))
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap([
+            '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f',
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_keymap() {
+        let keymap = Keymap::default();
+
+        assert_eq!(keymap.key_for('7'), Some(0x7));
+        assert_eq!(keymap.key_for('B'), Some(0xB));
+        assert_eq!(keymap.key_for('g'), None);
+        assert_eq!(keymap.char_for(0xF), 'f');
+    }
+
+    #[test]
+    fn test_parse_keymap() {
+        let keymap = Keymap::parse("x123qweasdzc4rfv").unwrap();
+
+        assert_eq!(keymap.key_for('x'), Some(0x0));
+        assert_eq!(keymap.key_for('v'), Some(0xF));
+        assert!(Keymap::parse("x123qweasdzc4rf").is_none());
+        assert!(Keymap::parse("x123qweasdzc4rfx").is_none());
+        assert!(Keymap::parse("x123qweasdzc4rf!").is_none());
+    }
+
+    #[test]
+    fn test_next_keymap() {
+        let qwerty = Keymap::default().next();
+
+        assert_eq!(qwerty.to_string(), "x123qweasdzc4rfv");
+        assert_eq!(qwerty.next(), Keymap::default());
+        assert_eq!(
+            Keymap::parse("abcdefghijklmnop").unwrap().next(),
+            Keymap::default()
+        );
+    }
+}
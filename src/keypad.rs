@@ -0,0 +1,104 @@
+use crate::osd::{Canvas, FRAME_HEIGHT, FRAME_WIDTH, KEYPAD};
+use crate::palette::Palette;
+
+/// The width of the keypad panel drawn to the right of the game, in overlay pixels. Frames with
+/// the panel are `FRAME_WIDTH + PANEL_WIDTH` wide.
+pub const PANEL_WIDTH: usize = FRAME_HEIGHT;
+
+/// The size of a key on the panel, including the gap around it
+const CELL: usize = PANEL_WIDTH / 4;
+
+/// Returns the CHIP-8 key under a position in a frame with the panel, if any
+///
+/// # Arguments
+///
+/// * `x` - The column within the frame, where the panel starts at `FRAME_WIDTH`
+/// * `y` - The row within the frame
+pub fn key_at(x: usize, y: usize) -> Option<u8> {
+    let x = x.checked_sub(FRAME_WIDTH)?;
+
+    if x >= PANEL_WIDTH || y >= FRAME_HEIGHT {
+        return None;
+    }
+
+    Some(KEYPAD[(y / CELL) * 4 + (x / CELL)] as u8)
+}
+
+/// Draws the keypad panel to the right of the game, laid out like the COSMAC VIP keypad. Held
+/// keys are filled in, and keys the game polled during the last frame are shaded halfway between
+/// the palette's colors, which shows which keys a game is listening to.
+///
+/// # Arguments
+///
+/// * `frame` - The RGBA frame, `FRAME_WIDTH + PANEL_WIDTH` wide and `FRAME_HEIGHT` tall
+/// * `palette` - The colors to draw with
+/// * `held` - The held keys, with bit `n` set while key `n` is held
+/// * `polled` - The keys the game polled, as returned by `System::get_polled_keys`
+pub fn draw(frame: &mut [u8], palette: &Palette, held: u16, polled: u16) {
+    let mut canvas = Canvas::new(frame, palette);
+    let shade = mix(palette.background, palette.foreground);
+
+    canvas.fill(FRAME_WIDTH, 0, PANEL_WIDTH, FRAME_HEIGHT, false);
+
+    for (idx, key) in KEYPAD.iter().enumerate() {
+        let x = FRAME_WIDTH + (idx % 4) * CELL + 1;
+        let y = (idx / 4) * CELL + 1;
+        let size = CELL - 2;
+        let held = held & (1 << key) != 0;
+
+        // Held keys are solid, others are outlined
+        canvas.fill(x, y, size, size, true);
+        if !held {
+            let inside = if polled & (1 << key) != 0 {
+                shade
+            } else {
+                palette.background
+            };
+            canvas.fill_color(x + 1, y + 1, size - 2, size - 2, inside);
+        }
+
+        let digit = std::char::from_digit(*key as u32, 16).unwrap_or(' ');
+        canvas.glyph(x + (size - 4) / 2, y + (size - 5) / 2, digit, !held);
+    }
+}
+
+/// Returns the color halfway between two colors
+fn mix(a: [u8; 3], b: [u8; 3]) -> [u8; 3] {
+    let channel = |idx: usize| ((a[idx] as u16 + b[idx] as u16) / 2) as u8;
+
+    [channel(0), channel(1), channel(2)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::COLOR_WIDTH;
+
+    #[test]
+    fn test_key_at() {
+        assert_eq!(key_at(FRAME_WIDTH - 1, 0), None);
+        assert_eq!(key_at(FRAME_WIDTH, 0), Some(0x1));
+        assert_eq!(key_at(FRAME_WIDTH + CELL, CELL), Some(0x5));
+        assert_eq!(
+            key_at(FRAME_WIDTH + PANEL_WIDTH - 1, FRAME_HEIGHT - 1),
+            Some(0xF)
+        );
+        assert_eq!(key_at(FRAME_WIDTH + PANEL_WIDTH, 0), None);
+    }
+
+    #[test]
+    fn test_draw_highlights() {
+        let width = FRAME_WIDTH + PANEL_WIDTH;
+        let mut frame = vec![0; width * FRAME_HEIGHT * COLOR_WIDTH];
+        let palette = Palette::default();
+
+        // Hold key 1 and poll key 2, the first two keys of the top row
+        draw(&mut frame, &palette, 1 << 0x1, 1 << 0x2);
+
+        let red = |x: usize, y: usize| frame[((y * width) + x) * COLOR_WIDTH];
+        assert_eq!(red(FRAME_WIDTH + 2, 2), 0xFF);
+        assert_eq!(red(FRAME_WIDTH + CELL + 2, 2), 0x7F);
+        assert_eq!(red(FRAME_WIDTH + (CELL * 2) + 2, 2), 0x00);
+        assert_eq!(red(FRAME_WIDTH + (CELL * 2) + 1, 1), 0xFF);
+    }
+}
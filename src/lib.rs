@@ -0,0 +1,28 @@
+pub mod asm;
+pub mod breakpoints;
+pub mod cli;
+pub mod condition;
+pub mod dap;
+pub mod debugger;
+pub mod disasm;
+pub mod dump;
+pub mod flow;
+pub mod gdb;
+pub mod headless;
+pub mod heatmap;
+pub mod json;
+pub mod keymap;
+pub mod keypad;
+pub mod osd;
+pub mod palette;
+pub mod presenter;
+pub mod profile;
+pub mod recent;
+pub mod record;
+pub mod screenshot;
+pub mod settings;
+pub mod sprites;
+pub mod symbols;
+pub mod system;
+pub mod timing;
+pub mod trace;
@@ -1,112 +1,502 @@
-mod system;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
 
-use std::fs::File;
-use std::io::{prelude::*, BufReader};
-use std::path::Path;
-use std::thread;
-
-use pixels::{wgpu::Surface, Error, Pixels, SurfaceTexture};
 use winit::dpi::LogicalSize;
 use winit::event::{Event, VirtualKeyCode};
-use winit::event_loop::{EventLoop, ControlFlow};
-use winit::window::WindowBuilder;
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::{Fullscreen, Window, WindowBuilder};
 use winit_input_helper::WinitInputHelper;
 
-use std::time::Instant;
+use chip8::cli::{take_flag, take_value, usage, Options};
+use chip8::heatmap::{self, Heatmap};
+use chip8::keypad::{self, PANEL_WIDTH};
+use chip8::osd::{self, Osd, FRAME_HEIGHT, FRAME_WIDTH};
+use chip8::presenter::{self, Renderer, Viewport};
+use chip8::recent::RecentRoms;
+use chip8::record::Recorder;
+use chip8::screenshot::timestamp;
+use chip8::settings::RomSettings;
+use chip8::system::{System, HEIGHT};
+use chip8::timing::{FrameClock, RateMeter, FRAME_RATE};
+
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the emulation speed and render rate shown on screen are measured
+const RATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How much of the heatmap panel's counts are kept each frame, so it shows about the last second
+const HEATMAP_DECAY: f32 = 0.95;
+
+const WINDOW_HELP: &str = "\
+Window options:
+    --renderer <RENDERER>      gpu (default) or software, which does not need a GPU driver
+    --fullscreen               Start in fullscreen, toggled with Alt+Enter
+    --keypad                   Show a keypad beside the game that can be clicked with the mouse
+    --heatmap                  Show a heatmap of recent memory reads (green), writes (red) and
+                               executed code (blue) beside the game, one pixel per address";
+
+/// Options only understood by the window frontend
+struct WindowOptions {
+    renderer: Renderer,
+    fullscreen: bool,
+    keypad: bool,
+    heatmap: bool,
+}
 
-fn main() -> Result<(), Error> {
-    let rom_path = Path::new("./roms/breakout.ch8");
-    let rom_file = File::open(rom_path).unwrap();
+fn main() {
+    let (window_options, options) = match parse_args() {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, help());
+            process::exit(2);
+        }
+    };
 
-    let mut reader = BufReader::new(rom_file);
-    let mut buffer: Vec<u8> = vec!();
+    if options.help {
+        println!("{}", help());
+        return;
+    }
 
-    reader.read_to_end(&mut buffer).unwrap();
+    let mut rom = canonical(&options.rom);
+    let (mut test_system, mut game_options, title) = match load_game(&rom, &options) {
+        Ok(game) => game,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
 
-    let mut test_system: system::System = system::System::new();
-    test_system.write_rom(buffer);
+    let recent_path = RecentRoms::default_path();
+    let mut recent = match recent_path.as_ref().map(RecentRoms::load) {
+        Some(Ok(recent)) => recent,
+        Some(Err(err)) => {
+            eprintln!("Failed to read the recent ROMs: {}", err);
+            RecentRoms::default()
+        }
+        None => RecentRoms::default(),
+    };
+    remember(&mut recent, recent_path.as_deref(), &rom);
 
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
 
+    // Sizes are logical so the window keeps its apparent size on HiDPI displays, while the
+    // presenters scale by whole physical pixels within it
+    let keypad_width = if window_options.keypad {
+        PANEL_WIDTH
+    } else {
+        0
+    };
+    let frame_width = if window_options.heatmap {
+        FRAME_WIDTH + keypad_width + heatmap::PANEL_WIDTH
+    } else {
+        FRAME_WIDTH + keypad_width
+    };
+
+    if window_options.heatmap {
+        test_system.set_heatmap(Some(Heatmap::new(HEATMAP_DECAY)));
+    }
     let window = {
-        let size = LogicalSize::new(512 as f64, 256 as f64);
+        let size = LogicalSize::new(
+            (frame_width * options.scale / osd::SCALE) as f64,
+            (HEIGHT * options.scale) as f64,
+        );
         WindowBuilder::new()
-            .with_title("CHIP-8")
+            .with_title(window_title(&title))
             .with_inner_size(size)
-            .with_resizable(false)
+            .with_min_inner_size(LogicalSize::new(frame_width as f64, FRAME_HEIGHT as f64))
+            .with_resizable(true)
             .build(&event_loop)
             .unwrap()
     };
 
-    let mut hidpi_factor = window.scale_factor();
+    if window_options.fullscreen {
+        toggle_fullscreen(&window);
+    }
 
-    let mut pixels = {
-        let surface = Surface::create(&window);
-        let surface_texture = SurfaceTexture::new(64, 32, surface);
-        Pixels::new(64, 32, surface_texture)?
-    };
+    // The frame size is fixed for the whole session, as the system only has a 64x32 display.
+    // Switching to a 128x64 mode would need the presenter recreated at the new size.
+    let mut presenter =
+        presenter::create(window_options.renderer, &window, frame_width, FRAME_HEIGHT)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
 
-    let mut last_frame = Instant::now();
-    let frame_duration = std::time::Duration::from_secs_f32(1.0/60.0);
+    let mut recorder = options.record.as_ref().map(|path| {
+        Recorder::create(
+            path,
+            options.record_format,
+            game_options.palette,
+            options.scale,
+        )
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to start recording to {}: {}", path.display(), err);
+            process::exit(1);
+        })
+    });
 
-    event_loop.run(move |event, _, control_flow| {
-        match event {
-            Event::RedrawRequested(_) => {
-                let previous_frame_time = last_frame;
-    
-                let mut frame = pixels.get_frame();
-
-                if (Instant::now() - previous_frame_time).as_micros() > 100000 / 60 {
-                    test_system.run_to_next_frame();
-                    last_frame = Instant::now();
-                }
-                
-                let framebuffer = test_system.get_framebuffer();
-                frame.write(framebuffer).unwrap();
-                pixels.render();
+    let mut osd = Osd::new();
 
-                if Instant::now() - last_frame < frame_duration {
-                    thread::sleep(frame_duration - (Instant::now() - last_frame));
-                }
-    
-                let delta = last_frame - previous_frame_time;
-                let fps = (1.0 / ((delta.as_millis() as f64) / 1000.0)).round();
-            
-                window.set_title(&format!("CHIP-8 ({} fps)", fps));
+    let start = Instant::now();
+    let mut clock = FrameClock::new(start);
+    let mut emulation_rate = RateMeter::new(start, RATE_INTERVAL);
+    let mut render_rate = RateMeter::new(start, RATE_INTERVAL);
+
+    event_loop.run(move |event, _, control_flow| {
+        if let Event::RedrawRequested(_) = event {
+            let framebuffer = test_system.get_framebuffer();
+            let now = Instant::now();
+            let frame = presenter.get_frame();
+            osd.draw(framebuffer, frame, &game_options.palette, now);
+            if window_options.keypad {
+                let held = test_system.get_keys();
+                let polled = test_system.get_polled_keys();
+                keypad::draw(frame, &game_options.palette, held, polled);
             }
-            _ => {}
+            if let Some(counts) = test_system.get_heatmap() {
+                let left = FRAME_WIDTH + keypad_width;
+                heatmap::draw(frame, &game_options.palette, counts, left);
+            }
+            presenter.render();
+
+            render_rate.add(1, now);
         }
-        
 
         if input.update(event) {
             if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+                if let Some(recorder) = recorder.take() {
+                    eprintln!("{}", finish_recording(recorder));
+                }
+
                 *control_flow = ControlFlow::Exit;
                 return;
             }
 
-            test_system.set_key(0x0, input.key_held(VirtualKeyCode::Key0));
-            test_system.set_key(0x1, input.key_held(VirtualKeyCode::Key1));
-            test_system.set_key(0x2, input.key_held(VirtualKeyCode::Key2));
-            test_system.set_key(0x3, input.key_held(VirtualKeyCode::Key3));
-            test_system.set_key(0x4, input.key_held(VirtualKeyCode::Key4));
-            test_system.set_key(0x5, input.key_held(VirtualKeyCode::Key5));
-            test_system.set_key(0x6, input.key_held(VirtualKeyCode::Key6));
-            test_system.set_key(0x7, input.key_held(VirtualKeyCode::Key7));
-            test_system.set_key(0x8, input.key_held(VirtualKeyCode::Key8));
-            test_system.set_key(0x9, input.key_held(VirtualKeyCode::Key9));
-            test_system.set_key(0xA, input.key_held(VirtualKeyCode::A));
-            test_system.set_key(0xB, input.key_held(VirtualKeyCode::B));
-            test_system.set_key(0xC, input.key_held(VirtualKeyCode::C));
-            test_system.set_key(0xD, input.key_held(VirtualKeyCode::D));
-            test_system.set_key(0xE, input.key_held(VirtualKeyCode::E));
-            test_system.set_key(0xF, input.key_held(VirtualKeyCode::F));
-
-            if let Some(factor) = input.scale_factor_changed() {
-                hidpi_factor = factor;
-            }
-
-            window.request_redraw();
+            let clicked = match input.mouse() {
+                Some((x, y)) if window_options.keypad && input.mouse_held(0) => {
+                    let size = window.inner_size();
+                    let viewport = Viewport::fit(
+                        frame_width,
+                        FRAME_HEIGHT,
+                        size.width as usize,
+                        size.height as usize,
+                    );
+
+                    viewport
+                        .to_frame(frame_width, FRAME_HEIGHT, x as usize, y as usize)
+                        .and_then(|(x, y)| keypad::key_at(x, y))
+                }
+                _ => None,
+            };
+
+            for key in 0..16 {
+                let held = clicked == Some(key)
+                    || virtual_key(game_options.keymap.char_for(key))
+                        .is_some_and(|code| input.key_held(code));
+
+                test_system.set_key(key, held);
+            }
+            osd.set_keys(test_system.get_keys());
+
+            let now = Instant::now();
+
+            if input.key_pressed(VirtualKeyCode::F1) {
+                osd.show_info = !osd.show_info;
+            }
+
+            if input.key_pressed(VirtualKeyCode::F2) {
+                osd.show_keys = !osd.show_keys;
+            }
+
+            if input.key_pressed(VirtualKeyCode::F3) {
+                game_options.palette = game_options.palette.next();
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.set_palette(game_options.palette);
+                }
+
+                let palette = game_options.palette;
+                let message = save_settings(&rom, |settings| settings.palette = Some(palette));
+                osd.show_message(message, now);
+            }
+
+            if input.key_pressed(VirtualKeyCode::F4) {
+                game_options.keymap = game_options.keymap.next();
+
+                let keymap = game_options.keymap;
+                let message = save_settings(&rom, |settings| settings.keymap = Some(keymap));
+                osd.show_message(message, now);
+            }
+
+            if input.key_pressed(VirtualKeyCode::F12) {
+                match test_system.save_screenshot(".", &game_options.palette, options.scale) {
+                    Ok(path) => {
+                        eprintln!("Saved screenshot to {}", path.display());
+                        osd.show_message("Screenshot saved", now);
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to save screenshot: {}", err);
+                        osd.show_message("Screenshot failed", now);
+                    }
+                }
+            }
+
+            if input.key_pressed(VirtualKeyCode::F11) {
+                match recorder.take() {
+                    Some(recorder) => {
+                        let message = finish_recording(recorder);
+                        eprintln!("{}", message);
+                        osd.show_message(message, now);
+                    }
+                    None => {
+                        let path = format!(
+                            "chip8-{}.{}",
+                            timestamp(SystemTime::now()),
+                            options.record_format.extension()
+                        );
+
+                        let started = Recorder::create(
+                            &path,
+                            options.record_format,
+                            game_options.palette,
+                            options.scale,
+                        );
+
+                        match started {
+                            Ok(started) => {
+                                eprintln!("Recording to {}", path);
+                                osd.show_message("Recording", now);
+                                recorder = Some(started);
+                            }
+                            Err(err) => {
+                                eprintln!("Failed to start recording: {}", err);
+                                osd.show_message("Recording failed", now);
+                            }
+                        }
+                    }
+                }
+            }
+
+            let swap = if let Some(dropped) = input.dropped_file() {
+                Some(canonical(&dropped))
+            } else if input.key_pressed(VirtualKeyCode::PageDown) {
+                recent.cycle(&rom, 1).map(Path::to_path_buf)
+            } else if input.key_pressed(VirtualKeyCode::PageUp) {
+                recent.cycle(&rom, -1).map(Path::to_path_buf)
+            } else {
+                None
+            };
+
+            if let Some(path) = swap {
+                match load_game(&path, &options) {
+                    Ok((system, loaded_options, title)) => {
+                        test_system = system;
+                        if window_options.heatmap {
+                            test_system.set_heatmap(Some(Heatmap::new(HEATMAP_DECAY)));
+                        }
+                        game_options = loaded_options;
+                        if let Some(recorder) = recorder.as_mut() {
+                            recorder.set_palette(game_options.palette);
+                        }
+
+                        // Cycling walks the list in place, only new ROMs move to the front
+                        if input.dropped_file().is_some() {
+                            remember(&mut recent, recent_path.as_deref(), &path);
+                        }
+                        rom = path;
+
+                        window.set_title(&window_title(&title));
+                        osd.show_message(title, now);
+                    }
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        osd.show_message("Failed to load ROM", now);
+                    }
+                }
+            }
+
+            if input.key_pressed(VirtualKeyCode::Return) && input.held_alt() {
+                toggle_fullscreen(&window);
+            }
+
+            if input.window_resized().is_some() || input.scale_factor_changed().is_some() {
+                let size = window.inner_size();
+                presenter.resize(size.width, size.height);
+                window.request_redraw();
+            }
+
+            let frames = clock.advance(now);
+
+            for _ in 0..frames {
+                test_system.run_to_next_frame();
+
+                if let Some(recorder) = recorder.as_mut() {
+                    if let Err(err) = recorder.record_frame(&test_system) {
+                        eprintln!("Failed to record frame: {}", err);
+                    }
+                }
+            }
+
+            if let Some(rate) = emulation_rate.add(frames, now) {
+                let fps = render_rate.rate().unwrap_or(0.0);
+                let speed = rate * 100.0 / FRAME_RATE as f64;
+
+                osd.set_info(format!("{:.0} FPS {:.0}%", fps, speed));
+            }
+
+            if frames > 0 {
+                window.request_redraw();
+            }
+
+            *control_flow = ControlFlow::WaitUntil(clock.next_frame());
         }
     });
 }
+
+fn help() -> String {
+    format!("{}\n\n{}", usage("chip8"), WINDOW_HELP)
+}
+
+/// Splits the window-specific options from the options shared with the other frontends
+fn parse_args() -> Result<(WindowOptions, Options), String> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let fullscreen = take_flag(&mut args, "--fullscreen");
+    let keypad = take_flag(&mut args, "--keypad");
+    let heatmap = take_flag(&mut args, "--heatmap");
+
+    let renderer = match take_value(&mut args, "--renderer")? {
+        Some(name) => {
+            Renderer::parse(&name).ok_or_else(|| format!("unknown renderer '{}'", name))?
+        }
+        None => Renderer::default(),
+    };
+
+    let window_options = WindowOptions {
+        renderer,
+        fullscreen,
+        keypad,
+        heatmap,
+    };
+
+    Ok((window_options, Options::parse(args)?))
+}
+
+/// Loads a ROM along with its settings, returning the system, the options with the settings
+/// applied and the ROM's title
+///
+/// # Arguments
+///
+/// * `rom` - The path of the ROM
+/// * `options` - The options given on the command line
+fn load_game(rom: &Path, options: &Options) -> Result<(System, Options, String), String> {
+    let error = |err| format!("Failed to load {}: {}", rom.display(), err);
+
+    let mut system = System::load_rom(rom).map_err(error)?;
+    let settings = RomSettings::load(rom).map_err(error)?;
+    let options = settings.apply(options);
+    system.set_quirks(options.quirks);
+
+    Ok((system, options, settings.title_for(rom)))
+}
+
+/// Changes a ROM's settings file, keeping its other settings, and returns a message saying
+/// whether it was saved
+///
+/// # Arguments
+///
+/// * `rom` - The path of the ROM
+/// * `change` - Sets the changed settings
+fn save_settings<F: FnOnce(&mut RomSettings)>(rom: &Path, change: F) -> String {
+    let path = RomSettings::path_for(rom);
+    let saved = RomSettings::load(rom).and_then(|mut settings| {
+        change(&mut settings);
+        settings.save(rom)
+    });
+
+    match saved {
+        Ok(()) => format!(
+            "Saved to {}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ),
+        Err(err) => {
+            eprintln!("Failed to save {}: {}", path.display(), err);
+            String::from("Failed to save settings")
+        }
+    }
+}
+
+/// Adds a ROM to the front of the recent list and saves it
+///
+/// # Arguments
+///
+/// * `recent` - The recent ROMs
+/// * `path` - Where the list is saved, if anywhere
+/// * `rom` - The ROM that was loaded
+fn remember(recent: &mut RecentRoms, path: Option<&Path>, rom: &Path) {
+    recent.add(rom);
+
+    if let Some(path) = path {
+        if let Err(err) = recent.save(path) {
+            eprintln!("Failed to save the recent ROMs: {}", err);
+        }
+    }
+}
+
+/// Returns the absolute form of a path, so the same ROM is only listed once however it was
+/// opened
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn window_title(title: &str) -> String {
+    format!("CHIP-8 - {}", title)
+}
+
+/// Switches between a borderless fullscreen window on the current monitor and a normal window
+///
+/// # Arguments
+///
+/// * `window` - The window to switch
+fn toggle_fullscreen(window: &Window) {
+    match window.fullscreen() {
+        Some(_) => window.set_fullscreen(None),
+        None => window.set_fullscreen(Some(Fullscreen::Borderless(window.current_monitor()))),
+    }
+}
+
+/// Terminates a recording, returning a message saying how many frames were captured
+///
+/// # Arguments
+///
+/// * `recorder` - The recording to finish
+fn finish_recording(recorder: Recorder) -> String {
+    let frames = recorder.frames();
+
+    match recorder.finish() {
+        Ok(()) => format!("Recorded {} frames", frames),
+        Err(err) => format!("Failed to finish recording: {}", err),
+    }
+}
+
+/// Returns the winit key code for a keymap character
+///
+/// # Arguments
+///
+/// * `c` - An ASCII letter or digit from the keymap
+fn virtual_key(c: char) -> Option<VirtualKeyCode> {
+    use VirtualKeyCode::*;
+
+    const DIGITS: [VirtualKeyCode; 10] =
+        [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+    const LETTERS: [VirtualKeyCode; 26] = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+
+    match c.to_ascii_lowercase() {
+        '0'..='9' => Some(DIGITS[c as usize - '0' as usize]),
+        c @ 'a'..='z' => Some(LETTERS[c as usize - 'a' as usize]),
+        _ => None,
+    }
+}
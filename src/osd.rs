@@ -0,0 +1,342 @@
+use crate::palette::Palette;
+use crate::system::{COLOR_WIDTH, FONT, HEIGHT, WIDTH};
+
+use std::time::{Duration, Instant};
+
+/// How many overlay pixels make up a CHIP-8 pixel. The overlay is drawn at a higher resolution
+/// than the game so that text stays small next to it.
+pub const SCALE: usize = 2;
+
+/// The width of frames drawn by `Osd::draw`, in overlay pixels
+pub const FRAME_WIDTH: usize = WIDTH * SCALE;
+
+/// The height of frames drawn by `Osd::draw`, in overlay pixels
+pub const FRAME_HEIGHT: usize = HEIGHT * SCALE;
+
+/// How long a message stays on screen
+pub const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+pub(crate) const GLYPH_HEIGHT: usize = 5;
+pub(crate) const GLYPH_ADVANCE: usize = 5;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+
+/// Glyphs for characters other than hexadecimal digits, which come from the interpreter font.
+/// Rows are stored like CHIP-8 sprites, 4 pixels wide in the high nibble.
+const GLYPHS: [(char, [u8; GLYPH_HEIGHT]); 34] = [
+    ('G', [0xF0, 0x80, 0xB0, 0x90, 0xF0]),
+    ('H', [0x90, 0x90, 0xF0, 0x90, 0x90]),
+    ('I', [0x70, 0x20, 0x20, 0x20, 0x70]),
+    ('J', [0x10, 0x10, 0x10, 0x90, 0x60]),
+    ('K', [0x90, 0xA0, 0xC0, 0xA0, 0x90]),
+    ('L', [0x80, 0x80, 0x80, 0x80, 0xF0]),
+    ('M', [0x90, 0xF0, 0xF0, 0x90, 0x90]),
+    ('N', [0x90, 0xD0, 0xB0, 0x90, 0x90]),
+    ('O', [0x60, 0x90, 0x90, 0x90, 0x60]),
+    ('P', [0xE0, 0x90, 0xE0, 0x80, 0x80]),
+    ('Q', [0x60, 0x90, 0x90, 0xB0, 0x70]),
+    ('R', [0xE0, 0x90, 0xE0, 0xA0, 0x90]),
+    ('S', [0x70, 0x80, 0x60, 0x10, 0xE0]),
+    ('T', [0xE0, 0x40, 0x40, 0x40, 0x40]),
+    ('U', [0x90, 0x90, 0x90, 0x90, 0x60]),
+    ('V', [0xA0, 0xA0, 0xA0, 0xA0, 0x40]),
+    ('W', [0x90, 0x90, 0xF0, 0xF0, 0x90]),
+    ('X', [0x90, 0x90, 0x60, 0x90, 0x90]),
+    ('Y', [0xA0, 0xA0, 0x40, 0x40, 0x40]),
+    ('Z', [0xF0, 0x10, 0x60, 0x80, 0xF0]),
+    ('×', [0x00, 0x90, 0x60, 0x90, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x40]),
+    (',', [0x00, 0x00, 0x00, 0x40, 0x80]),
+    (':', [0x00, 0x40, 0x00, 0x40, 0x00]),
+    ('\'', [0x40, 0x40, 0x00, 0x00, 0x00]),
+    ('-', [0x00, 0x00, 0xF0, 0x00, 0x00]),
+    ('+', [0x00, 0x40, 0xE0, 0x40, 0x00]),
+    ('=', [0x00, 0xF0, 0x00, 0xF0, 0x00]),
+    ('/', [0x10, 0x20, 0x20, 0x40, 0x80]),
+    ('%', [0x90, 0x10, 0x20, 0x40, 0x90]),
+    ('!', [0x40, 0x40, 0x40, 0x00, 0x40]),
+    ('?', [0xE0, 0x10, 0x60, 0x00, 0x40]),
+    ('(', [0x20, 0x40, 0x40, 0x40, 0x20]),
+    (')', [0x40, 0x20, 0x20, 0x20, 0x40]),
+];
+
+/// The CHIP-8 keys in the order they appear on the COSMAC VIP keypad, row by row
+pub const KEYPAD: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+/// An on-screen display drawn over the game: a transient message along the bottom, an info line
+/// along the top and a view of the held keys in the top-right corner
+#[derive(Clone, Debug, Default)]
+pub struct Osd {
+    message: Option<(String, Instant)>,
+    info: String,
+    keys: u16,
+    pub show_info: bool,
+    pub show_keys: bool,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shows a message for `MESSAGE_DURATION`, replacing the current one
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to show. Letters are drawn in upper case.
+    /// * `now` - The current time
+    pub fn show_message<S: Into<String>>(&mut self, message: S, now: Instant) {
+        self.message = Some((message.into(), now + MESSAGE_DURATION));
+    }
+
+    /// Sets the info line shown while `show_info` is enabled, e.g. the frame rate
+    ///
+    /// # Arguments
+    ///
+    /// * `info` - The text of the info line
+    pub fn set_info<S: Into<String>>(&mut self, info: S) {
+        self.info = info.into();
+    }
+
+    /// Sets the keys shown as held while `show_keys` is enabled
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The held keys, with bit `n` set while key `n` is held
+    pub fn set_keys(&mut self, keys: u16) {
+        self.keys = keys;
+    }
+
+    /// Returns the message currently on screen
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time
+    pub fn get_message(&self, now: Instant) -> Option<&str> {
+        match self.message.as_ref() {
+            Some((message, expires)) if now < *expires => Some(message),
+            _ => None,
+        }
+    }
+
+    /// Draws the game's framebuffer enlarged by `SCALE` into `frame`, with the overlay on top.
+    /// Text is drawn in the foreground color on a box of the background color, so it stays
+    /// readable over any game.
+    ///
+    /// # Arguments
+    ///
+    /// * `framebuffer` - The game's `WIDTH`x`HEIGHT` RGBA framebuffer
+    /// * `frame` - The RGBA frame to draw into, `FRAME_HEIGHT` tall and at least `FRAME_WIDTH`
+    ///   wide. The game fills the left `FRAME_WIDTH` columns, leaving any others for panels.
+    /// * `palette` - The colors to draw with
+    /// * `now` - The current time, used to expire messages
+    pub fn draw(&self, framebuffer: &[u8], frame: &mut [u8], palette: &Palette, now: Instant) {
+        let mut canvas = Canvas::new(frame, palette);
+
+        for y in 0..FRAME_HEIGHT {
+            for x in 0..FRAME_WIDTH {
+                let idx = (((y / SCALE) * WIDTH) + (x / SCALE)) * COLOR_WIDTH;
+                canvas.set(x, y, framebuffer[idx] != 0);
+            }
+        }
+
+        if self.show_info && !self.info.is_empty() {
+            canvas.text(1, 1, &self.info);
+        }
+
+        if self.show_keys {
+            canvas.keypad(self.keys);
+        }
+
+        if let Some(message) = self.get_message(now) {
+            canvas.text(1, FRAME_HEIGHT - LINE_HEIGHT - 1, message);
+        }
+    }
+}
+
+/// An RGBA frame `FRAME_HEIGHT` pixels tall being drawn into with a palette
+pub(crate) struct Canvas<'a> {
+    frame: &'a mut [u8],
+    palette: &'a Palette,
+    width: usize,
+}
+
+impl<'a> Canvas<'a> {
+    pub(crate) fn new(frame: &'a mut [u8], palette: &'a Palette) -> Self {
+        let width = frame.len() / COLOR_WIDTH / FRAME_HEIGHT;
+
+        Self {
+            frame,
+            palette,
+            width,
+        }
+    }
+
+    pub(crate) fn set_color(&mut self, x: usize, y: usize, [r, g, b]: [u8; 3]) {
+        if x >= self.width || y >= FRAME_HEIGHT {
+            return;
+        }
+
+        let idx = ((y * self.width) + x) * COLOR_WIDTH;
+        self.frame[idx..idx + COLOR_WIDTH].copy_from_slice(&[r, g, b, 0xFF]);
+    }
+
+    pub(crate) fn set(&mut self, x: usize, y: usize, lit: bool) {
+        self.set_color(x, y, self.palette.color(lit));
+    }
+
+    pub(crate) fn fill_color(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: [u8; 3],
+    ) {
+        for y in y..y + height {
+            for x in x..x + width {
+                self.set_color(x, y, color);
+            }
+        }
+    }
+
+    pub(crate) fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, lit: bool) {
+        self.fill_color(x, y, width, height, self.palette.color(lit));
+    }
+
+    /// Draws a character's glyph with its top-left corner at `x`, `y`
+    pub(crate) fn glyph(&mut self, x: usize, y: usize, c: char, lit: bool) {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..4 {
+                if bits & (0x80 >> col) != 0 {
+                    self.set(x + col, y + row, lit);
+                }
+            }
+        }
+    }
+
+    /// Draws a line of text on a background box, with its top-left corner at `x`, `y`. Text too
+    /// long for the game's part of the frame is cut short with `..`, so it stays off any panels.
+    fn text(&mut self, x: usize, y: usize, text: &str) {
+        let fits = (FRAME_WIDTH - x - 1) / GLYPH_ADVANCE;
+        let mut chars: Vec<char> = text.chars().collect();
+        if chars.len() > fits {
+            chars.truncate(fits.saturating_sub(2));
+            chars.extend_from_slice(&['.', '.']);
+        }
+
+        let width = chars.len() * GLYPH_ADVANCE + 1;
+        self.fill(x, y, width, LINE_HEIGHT, false);
+
+        for (idx, c) in chars.into_iter().enumerate() {
+            self.glyph(x + 1 + idx * GLYPH_ADVANCE, y + 1, c, true);
+        }
+    }
+
+    /// Draws the 4x4 keypad in the top-right corner, with held keys filled in
+    fn keypad(&mut self, keys: u16) {
+        const CELL: usize = 4;
+        let left = FRAME_WIDTH - (4 * CELL) - 2;
+
+        self.fill(left, 1, 4 * CELL + 1, 4 * CELL + 1, false);
+
+        for (idx, key) in KEYPAD.iter().enumerate() {
+            let x = left + 1 + (idx % 4) * CELL;
+            let y = 2 + (idx / 4) * CELL;
+
+            if keys & (1 << key) != 0 {
+                self.fill(x, y, CELL - 1, CELL - 1, true);
+            } else {
+                self.set(x + 1, y + 1, true);
+            }
+        }
+    }
+}
+
+/// Returns the rows of a character's glyph, blank for characters without one
+///
+/// # Arguments
+///
+/// * `c` - The character to draw, case insensitive
+pub(crate) fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    let mut rows = [0; GLYPH_HEIGHT];
+
+    if let Some(digit) = c.to_digit(16) {
+        let start = digit as usize * GLYPH_HEIGHT;
+        rows.copy_from_slice(&FONT[start..start + GLYPH_HEIGHT]);
+    } else if let Some((_, glyph)) = GLYPHS.iter().find(|(glyph, _)| *glyph == c) {
+        rows = *glyph;
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(frame: &[u8], x: usize, y: usize) -> bool {
+        frame[((y * FRAME_WIDTH) + x) * COLOR_WIDTH] != 0
+    }
+
+    #[test]
+    fn test_glyphs() {
+        // Hex digits come from the interpreter font, in either case
+        assert_eq!(glyph('a'), [0xF0, 0x90, 0xF0, 0x90, 0x90]);
+        assert_eq!(glyph('O'), [0x60, 0x90, 0x90, 0x90, 0x60]);
+        assert_eq!(glyph(' '), [0; GLYPH_HEIGHT]);
+    }
+
+    #[test]
+    fn test_message_expires() {
+        let start = Instant::now();
+        let mut osd = Osd::new();
+
+        osd.show_message("Paused", start);
+        assert_eq!(osd.get_message(start), Some("Paused"));
+        assert_eq!(osd.get_message(start + MESSAGE_DURATION), None);
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut framebuffer = vec![0; WIDTH * HEIGHT * COLOR_WIDTH];
+        // Light the bottom-right pixel of the game
+        framebuffer[(WIDTH * HEIGHT - 1) * COLOR_WIDTH] = 0xFF;
+
+        let start = Instant::now();
+        let mut osd = Osd::new();
+        osd.show_message("1", start);
+
+        let mut frame = vec![0; FRAME_WIDTH * FRAME_HEIGHT * COLOR_WIDTH];
+        osd.draw(&framebuffer, &mut frame, &Palette::default(), start);
+
+        assert!(lit(&frame, FRAME_WIDTH - 1, FRAME_HEIGHT - 1));
+        assert!(lit(&frame, FRAME_WIDTH - SCALE, FRAME_HEIGHT - SCALE));
+        assert!(!lit(&frame, FRAME_WIDTH - SCALE - 1, FRAME_HEIGHT - 1));
+
+        // The "1" glyph's top row is 0x20, on a box starting at the left edge
+        let top = FRAME_HEIGHT - LINE_HEIGHT;
+        assert!(!lit(&frame, 2, top));
+        assert!(lit(&frame, 4, top));
+    }
+
+    #[test]
+    fn test_long_message() {
+        let framebuffer = vec![0; WIDTH * HEIGHT * COLOR_WIDTH];
+        let start = Instant::now();
+        let mut osd = Osd::new();
+        osd.show_message("WWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWW", start);
+
+        // A frame with a panel to the right of the game, which the message must not reach
+        let width = FRAME_WIDTH + 16;
+        let mut frame = vec![0; width * FRAME_HEIGHT * COLOR_WIDTH];
+        osd.draw(&framebuffer, &mut frame, &Palette::default(), start);
+
+        let panel = frame
+            .chunks_exact(width * COLOR_WIDTH)
+            .flat_map(|row| row[FRAME_WIDTH * COLOR_WIDTH..].iter());
+        assert!(panel.into_iter().all(|byte| *byte == 0));
+    }
+}
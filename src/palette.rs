@@ -0,0 +1,106 @@
+use std::fmt;
+
+/// The palettes `Palette::next` cycles through: white, green and amber phosphor, and the green
+/// of an early handheld LCD
+const PRESETS: [Palette; 4] = [
+    Palette::new([0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF]),
+    Palette::new([0x0C, 0x1A, 0x0C], [0x33, 0xFF, 0x66]),
+    Palette::new([0x1A, 0x0F, 0x00], [0xFF, 0xB0, 0x00]),
+    Palette::new([0x0F, 0x38, 0x0F], [0x9B, 0xBC, 0x0F]),
+];
+
+/// The two colors used when converting the monochrome framebuffer into an image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub background: [u8; 3],
+    pub foreground: [u8; 3],
+}
+
+impl Palette {
+    pub const fn new(background: [u8; 3], foreground: [u8; 3]) -> Self {
+        Self {
+            background,
+            foreground,
+        }
+    }
+
+    /// Returns the RGB color for a pixel
+    ///
+    /// # Arguments
+    ///
+    /// * `lit` - Whether the pixel is set in the framebuffer
+    pub fn color(&self, lit: bool) -> [u8; 3] {
+        if lit {
+            self.foreground
+        } else {
+            self.background
+        }
+    }
+
+    /// Colors an RGBA framebuffer, as returned by `System::get_framebuffer`, into an RGBA frame
+    ///
+    /// # Arguments
+    ///
+    /// * `framebuffer` - The monochrome framebuffer to read
+    /// * `frame` - The frame to write, the same size as `framebuffer`
+    pub fn apply(&self, framebuffer: &[u8], frame: &mut [u8]) {
+        for (pixel, out) in framebuffer.chunks_exact(4).zip(frame.chunks_exact_mut(4)) {
+            out[..3].copy_from_slice(&self.color(pixel[0] != 0));
+            out[3] = 0xFF;
+        }
+    }
+
+    /// Parses a palette written as two hex colors, e.g. `000000:FFFFFF`
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The background and foreground colors separated by a colon
+    pub fn parse(value: &str) -> Option<Palette> {
+        let mut colors = value.split(':').map(parse_color);
+
+        match (colors.next(), colors.next(), colors.next()) {
+            (Some(Some(background)), Some(Some(foreground)), None) => {
+                Some(Palette::new(background, foreground))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Palette {
+    /// Returns the built-in palette after this one, or the first for a custom palette
+    pub fn next(&self) -> Palette {
+        match PRESETS.iter().position(|preset| preset == self) {
+            Some(idx) => PRESETS[(idx + 1) % PRESETS.len()],
+            None => PRESETS[0],
+        }
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b] = self.background;
+        write!(f, "{:02X}{:02X}{:02X}:", r, g, b)?;
+        let [r, g, b] = self.foreground;
+        write!(f, "{:02X}{:02X}{:02X}", r, g, b)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        PRESETS[0]
+    }
+}
+
+/// Parses a single `RRGGBB` color, optionally prefixed with `#`
+fn parse_color(value: &str) -> Option<[u8; 3]> {
+    let value = value.trim_start_matches('#');
+
+    if value.len() != 6 || !value.is_ascii() {
+        return None;
+    }
+
+    let channel = |idx: usize| u8::from_str_radix(&value[idx..idx + 2], 16).ok();
+
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
@@ -0,0 +1,420 @@
+use crate::system::COLOR_WIDTH;
+
+use winit::window::Window;
+
+/// The ways frames can be drawn into a window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Renderer {
+    /// Draws through wgpu, which needs a GPU and a working driver
+    Gpu,
+    /// Scales and blits frames on the CPU
+    Software,
+}
+
+impl Renderer {
+    /// Parses a renderer name, either `gpu` or `software`
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the renderer, case insensitive
+    pub fn parse(name: &str) -> Option<Renderer> {
+        match name.to_ascii_lowercase().as_str() {
+            "gpu" => Some(Renderer::Gpu),
+            "software" | "cpu" => Some(Renderer::Software),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        if cfg!(feature = "gpu") {
+            Renderer::Gpu
+        } else {
+            Renderer::Software
+        }
+    }
+}
+
+/// Draws fixed-size RGBA frames into a window
+pub trait Presenter {
+    /// Returns the RGBA buffer drawn by the next call to `render`. It keeps the previous frame's
+    /// contents until overwritten.
+    fn get_frame(&mut self) -> &mut [u8];
+
+    /// Draws the current frame into the window
+    fn render(&mut self);
+
+    /// Adapts to a new window size, in physical pixels
+    fn resize(&mut self, width: u32, height: u32);
+}
+
+/// Creates a presenter drawing into a window. When the GPU cannot be used and the software
+/// renderer is available, it is used instead.
+///
+/// # Arguments
+///
+/// * `renderer` - The preferred renderer
+/// * `window` - The window to draw into
+/// * `width` - The width of the frames, in pixels
+/// * `height` - The height of the frames, in pixels
+pub fn create(
+    renderer: Renderer,
+    window: &Window,
+    width: usize,
+    height: usize,
+) -> Result<Box<dyn Presenter>, String> {
+    match renderer {
+        Renderer::Gpu => match gpu::create(window, width, height) {
+            Ok(presenter) => Ok(presenter),
+            Err(err) if cfg!(feature = "software") => {
+                eprintln!("{}, falling back to the software renderer", err);
+                software::create(window, width, height)
+            }
+            Err(err) => Err(err),
+        },
+        Renderer::Software => software::create(window, width, height),
+    }
+}
+
+#[cfg(feature = "gpu")]
+mod gpu {
+    use super::Presenter;
+
+    use pixels::{wgpu::Surface, Pixels, SurfaceTexture};
+    use winit::window::Window;
+
+    pub fn create(
+        window: &Window,
+        width: usize,
+        height: usize,
+    ) -> Result<Box<dyn Presenter>, String> {
+        let size = window.inner_size();
+        let surface = Surface::create(window);
+        let surface_texture = SurfaceTexture::new(size.width, size.height, surface);
+
+        match Pixels::new(width as u32, height as u32, surface_texture) {
+            Ok(pixels) => Ok(Box::new(pixels)),
+            Err(err) => Err(format!("Failed to initialize the GPU renderer: {:?}", err)),
+        }
+    }
+
+    impl Presenter for Pixels {
+        fn get_frame(&mut self) -> &mut [u8] {
+            Pixels::get_frame(self)
+        }
+
+        fn render(&mut self) {
+            Pixels::render(self)
+        }
+
+        fn resize(&mut self, width: u32, height: u32) {
+            Pixels::resize(self, width, height)
+        }
+    }
+}
+
+#[cfg(not(feature = "gpu"))]
+mod gpu {
+    use super::Presenter;
+
+    use winit::window::Window;
+
+    pub fn create(
+        _window: &Window,
+        _width: usize,
+        _height: usize,
+    ) -> Result<Box<dyn Presenter>, String> {
+        Err(String::from(
+            "The GPU renderer was not enabled at build time",
+        ))
+    }
+}
+
+#[cfg(feature = "software")]
+mod software {
+    use super::{blit_scaled, Presenter, COLOR_WIDTH};
+
+    use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+    use raw_window_handle_04 as rwh;
+    use softbuffer::GraphicsContext;
+    use winit::window::Window;
+
+    pub fn create(
+        window: &Window,
+        width: usize,
+        height: usize,
+    ) -> Result<Box<dyn Presenter>, String> {
+        let handle = WindowHandle::new(window.raw_window_handle())
+            .ok_or_else(|| String::from("The software renderer does not support this platform"))?;
+
+        // The window outlives the presenter, since both are owned by the event loop closure and
+        // the presenter is created from it
+        let context = unsafe { GraphicsContext::new(handle) }
+            .map_err(|err| format!("Failed to initialize the software renderer: {}", err))?;
+        let size = window.inner_size();
+
+        Ok(Box::new(SoftwarePresenter {
+            context,
+            frame: vec![0; width * height * COLOR_WIDTH],
+            frame_width: width,
+            frame_height: height,
+            buffer: vec![],
+            width: size.width,
+            height: size.height,
+        }))
+    }
+
+    struct SoftwarePresenter {
+        context: GraphicsContext<WindowHandle>,
+        frame: Vec<u8>,
+        frame_width: usize,
+        frame_height: usize,
+        buffer: Vec<u32>,
+        width: u32,
+        height: u32,
+    }
+
+    impl Presenter for SoftwarePresenter {
+        fn get_frame(&mut self) -> &mut [u8] {
+            &mut self.frame
+        }
+
+        fn render(&mut self) {
+            let width = self.width.min(u16::MAX as u32) as usize;
+            let height = self.height.min(u16::MAX as u32) as usize;
+
+            if width == 0 || height == 0 {
+                return;
+            }
+
+            self.buffer.resize(width * height, 0);
+            let frame_size = (self.frame_width, self.frame_height);
+            blit_scaled(&self.frame, frame_size, &mut self.buffer, width, height);
+
+            self.context
+                .set_buffer(&self.buffer, width as u16, height as u16);
+        }
+
+        fn resize(&mut self, width: u32, height: u32) {
+            self.width = width;
+            self.height = height;
+        }
+    }
+
+    /// winit still hands out `raw-window-handle` 0.3 handles, while softbuffer expects 0.4 ones.
+    /// Both describe the same native handles, so they are translated field by field.
+    #[derive(Clone, Copy)]
+    struct WindowHandle(rwh::RawWindowHandle);
+
+    impl WindowHandle {
+        fn new(handle: RawWindowHandle) -> Option<Self> {
+            let handle = match handle {
+                #[cfg(target_os = "linux")]
+                RawWindowHandle::Xlib(xlib) => {
+                    let mut handle = rwh::XlibHandle::empty();
+                    handle.window = xlib.window;
+                    handle.display = xlib.display;
+                    rwh::RawWindowHandle::Xlib(handle)
+                }
+                #[cfg(target_os = "linux")]
+                RawWindowHandle::Wayland(wayland) => {
+                    let mut handle = rwh::WaylandHandle::empty();
+                    handle.surface = wayland.surface;
+                    handle.display = wayland.display;
+                    rwh::RawWindowHandle::Wayland(handle)
+                }
+                #[cfg(target_os = "windows")]
+                RawWindowHandle::Windows(windows) => {
+                    let mut handle = rwh::Win32Handle::empty();
+                    handle.hwnd = windows.hwnd;
+                    handle.hinstance = windows.hinstance;
+                    rwh::RawWindowHandle::Win32(handle)
+                }
+                #[cfg(target_os = "macos")]
+                RawWindowHandle::MacOS(macos) => {
+                    let mut handle = rwh::AppKitHandle::empty();
+                    handle.ns_window = macos.ns_window;
+                    handle.ns_view = macos.ns_view;
+                    rwh::RawWindowHandle::AppKit(handle)
+                }
+                _ => return None,
+            };
+
+            Some(WindowHandle(handle))
+        }
+    }
+
+    unsafe impl rwh::HasRawWindowHandle for WindowHandle {
+        fn raw_window_handle(&self) -> rwh::RawWindowHandle {
+            self.0
+        }
+    }
+}
+
+#[cfg(not(feature = "software"))]
+mod software {
+    use super::Presenter;
+
+    use winit::window::Window;
+
+    pub fn create(
+        _window: &Window,
+        _width: usize,
+        _height: usize,
+    ) -> Result<Box<dyn Presenter>, String> {
+        Err(String::from(
+            "The software renderer was not enabled at build time",
+        ))
+    }
+}
+
+/// Where a scaled frame lands inside a larger surface
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    /// The left edge of the frame, in surface pixels
+    pub x: usize,
+    /// The top edge of the frame, in surface pixels
+    pub y: usize,
+    /// How many surface pixels wide and tall each frame pixel is
+    pub scale: usize,
+}
+
+impl Viewport {
+    /// Scales a frame by the largest integer factor that fits a surface and centers it, leaving
+    /// black bars on the sides that do not fill up. Every frame pixel covers the same number of
+    /// surface pixels, so the image stays sharp and keeps its aspect ratio at any size.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame_width` - The width of the frame, e.g. `WIDTH`
+    /// * `frame_height` - The height of the frame, e.g. `HEIGHT`
+    /// * `width` - The width of the surface in physical pixels
+    /// * `height` - The height of the surface in physical pixels
+    pub fn fit(frame_width: usize, frame_height: usize, width: usize, height: usize) -> Self {
+        let scale = (width / frame_width).min(height / frame_height).max(1);
+
+        Self {
+            x: width.saturating_sub(frame_width * scale) / 2,
+            y: height.saturating_sub(frame_height * scale) / 2,
+            scale,
+        }
+    }
+
+    /// Returns the frame pixel under a surface position, if the position is inside the frame
+    ///
+    /// # Arguments
+    ///
+    /// * `frame_width` - The width of the frame
+    /// * `frame_height` - The height of the frame
+    /// * `x` - The horizontal surface position
+    /// * `y` - The vertical surface position
+    pub fn to_frame(
+        &self,
+        frame_width: usize,
+        frame_height: usize,
+        x: usize,
+        y: usize,
+    ) -> Option<(usize, usize)> {
+        let frame_x = x.checked_sub(self.x)? / self.scale;
+        let frame_y = y.checked_sub(self.y)? / self.scale;
+
+        if frame_x < frame_width && frame_y < frame_height {
+            Some((frame_x, frame_y))
+        } else {
+            None
+        }
+    }
+}
+
+/// Enlarges an RGBA frame to fit the destination as described by `Viewport::fit`, and converts
+/// it to the `0RGB` pixels expected by native window surfaces
+///
+/// # Arguments
+///
+/// * `frame` - The RGBA frame to draw
+/// * `frame_size` - The width and height of the frame
+/// * `dest` - The destination pixels, `width * height` long
+/// * `width` - The width of the destination in pixels
+/// * `height` - The height of the destination in pixels
+#[cfg_attr(not(feature = "software"), allow(dead_code))]
+fn blit_scaled(
+    frame: &[u8],
+    frame_size: (usize, usize),
+    dest: &mut [u32],
+    width: usize,
+    height: usize,
+) {
+    let (frame_width, frame_height) = frame_size;
+    let viewport = Viewport::fit(frame_width, frame_height, width, height);
+
+    for pixel in dest.iter_mut() {
+        *pixel = 0;
+    }
+
+    for (y, row) in dest.chunks_exact_mut(width).enumerate().skip(viewport.y) {
+        let source_y = (y - viewport.y) / viewport.scale;
+        if source_y >= frame_height {
+            break;
+        }
+
+        for (x, pixel) in row.iter_mut().enumerate().skip(viewport.x) {
+            let source_x = (x - viewport.x) / viewport.scale;
+            if source_x >= frame_width {
+                break;
+            }
+
+            let idx = ((source_y * frame_width) + source_x) * COLOR_WIDTH;
+            *pixel =
+                (frame[idx] as u32) << 16 | (frame[idx + 1] as u32) << 8 | frame[idx + 2] as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::{HEIGHT, WIDTH};
+
+    #[test]
+    fn test_blit_scaled_centers_frame() {
+        let mut frame = vec![0; WIDTH * HEIGHT * COLOR_WIDTH];
+        // Light the top-left pixel red
+        frame[0] = 0xFF;
+
+        // Room for a 2x scale with a 3 pixel border left and right and 1 pixel top and bottom
+        let (width, height) = (WIDTH * 2 + 6, HEIGHT * 2 + 2);
+        let mut dest = vec![0xDEAD; width * height];
+        blit_scaled(&frame, (WIDTH, HEIGHT), &mut dest, width, height);
+
+        let at = |x: usize, y: usize| dest[(y * width) + x];
+        assert_eq!(at(0, 0), 0);
+        assert_eq!(at(2, 1), 0);
+        assert_eq!(at(3, 1), 0xFF_0000);
+        assert_eq!(at(4, 2), 0xFF_0000);
+        assert_eq!(at(5, 1), 0);
+        assert_eq!(at(3, 3), 0);
+    }
+
+    #[test]
+    fn test_viewport_letterboxes() {
+        // A 16:9 surface fits the 2:1 frame 30 times, with bars above and below
+        let viewport = Viewport::fit(64, 32, 1920, 1080);
+        assert_eq!(
+            viewport,
+            Viewport {
+                x: 0,
+                y: 60,
+                scale: 30
+            }
+        );
+
+        // A 128x64 frame halves the scale on the same surface
+        assert_eq!(Viewport::fit(128, 64, 1920, 1080).scale, 15);
+
+        assert_eq!(viewport.to_frame(64, 32, 0, 60), Some((0, 0)));
+        assert_eq!(viewport.to_frame(64, 32, 1919, 1019), Some((63, 31)));
+        assert_eq!(viewport.to_frame(64, 32, 0, 59), None);
+        assert_eq!(viewport.to_frame(64, 32, 0, 1020), None);
+    }
+}
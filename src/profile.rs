@@ -0,0 +1,432 @@
+use crate::disasm::{Instruction, Syntax};
+use crate::symbols::Symbols;
+use crate::system::{System, TICKS_PER_FRAME};
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+/// How many of the most executed addresses the report lists
+const HOT_SPOTS: usize = 20;
+
+/// The largest loop, in bytes from its start to its backward jump, that is treated as polling
+/// the delay timer
+const WAIT_LOOP_SIZE: usize = 16;
+
+/// What an instruction spends its time on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Category {
+    /// Drawing sprites, `DXYN`
+    Draw,
+    /// Waiting for a key with `FX0A` or for the delay timer in a polling loop
+    Wait,
+    /// Everything else
+    Logic,
+}
+
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Draw => "draw",
+            Category::Wait => "wait",
+            Category::Logic => "logic",
+        }
+    }
+}
+
+/// Counts the instructions a program runs, by address, by opcode class and by subroutine.
+///
+/// Subroutines are told apart by the calls that entered them, kept on a stack that follows the
+/// system's own, so returns and programs that rewrite the stack pointer are accounted for. Each
+/// instruction is one cycle. A delay timer polling loop is a short loop that only reads `FX07` and
+/// compares it; it counts as waiting once it has been seen to jump back.
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    cycles: u64,
+    /// The opcode and execution count of every executed address
+    addresses: HashMap<usize, (u16, u64)>,
+    /// How often each opcode ran, grouped into classes for the report
+    opcodes: HashMap<u16, u64>,
+    categories: [u64; 3],
+    /// The entry points of the subroutines being run, starting with the program's
+    stack: Vec<usize>,
+    /// How many cycles were spent with each call stack
+    stacks: HashMap<Vec<usize>, u64>,
+    calls: HashMap<usize, u64>,
+    /// The target of the call that ran last, entered if the stack pointer grew
+    call: Option<usize>,
+    /// The start and end of every delay timer polling loop seen
+    wait_loops: Vec<(usize, usize)>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the instruction a system is about to run. Calls are followed into their
+    /// subroutines by comparing the stack with the previous instruction's, so none may be left
+    /// out.
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The system
+    pub fn record(&mut self, system: &System) {
+        let pc = system.get_pc();
+        let op = system.get_opcode(pc);
+        let depth = system.get_sp() + 1;
+
+        if self.stack.is_empty() {
+            self.stack.push(pc);
+        }
+        if let Some(target) = self.call.take().filter(|_| depth > self.stack.len()) {
+            self.stack.push(target);
+            *self.calls.entry(target).or_insert(0) += 1;
+        }
+        self.stack.truncate(depth);
+
+        self.cycles += 1;
+        self.addresses.entry(pc).or_insert((op, 0)).1 += 1;
+        *self.opcodes.entry(op).or_insert(0) += 1;
+        match self.stacks.get_mut(&self.stack[..]) {
+            Some(cycles) => *cycles += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+
+        let instruction = Instruction::decode(op);
+        let category = match instruction {
+            Some(Instruction::Draw(..)) => Category::Draw,
+            Some(Instruction::WaitKey(_)) => Category::Wait,
+            _ if self.is_waiting(pc) => Category::Wait,
+            _ => Category::Logic,
+        };
+        self.categories[category as usize] += 1;
+
+        match instruction {
+            Some(Instruction::Call(target)) => self.call = Some(target as usize),
+            Some(Instruction::Jump(target))
+                if is_polling_loop(system, target as usize, pc) && !self.is_waiting(pc) =>
+            {
+                self.wait_loops.push((target as usize, pc))
+            }
+            _ => {}
+        }
+    }
+
+    fn is_waiting(&self, pc: usize) -> bool {
+        self.wait_loops
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&pc))
+    }
+
+    /// Returns how many instructions were recorded
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Returns how many instructions were spent on a category
+    pub fn get_time(&self, category: Category) -> u64 {
+        self.categories[category as usize]
+    }
+
+    /// Returns how many times an address was executed
+    pub fn get_count(&self, address: usize) -> u64 {
+        self.addresses.get(&address).map_or(0, |(_, count)| *count)
+    }
+
+    /// Returns the cycles spent in each subroutine by its entry point, as the cycles spent in its
+    /// own code and the cycles including the subroutines it called
+    pub fn get_subroutines(&self) -> BTreeMap<usize, (u64, u64)> {
+        let mut subroutines = BTreeMap::new();
+
+        for (stack, cycles) in self.stacks.iter() {
+            for (idx, entry) in stack.iter().enumerate() {
+                // A recursive subroutine only counts once towards its total
+                if stack[..idx].contains(entry) {
+                    continue;
+                }
+                subroutines.entry(*entry).or_insert((0, 0)).1 += cycles;
+            }
+            if let Some(entry) = stack.last() {
+                subroutines.entry(*entry).or_insert((0, 0)).0 += cycles;
+            }
+        }
+
+        subroutines
+    }
+
+    /// Writes the report: time per category, then the most executed addresses, opcode classes
+    /// and subroutines, each sorted by cycles. Addresses with symbols are followed by their name.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where to write the report
+    /// * `symbols` - The names of addresses, which may be empty
+    pub fn write_report<W: Write>(&self, mut writer: W, symbols: &Symbols) -> io::Result<()> {
+        let name = |address: usize| match symbols.lookup(address) {
+            Some(name) => format!("  <{}>", name),
+            None => String::new(),
+        };
+        let percent = |count: u64| 100.0 * count as f64 / self.cycles.max(1) as f64;
+
+        writeln!(
+            writer,
+            "{} cycles, {} frames",
+            self.cycles,
+            self.cycles / TICKS_PER_FRAME as u64
+        )?;
+
+        writeln!(writer, "\nTime")?;
+        for category in [Category::Draw, Category::Wait, Category::Logic].iter() {
+            let count = self.get_time(*category);
+            writeln!(
+                writer,
+                "  {:<8} {:>12} {:>6.1}%",
+                category.name(),
+                count,
+                percent(count)
+            )?;
+        }
+
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by_key(|(address, (_, count))| (std::cmp::Reverse(*count), **address));
+
+        writeln!(writer, "\nHot spots")?;
+        writeln!(
+            writer,
+            "  {:<8} {:>12} {:>7}  Instruction",
+            "Address", "Count", "%"
+        )?;
+        for (address, (op, count)) in addresses.into_iter().take(HOT_SPOTS) {
+            let disassembly = match Instruction::decode(*op) {
+                Some(instruction) => {
+                    let target = symbols.lookup(*op as usize & 0x0FFF);
+                    instruction.format(Syntax::Cowgod, target.as_deref())
+                }
+                None => String::from("???"),
+            };
+            writeln!(
+                writer,
+                "  {:<#8x} {:>12} {:>6.1}%  {}{}",
+                address,
+                count,
+                percent(*count),
+                disassembly,
+                name(*address)
+            )?;
+        }
+
+        let mut classes: HashMap<String, u64> = HashMap::new();
+        for (op, count) in self.opcodes.iter() {
+            *classes.entry(opcode_class(*op)).or_insert(0) += count;
+        }
+        let mut classes: Vec<_> = classes.into_iter().collect();
+        classes.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+
+        writeln!(writer, "\nOpcode classes")?;
+        for (class, count) in classes {
+            writeln!(
+                writer,
+                "  {:<8} {:>12} {:>6.1}%",
+                class,
+                count,
+                percent(count)
+            )?;
+        }
+
+        let mut subroutines: Vec<_> = self.get_subroutines().into_iter().collect();
+        subroutines.sort_by_key(|(entry, (_, total))| (std::cmp::Reverse(*total), *entry));
+
+        writeln!(writer, "\nSubroutines")?;
+        writeln!(
+            writer,
+            "  {:<8} {:>8} {:>12} {:>7} {:>12} {:>7}",
+            "Address", "Calls", "Self", "%", "Total", "%"
+        )?;
+        for (entry, (own, total)) in subroutines {
+            writeln!(
+                writer,
+                "  {:<#8x} {:>8} {:>12} {:>6.1}% {:>12} {:>6.1}%{}",
+                entry,
+                self.calls.get(&entry).copied().unwrap_or(0),
+                own,
+                percent(own),
+                total,
+                percent(total),
+                name(entry)
+            )?;
+        }
+
+        writer.flush()
+    }
+
+    /// Writes the cycles of every call stack in the collapsed format read by flame graph tools,
+    /// one `outer;inner count` line per stack. Subroutines are named after their symbols, or
+    /// their address.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where to write the stacks
+    /// * `symbols` - The names of addresses, which may be empty
+    pub fn write_collapsed<W: Write>(&self, mut writer: W, symbols: &Symbols) -> io::Result<()> {
+        let mut stacks: Vec<(String, u64)> = self
+            .stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let names: Vec<String> = stack.iter().map(|entry| symbols.format(*entry)).collect();
+                (names.join(";"), *cycles)
+            })
+            .collect();
+        stacks.sort();
+
+        for (stack, cycles) in stacks {
+            writeln!(writer, "{} {}", stack, cycles)?;
+        }
+
+        writer.flush()
+    }
+}
+
+/// Returns whether the code from `start` to a backward jump at `end` only reads the delay
+/// timer until it reaches a value
+fn is_polling_loop(system: &System, start: usize, end: usize) -> bool {
+    use Instruction::*;
+
+    if start > end || end - start > WAIT_LOOP_SIZE {
+        return false;
+    }
+
+    let instructions: Vec<_> = (start..=end)
+        .step_by(2)
+        .map(|address| Instruction::decode(system.get_opcode(address)))
+        .collect();
+
+    instructions
+        .iter()
+        .any(|instruction| matches!(instruction, Some(LoadDelay(_))))
+        && instructions.iter().all(|instruction| {
+            matches!(
+                instruction,
+                Some(LoadDelay(_))
+                    | Some(SkipEqByte(..))
+                    | Some(SkipNeByte(..))
+                    | Some(SkipEqReg(..))
+                    | Some(SkipNeReg(..))
+                    | Some(LoadByte(..))
+                    | Some(LoadReg(..))
+                    | Some(And(..))
+                    | Some(Sub(..))
+                    | Some(SubN(..))
+                    | Some(Jump(_))
+            )
+        })
+}
+
+/// Names the class of an opcode like the instruction tables, e.g. `DXYN` or `8XY4`
+fn opcode_class(op: u16) -> String {
+    let op = match Instruction::decode(op) {
+        Some(instruction) => instruction.encode(),
+        None => return String::from("???"),
+    };
+    let group = op >> 12;
+
+    match group {
+        0x0 if op == 0x00E0 || op == 0x00EE => format!("{:04X}", op),
+        0x0 | 0x1 | 0x2 | 0xA | 0xB => format!("{:X}NNN", group),
+        0x3 | 0x4 | 0x6 | 0x7 | 0xC => format!("{:X}XNN", group),
+        0x5 | 0x9 => format!("{:X}XY0", group),
+        0x8 => format!("8XY{:X}", op & 0xF),
+        0xD => String::from("DXYN"),
+        _ => format!("{:X}X{:02X}", group, op & 0xFF),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(rom: Vec<u8>, cycles: usize) -> Profiler {
+        let mut system = System::new();
+        let mut profiler = Profiler::new();
+        system.write_rom(rom);
+
+        for _ in 0..cycles {
+            profiler.record(&system);
+            system.tick();
+        }
+
+        profiler
+    }
+
+    #[test]
+    fn test_subroutines() {
+        // 0x200: call 0x206, call 0x206, jump to self; 0x206: draw, call 0x20C, return;
+        // 0x20C: return
+        let profiler = profile(
+            vec![
+                0x22, 0x06, 0x22, 0x06, 0x12, 0x04, 0xD0, 0x01, 0x22, 0x0C, 0x00, 0xEE, 0x00, 0xEE,
+            ],
+            14,
+        );
+        let subroutines = profiler.get_subroutines();
+
+        assert_eq!(profiler.get_cycles(), 14);
+        assert_eq!(profiler.get_count(0x204), 4);
+        assert_eq!(subroutines[&0x200], (6, 14));
+        assert_eq!(subroutines[&0x206], (6, 8));
+        assert_eq!(subroutines[&0x20C], (2, 2));
+        assert_eq!(profiler.calls[&0x206], 2);
+        assert_eq!(profiler.get_time(Category::Draw), 2);
+
+        let mut collapsed = vec![];
+        profiler
+            .write_collapsed(&mut collapsed, &Symbols::new())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(collapsed).unwrap(),
+            "0x200 6\n0x200;0x206 6\n0x200;0x206;0x20c 2\n"
+        );
+
+        let symbols = Symbols::parse("0x200 main\n0x206 update\n").unwrap();
+        let mut collapsed = vec![];
+        profiler.write_collapsed(&mut collapsed, &symbols).unwrap();
+        assert_eq!(
+            String::from_utf8(collapsed).unwrap(),
+            "main 6\nmain;update 6\nmain;update;update+0x6 2\n"
+        );
+    }
+
+    #[test]
+    fn test_wait_loops() {
+        // 0x200: delay = 0x10; 0x204: v0 = delay, skip if v0 == 0, jump 0x204; 0x20A: wait key
+        let profiler = profile(
+            vec![
+                0x60, 0x10, 0xF0, 0x15, 0xF0, 0x07, 0x30, 0x00, 0x12, 0x04, 0xF1, 0x0A,
+            ],
+            100,
+        );
+
+        // The first pass through the loop is logic, until the jump shows it polls
+        assert_eq!(profiler.get_time(Category::Logic), 5);
+        assert_eq!(profiler.get_time(Category::Wait), 95);
+
+        let mut report = vec![];
+        profiler.write_report(&mut report, &Symbols::new()).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("100 cycles, 11 frames\n"));
+        assert!(report.contains("\n  FX07 "));
+        assert!(report.contains("  wait               95   95.0%"));
+    }
+
+    #[test]
+    fn test_opcode_class() {
+        assert_eq!(opcode_class(0xD123), "DXYN");
+        assert_eq!(opcode_class(0x8AB4), "8XY4");
+        assert_eq!(opcode_class(0x00EE), "00EE");
+        assert_eq!(opcode_class(0x0123), "0NNN");
+        assert_eq!(opcode_class(0x5AB1), "5XY0");
+        assert_eq!(opcode_class(0xF533), "FX33");
+        assert_eq!(opcode_class(0xE0FF), "???");
+    }
+}
@@ -0,0 +1,179 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The most ROMs remembered in the recent list
+pub const MAX_RECENT: usize = 10;
+
+/// Returns the directory the emulator keeps its settings in, `chip8` inside the platform's
+/// configuration directory, if one can be found
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+
+    base.map(|dir| dir.join("chip8"))
+}
+
+/// The most recently loaded ROMs, newest first. The list is stored as a text file with one path
+/// per line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecentRoms {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+    /// Returns the default location of the list, inside `config_dir`
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("recent.txt"))
+    }
+
+    /// Reads the list from a file. A missing file is an empty list.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file the list was saved to
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(Self::parse(&contents)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Parses the text form of the list, ignoring empty lines
+    ///
+    /// # Arguments
+    ///
+    /// * `contents` - One path per line, newest first
+    pub fn parse(contents: &str) -> Self {
+        let mut recent = Self::default();
+
+        for line in contents
+            .lines()
+            .rev()
+            .filter(|line| !line.trim().is_empty())
+        {
+            recent.add(line.trim());
+        }
+
+        recent
+    }
+
+    /// Writes the list to a file, creating its directory if needed
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to write the list to
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut contents = String::new();
+        for rom in self.paths.iter() {
+            contents.push_str(&rom.to_string_lossy());
+            contents.push('\n');
+        }
+
+        fs::write(path, contents)
+    }
+
+    /// Moves a ROM to the front of the list, dropping the oldest entry once there are more than
+    /// `MAX_RECENT`
+    ///
+    /// # Arguments
+    ///
+    /// * `rom` - The path of the ROM that was loaded
+    pub fn add<P: Into<PathBuf>>(&mut self, rom: P) {
+        let rom = rom.into();
+
+        self.paths.retain(|path| *path != rom);
+        self.paths.insert(0, rom);
+        self.paths.truncate(MAX_RECENT);
+    }
+
+    /// Returns the ROMs, newest first
+    pub fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Returns the ROM `step` places after `current` in the list, wrapping around at either end.
+    /// Positive steps go towards older ROMs. When `current` is not in the list, the newest ROM
+    /// is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `current` - The ROM currently running
+    /// * `step` - How far to move through the list
+    pub fn cycle(&self, current: &Path, step: isize) -> Option<&Path> {
+        if self.paths.is_empty() {
+            return None;
+        }
+
+        let len = self.paths.len() as isize;
+        let idx = match self.paths.iter().position(|path| path == current) {
+            Some(idx) => (idx as isize + step).rem_euclid(len),
+            None => 0,
+        };
+
+        Some(&self.paths[idx as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_moves_to_front() {
+        let mut recent = RecentRoms::parse("b.ch8\n\na.ch8\n");
+        recent.add("a.ch8");
+        recent.add("c.ch8");
+
+        assert_eq!(
+            recent.get_paths(),
+            &[
+                PathBuf::from("c.ch8"),
+                PathBuf::from("a.ch8"),
+                PathBuf::from("b.ch8"),
+            ]
+        );
+
+        for idx in 0..MAX_RECENT {
+            recent.add(format!("{}.ch8", idx));
+        }
+        assert_eq!(recent.get_paths().len(), MAX_RECENT);
+    }
+
+    #[test]
+    fn test_cycle_wraps() {
+        let recent = RecentRoms::parse("a.ch8\nb.ch8\nc.ch8");
+
+        assert_eq!(
+            recent.cycle(Path::new("a.ch8"), 1),
+            Some(Path::new("b.ch8"))
+        );
+        assert_eq!(
+            recent.cycle(Path::new("c.ch8"), 1),
+            Some(Path::new("a.ch8"))
+        );
+        assert_eq!(
+            recent.cycle(Path::new("a.ch8"), -1),
+            Some(Path::new("c.ch8"))
+        );
+        assert_eq!(
+            recent.cycle(Path::new("z.ch8"), 1),
+            Some(Path::new("a.ch8"))
+        );
+        assert_eq!(RecentRoms::default().cycle(Path::new("a.ch8"), 1), None);
+    }
+}
@@ -0,0 +1,519 @@
+use crate::palette::Palette;
+use crate::screenshot::scale_framebuffer;
+use crate::system::{System, HEIGHT, WIDTH};
+use crate::timing::FRAME_RATE;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// GIF limits LZW codes to 12 bits
+const MAX_LZW_CODE: u16 = 0xFFF;
+
+/// The container a recording is written in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// An animated, looping GIF using the active palette
+    Gif,
+    /// A raw YUV4MPEG2 (4:4:4) stream, understood by most encoders
+    Y4m,
+    /// Concatenated binary PPM images, for `image2pipe` style encoders
+    Ppm,
+}
+
+impl Format {
+    /// Parses a format name such as `gif`, `y4m` or `ppm`
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the format, case insensitive
+    pub fn parse(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "gif" => Some(Format::Gif),
+            "y4m" => Some(Format::Y4m),
+            "ppm" => Some(Format::Ppm),
+            _ => None,
+        }
+    }
+
+    /// Returns the file extension conventionally used for the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Gif => "gif",
+            Format::Y4m => "y4m",
+            Format::Ppm => "ppm",
+        }
+    }
+
+    /// Guesses the format of a recording from its file extension
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path the recording will be written to
+    pub fn from_path(path: &Path) -> Option<Format> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Format::parse)
+    }
+}
+
+/// How long each frame of a GIF is shown, in hundredths of a second. Most viewers show frames
+/// with shorter delays for a tenth of a second instead, so GIFs run at 50 fps.
+const GIF_DELAY: u16 = 2;
+
+/// Records the frames produced by a `System` into an animation or raw video stream
+pub struct Recorder {
+    writer: Box<dyn Write>,
+    format: Format,
+    palette: Palette,
+    /// The palette the stream started with, which GIFs keep as their global colors
+    initial_palette: Palette,
+    scale: usize,
+    frames: u64,
+}
+
+impl Recorder {
+    /// Starts a new recording and writes the stream header
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where the recording will be written
+    /// * `format` - The container to write
+    /// * `palette` - The colors used for unlit and lit pixels
+    /// * `scale` - The integer factor each CHIP-8 pixel is enlarged by
+    pub fn new(
+        writer: Box<dyn Write>,
+        format: Format,
+        palette: Palette,
+        scale: usize,
+    ) -> io::Result<Self> {
+        let mut recorder = Self {
+            writer,
+            format,
+            palette,
+            initial_palette: palette,
+            scale: scale.max(1),
+            frames: 0,
+        };
+
+        recorder.write_header()?;
+
+        Ok(recorder)
+    }
+
+    /// Starts a new recording at `path`, or on standard output when `path` is `-`
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file the recording will be written to
+    /// * `format` - The container to write
+    /// * `palette` - The colors used for unlit and lit pixels
+    /// * `scale` - The integer factor each CHIP-8 pixel is enlarged by
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        format: Format,
+        palette: Palette,
+        scale: usize,
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+        let writer: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(BufWriter::new(io::stdout()))
+        } else {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+
+        Self::new(writer, format, palette, scale)
+    }
+
+    /// Returns how many frames have been recorded so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Changes the colors of the frames recorded from now on, e.g. when another ROM is loaded
+    ///
+    /// # Arguments
+    ///
+    /// * `palette` - The colors used for unlit and lit pixels
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Appends the current framebuffer of a system to the recording
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The system to capture
+    pub fn record_frame(&mut self, system: &System) -> io::Result<()> {
+        match self.format {
+            Format::Gif => self.write_gif_frame(system)?,
+            Format::Y4m => self.write_y4m_frame(system)?,
+            Format::Ppm => self.write_ppm_frame(system)?,
+        }
+
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    /// Terminates the stream and flushes everything to the underlying writer
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.format == Format::Gif {
+            self.writer.write_all(&[0x3B])?;
+        }
+
+        self.writer.flush()
+    }
+
+    fn width(&self) -> usize {
+        WIDTH * self.scale
+    }
+
+    fn height(&self) -> usize {
+        HEIGHT * self.scale
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let (width, height) = (self.width(), self.height());
+
+        match self.format {
+            Format::Gif => {
+                self.writer.write_all(b"GIF89a")?;
+                self.writer.write_all(&(width as u16).to_le_bytes())?;
+                self.writer.write_all(&(height as u16).to_le_bytes())?;
+                // Global color table of 2 entries, 8 bits per primary
+                self.writer.write_all(&[0xF0, 0x00, 0x00])?;
+                self.writer.write_all(&self.palette.background)?;
+                self.writer.write_all(&self.palette.foreground)?;
+
+                // Loop forever
+                self.writer.write_all(&[0x21, 0xFF, 0x0B])?;
+                self.writer.write_all(b"NETSCAPE2.0")?;
+                self.writer.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])
+            }
+            Format::Y4m => writeln!(
+                self.writer,
+                "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                width, height, FRAME_RATE
+            ),
+            Format::Ppm => Ok(()),
+        }
+    }
+
+    fn write_gif_frame(&mut self, system: &System) -> io::Result<()> {
+        let (width, height) = (self.width(), self.height());
+
+        // GIF delays are in hundredths of a second, and viewers slow down shorter delays than
+        // `GIF_DELAY`, so a frame is only written once per delay and the rest are dropped
+        let slot = |frames: u64| frames * 100 / (FRAME_RATE as u64 * GIF_DELAY as u64);
+        if self.frames > 0 && slot(self.frames) == slot(self.frames - 1) {
+            return Ok(());
+        }
+
+        self.writer.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        self.writer.write_all(&GIF_DELAY.to_le_bytes())?;
+        self.writer.write_all(&[0x00, 0x00])?;
+
+        self.writer.write_all(&[0x2C, 0x00, 0x00, 0x00, 0x00])?;
+        self.writer.write_all(&(width as u16).to_le_bytes())?;
+        self.writer.write_all(&(height as u16).to_le_bytes())?;
+        if self.palette == self.initial_palette {
+            self.writer.write_all(&[0x00])?;
+        } else {
+            // A local color table of 2 entries replaces the global one for this frame
+            self.writer.write_all(&[0x80])?;
+            self.writer.write_all(&self.palette.background)?;
+            self.writer.write_all(&self.palette.foreground)?;
+        }
+
+        let mut indices = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                indices.push(system.get_pixel(x / self.scale, y / self.scale) as u8);
+            }
+        }
+
+        let min_code_size = 2;
+        self.writer.write_all(&[min_code_size])?;
+        for block in lzw_encode(&indices, min_code_size).chunks(255) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+        self.writer.write_all(&[0x00])
+    }
+
+    fn write_y4m_frame(&mut self, system: &System) -> io::Result<()> {
+        let rgb = scale_framebuffer(system, &self.palette, self.scale);
+        let pixels = rgb.len() / 3;
+        let mut planes = vec![0; pixels * 3];
+
+        for (idx, color) in rgb.chunks(3).enumerate() {
+            let (y, u, v) = rgb_to_yuv(color);
+            planes[idx] = y;
+            planes[pixels + idx] = u;
+            planes[(pixels * 2) + idx] = v;
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&planes)
+    }
+
+    fn write_ppm_frame(&mut self, system: &System) -> io::Result<()> {
+        let rgb = scale_framebuffer(system, &self.palette, self.scale);
+
+        write!(self.writer, "P6\n{} {}\n255\n", self.width(), self.height())?;
+        self.writer.write_all(&rgb)
+    }
+}
+
+/// Converts an RGB color into studio-swing BT.601 YUV
+///
+/// # Arguments
+///
+/// * `color` - The RGB color to convert
+fn rgb_to_yuv(color: &[u8]) -> (u8, u8, u8) {
+    let (r, g, b) = (color[0] as i32, color[1] as i32, color[2] as i32);
+
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+
+    (y as u8, u as u8, v as u8)
+}
+
+/// Compresses color indices with the variable-width LZW scheme used by GIF image data
+///
+/// # Arguments
+///
+/// * `indices` - The color index of every pixel in the image
+/// * `min_code_size` - The number of bits needed to represent every color index
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear_code = 1u16 << min_code_size;
+    let end_code = clear_code + 1;
+
+    let mut output = BitWriter::new();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut code_size = min_code_size + 1;
+    let mut next_code = end_code + 1;
+
+    output.write(clear_code, code_size);
+
+    let mut pixels = indices.iter();
+    let mut current = match pixels.next() {
+        Some(index) => *index as u16,
+        None => {
+            output.write(end_code, code_size);
+            return output.finish();
+        }
+    };
+
+    for &index in pixels {
+        if let Some(&code) = table.get(&(current, index)) {
+            current = code;
+            continue;
+        }
+
+        output.write(current, code_size);
+
+        if next_code > MAX_LZW_CODE {
+            output.write(clear_code, code_size);
+            table.clear();
+            code_size = min_code_size + 1;
+            next_code = end_code + 1;
+        } else {
+            table.insert((current, index), next_code);
+            if next_code == 1 << code_size {
+                code_size += 1;
+            }
+            next_code += 1;
+        }
+
+        current = index as u16;
+    }
+
+    output.write(current, code_size);
+    output.write(end_code, code_size);
+    output.finish()
+}
+
+/// Packs variable-width codes least significant bit first
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: vec![],
+            buffer: 0,
+            bits: 0,
+        }
+    }
+
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A writer that can still be inspected after being boxed into a `Recorder`
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Reference GIF LZW decoder used to verify the encoder
+    fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear_code = 1u16 << min_code_size;
+        let end_code = clear_code + 1;
+        let reset = || -> Vec<Vec<u8>> { (0..clear_code + 2).map(|c| vec![c as u8]).collect() };
+
+        let mut table = reset();
+        let mut code_size = min_code_size + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut output = vec![];
+        let (mut buffer, mut bits, mut bytes) = (0u32, 0u8, data.iter());
+
+        loop {
+            while bits < code_size {
+                buffer |= (*bytes.next().unwrap() as u32) << bits;
+                bits += 8;
+            }
+            let code = (buffer & ((1 << code_size) - 1)) as u16;
+            buffer >>= code_size;
+            bits -= code_size;
+
+            if code == clear_code {
+                table = reset();
+                code_size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end_code {
+                return output;
+            }
+
+            let entry = match (table.get(code as usize), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => {
+                    let mut entry = previous.clone();
+                    entry.push(previous[0]);
+                    entry
+                }
+                (None, None) => panic!("invalid code"),
+            };
+
+            if let Some(mut previous) = previous {
+                previous.push(entry[0]);
+                table.push(previous);
+                if table.len() == 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            }
+
+            output.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        let indices: Vec<u8> = (0..20_000u32)
+            .map(|i| ((i * 7919) % 13 < 5) as u8)
+            .collect();
+
+        assert_eq!(lzw_decode(&lzw_encode(&indices, 2), 2), indices);
+    }
+
+    #[test]
+    fn test_gif_frames() {
+        let buffer = SharedBuffer::default();
+        let mut recorder =
+            Recorder::new(Box::new(buffer.clone()), Format::Gif, Palette::default(), 1).unwrap();
+        let system = System::new();
+
+        for _ in 0..6 {
+            recorder.record_frame(&system).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let gif = buffer.0.borrow();
+        assert_eq!(&gif[0..6], b"GIF89a");
+        assert_eq!(gif[6..8], (WIDTH as u16).to_le_bytes());
+        assert_eq!(gif[8..10], (HEIGHT as u16).to_le_bytes());
+        assert_eq!(gif.last(), Some(&0x3B));
+
+        // Six frames at 60 fps take a tenth of a second, shown as five frames at 50 fps
+        let delays: Vec<u16> = gif
+            .windows(6)
+            .filter(|window| window[0..3] == [0x21, 0xF9, 0x04])
+            .map(|window| u16::from_le_bytes([window[4], window[5]]))
+            .collect();
+        assert_eq!(delays, [2; 5]);
+    }
+
+    #[test]
+    fn test_gif_palette_change() {
+        let buffer = SharedBuffer::default();
+        let mut recorder =
+            Recorder::new(Box::new(buffer.clone()), Format::Gif, Palette::default(), 1).unwrap();
+        let system = System::new();
+        let green = Palette::new([0x00, 0x20, 0x00], [0x33, 0xFF, 0x66]);
+
+        recorder.record_frame(&system).unwrap();
+        recorder.set_palette(green);
+        for _ in 0..2 {
+            recorder.record_frame(&system).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        // The frame after the change carries its own color table
+        let table = [0x80, 0x00, 0x20, 0x00, 0x33, 0xFF, 0x66];
+        let gif = buffer.0.borrow();
+        assert_eq!(gif.windows(table.len()).filter(|w| *w == table).count(), 1);
+    }
+
+    #[test]
+    fn test_y4m_frame_size() {
+        let buffer = SharedBuffer::default();
+        let mut recorder =
+            Recorder::new(Box::new(buffer.clone()), Format::Y4m, Palette::default(), 2).unwrap();
+
+        recorder.record_frame(&System::new()).unwrap();
+        recorder.finish().unwrap();
+
+        let header = "YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\nFRAME\n";
+        assert_eq!(buffer.0.borrow().len(), header.len() + (128 * 64 * 3));
+    }
+}
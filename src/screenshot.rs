@@ -0,0 +1,331 @@
+use crate::osd;
+use crate::palette::Palette;
+use crate::system::{System, HEIGHT, WIDTH};
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// The largest amount of data a single stored (uncompressed) deflate block can hold
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+impl System {
+    /// Encodes the current framebuffer as a PNG image
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where the encoded PNG will be written
+    /// * `palette` - The colors used for unlit and lit pixels
+    /// * `scale` - The integer factor each CHIP-8 pixel is enlarged by
+    pub fn write_screenshot<W: Write>(
+        &self,
+        writer: W,
+        palette: &Palette,
+        scale: usize,
+    ) -> io::Result<()> {
+        let scale = scale.max(1);
+        let rgb = scale_framebuffer(self, palette, scale);
+
+        encode_png(writer, WIDTH * scale, HEIGHT * scale, &rgb)
+    }
+
+    /// Saves the current framebuffer to a timestamped PNG file and returns its path
+    ///
+    /// # Arguments
+    ///
+    /// * `directory` - The directory the screenshot will be created in
+    /// * `palette` - The colors used for unlit and lit pixels
+    /// * `scale` - The integer factor each CHIP-8 pixel is enlarged by
+    pub fn save_screenshot<P: AsRef<Path>>(
+        &self,
+        directory: P,
+        palette: &Palette,
+        scale: usize,
+    ) -> io::Result<PathBuf> {
+        let path = directory
+            .as_ref()
+            .join(format!("chip8-{}.png", timestamp(SystemTime::now())));
+        let file = File::create(&path)?;
+
+        self.write_screenshot(BufWriter::new(file), palette, scale)?;
+
+        Ok(path)
+    }
+}
+
+/// Converts the framebuffer of a system into tightly packed RGB rows, enlarging every pixel
+///
+/// # Arguments
+///
+/// * `system` - The system to read the framebuffer from
+/// * `palette` - The colors used for unlit and lit pixels
+/// * `scale` - The integer factor each CHIP-8 pixel is enlarged by
+pub(crate) fn scale_framebuffer(system: &System, palette: &Palette, scale: usize) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(WIDTH * HEIGHT * scale * scale * 3);
+
+    for y in 0..HEIGHT * scale {
+        for x in 0..WIDTH * scale {
+            rgb.extend_from_slice(&palette.color(system.get_pixel(x / scale, y / scale)));
+        }
+    }
+
+    rgb
+}
+
+/// Writes an 8-bit RGB PNG. The image data is stored without compression, which keeps the encoder
+/// small and dependency free while still producing files every viewer understands.
+///
+/// # Arguments
+///
+/// * `writer` - Where the encoded PNG will be written
+/// * `width` - The width of the image in pixels
+/// * `height` - The height of the image in pixels
+/// * `rgb` - Tightly packed RGB rows, `width * height * 3` bytes long
+pub fn encode_png<W: Write>(
+    mut writer: W,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+) -> io::Result<()> {
+    assert_eq!(rgb.len(), width * height * 3);
+
+    writer.write_all(&PNG_SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth 8, truecolor, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut writer, b"IHDR", &header)?;
+
+    // Every scanline is prefixed with its filter type, which is always "none" here
+    let mut scanlines = Vec::with_capacity(rgb.len() + height);
+    for row in rgb.chunks(width * 3) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+    write_chunk(&mut writer, b"IDAT", &zlib_stored(&scanlines))?;
+
+    write_chunk(&mut writer, b"IEND", &[])?;
+    writer.flush()
+}
+
+/// An RGB image for exporting as a PNG, with positions and sizes in units of `scale` pixels
+pub(crate) struct Image {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) rgb: Vec<u8>,
+    scale: usize,
+}
+
+impl Image {
+    /// Creates an image filled with one color
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - The width in units
+    /// * `height` - The height in units
+    /// * `scale` - How many pixels wide and tall a unit is
+    /// * `color` - The color to fill the image with
+    pub(crate) fn new(width: usize, height: usize, scale: usize, color: [u8; 3]) -> Self {
+        let (width, height) = (width * scale, height * scale);
+
+        Self {
+            width,
+            height,
+            rgb: color.repeat(width * height),
+            scale,
+        }
+    }
+
+    pub(crate) fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+        for y in y * self.scale..(y + height) * self.scale {
+            for x in x * self.scale..(x + width) * self.scale {
+                let idx = (y * self.width + x) * 3;
+                self.rgb[idx..idx + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Draws text in the glyphs of the on-screen display
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - The left edge of the text
+    /// * `y` - The top edge of the text
+    /// * `text` - The text to draw
+    /// * `size` - How many units wide and tall a pixel of the glyphs is
+    /// * `color` - The color of the text
+    pub(crate) fn text(&mut self, x: usize, y: usize, text: &str, size: usize, color: [u8; 3]) {
+        for (idx, c) in text.chars().enumerate() {
+            for (row, bits) in osd::glyph(c).iter().enumerate() {
+                for col in 0..4 {
+                    if bits & (0x80 >> col) != 0 {
+                        let x = x + (idx * osd::GLYPH_ADVANCE + col) * size;
+                        self.fill(x, y + row * size, size, size, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Writes a single length-prefixed, CRC-terminated PNG chunk
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+    writer.write_all(&crc.finish().to_be_bytes())
+}
+
+/// Wraps data in a zlib stream made of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = (data.len() / MAX_STORED_BLOCK) + 1;
+    let mut out = Vec::with_capacity(data.len() + (blocks * 5) + 6);
+
+    // CMF/FLG for deflate with a 32K window and no preset dictionary
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+
+        out.push(if last { 0x01 } else { 0x00 });
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+
+    (b << 16) | a
+}
+
+/// Incremental CRC-32 (ISO-HDLC), as used by PNG chunks and gzip
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32(0xFFFF_FFFF)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 ^= *byte as u32;
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Formats a point in time as `YYYYMMDD-HHMMSS-mmm` in UTC, suitable for file names
+///
+/// # Arguments
+///
+/// * `time` - The time to format
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let days = (secs / 86400) as i64;
+    let secs_of_day = secs % 86400;
+
+    // Convert days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        (secs_of_day / 60) % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_crc32() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        assert_eq!(timestamp(time), "20231114-221320-123");
+    }
+
+    #[test]
+    fn test_screenshot_header() {
+        let system = System::new();
+        let mut png = vec![];
+
+        system
+            .write_screenshot(&mut png, &Palette::default(), 4)
+            .unwrap();
+
+        assert_eq!(png[0..8], PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..20], (WIDTH as u32 * 4).to_be_bytes());
+        assert_eq!(png[20..24], (HEIGHT as u32 * 4).to_be_bytes());
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+}
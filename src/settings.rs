@@ -0,0 +1,251 @@
+use crate::cli::Options;
+use crate::keymap::Keymap;
+use crate::palette::Palette;
+use crate::system::{Quirks, Variant};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Metadata and settings kept next to a ROM and applied whenever it is loaded, so games with
+/// unusual controls or colors do not need command line options every time.
+///
+/// They are read from a file named like the ROM with a `.cfg` extension, holding `key = value`
+/// lines. Empty lines and anything after a `#` are ignored.
+///
+/// ```text
+/// # breakout.cfg
+/// title = Breakout
+/// keymap = x123qweasdzc4rfv
+/// palette = 000000:33FF66
+/// variant = schip
+/// ```
+///
+/// `variant` takes the names `--variant` does, and `quirks` a list like `--quirks`, which
+/// replaces the variant's quirks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RomSettings {
+    pub title: Option<String>,
+    pub keymap: Option<Keymap>,
+    pub palette: Option<Palette>,
+    pub variant: Option<Variant>,
+    pub quirks: Option<Quirks>,
+}
+
+impl RomSettings {
+    /// Returns the settings file belonging to a ROM
+    ///
+    /// # Arguments
+    ///
+    /// * `rom` - The path of the ROM
+    pub fn path_for(rom: &Path) -> PathBuf {
+        rom.with_extension("cfg")
+    }
+
+    /// Reads the settings belonging to a ROM. A ROM without a settings file has default settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `rom` - The path of the ROM
+    pub fn load(rom: &Path) -> io::Result<Self> {
+        let path = Self::path_for(rom);
+
+        match fs::read_to_string(&path) {
+            Ok(source) => Self::parse(&source).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), err),
+                )
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Writes the settings next to a ROM, replacing its settings file. Comments in the file are
+    /// not kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `rom` - The path of the ROM
+    pub fn save(&self, rom: &Path) -> io::Result<()> {
+        fs::write(Self::path_for(rom), self.to_string())
+    }
+
+    /// Parses the text of a settings file
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The text of the file
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut settings = Self::default();
+
+        for (idx, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |message: String| format!("line {}: {}", idx + 1, message);
+            let (key, value) = match line.find('=') {
+                Some(split) => (line[..split].trim(), line[split + 1..].trim()),
+                None => return Err(error(String::from("expected `key = value`"))),
+            };
+
+            match key {
+                "title" => settings.title = Some(value.to_string()),
+                "keymap" => {
+                    settings.keymap = Some(
+                        Keymap::parse(value)
+                            .ok_or_else(|| error(format!("invalid keymap '{}'", value)))?,
+                    )
+                }
+                "palette" => {
+                    settings.palette = Some(
+                        Palette::parse(value)
+                            .ok_or_else(|| error(format!("invalid palette '{}'", value)))?,
+                    )
+                }
+                "variant" => {
+                    settings.variant = Some(
+                        Variant::parse(value)
+                            .ok_or_else(|| error(format!("unknown variant '{}'", value)))?,
+                    )
+                }
+                "quirks" => {
+                    settings.quirks = Some(
+                        Quirks::parse(value)
+                            .ok_or_else(|| error(format!("invalid quirks '{}'", value)))?,
+                    )
+                }
+                _ => return Err(error(format!("unknown setting '{}'", key))),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// Returns the title to show for a ROM, falling back to its file name
+    ///
+    /// # Arguments
+    ///
+    /// * `rom` - The path of the ROM
+    pub fn title_for(&self, rom: &Path) -> String {
+        match self.title.as_ref() {
+            Some(title) => title.clone(),
+            None => rom
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns the options with these settings taking precedence
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - The options given on the command line
+    pub fn apply(&self, options: &Options) -> Options {
+        let mut options = options.clone();
+
+        if let Some(keymap) = self.keymap {
+            options.keymap = keymap;
+        }
+        if let Some(palette) = self.palette {
+            options.palette = palette;
+        }
+        if let Some(quirks) = self
+            .quirks
+            .or_else(|| self.variant.map(|variant| variant.get_quirks()))
+        {
+            options.quirks = quirks;
+        }
+
+        options
+    }
+}
+
+/// Writes the text of a settings file, which `RomSettings::parse` reads back
+impl fmt::Display for RomSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(title) = self.title.as_ref() {
+            writeln!(f, "title = {}", title)?;
+        }
+        if let Some(keymap) = self.keymap {
+            writeln!(f, "keymap = {}", keymap)?;
+        }
+        if let Some(palette) = self.palette {
+            writeln!(f, "palette = {}", palette)?;
+        }
+        if let Some(variant) = self.variant {
+            writeln!(f, "variant = {}", variant)?;
+        }
+        if let Some(quirks) = self.quirks {
+            writeln!(f, "quirks = {}", quirks)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let settings = RomSettings::parse(
+            "# Pong\ntitle = Pong (1 player)\n\npalette = 101010:33ff66 # green\n",
+        )
+        .unwrap();
+
+        assert_eq!(settings.title, Some(String::from("Pong (1 player)")));
+        assert_eq!(
+            settings.palette,
+            Some(Palette::new([0x10, 0x10, 0x10], [0x33, 0xFF, 0x66]))
+        );
+        assert_eq!(settings.keymap, None);
+
+        assert!(RomSettings::parse("speed = 2").is_err());
+        assert!(RomSettings::parse("keymap = abc").is_err());
+        assert!(RomSettings::parse("quirks = fast").is_err());
+        assert!(RomSettings::parse("title").is_err());
+    }
+
+    #[test]
+    fn test_save_round_trip() {
+        let dir = std::env::temp_dir().join(format!("chip8-settings-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("pong.ch8");
+
+        let settings = RomSettings {
+            title: Some(String::from("Pong")),
+            keymap: Keymap::parse("x123qweasdzc4rfv"),
+            palette: Some(Palette::new([0x10, 0x10, 0x10], [0x33, 0xFF, 0x66])),
+            variant: Some(Variant::Schip),
+            quirks: Quirks::parse("keep-i"),
+        };
+        settings.save(&rom).unwrap();
+        let loaded = RomSettings::load(&rom);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.unwrap(), settings);
+        assert_eq!(RomSettings::default().to_string(), "");
+    }
+
+    #[test]
+    fn test_apply_and_title() {
+        let settings = RomSettings::parse("keymap = x123qweasdzc4rfv\nvariant = chip8").unwrap();
+        let options = settings.apply(&Options::default());
+
+        assert_eq!(options.keymap.char_for(0), 'x');
+        assert_eq!(options.palette, Palette::default());
+        assert_eq!(options.quirks, Variant::Chip8.get_quirks());
+        assert_eq!(settings.title_for(Path::new("roms/pong.ch8")), "pong");
+        assert_eq!(
+            RomSettings::path_for(Path::new("roms/pong.ch8")),
+            PathBuf::from("roms/pong.cfg")
+        );
+    }
+}
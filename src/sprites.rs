@@ -0,0 +1,396 @@
+use crate::osd;
+use crate::palette::Palette;
+use crate::screenshot::{encode_png, Image};
+use crate::system::{System, TICKS_PER_FRAME};
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// The number of addresses whose draws are recorded, the whole of memory
+const MEMORY_SIZE: usize = 4096;
+
+/// The color of the lines between sprites and behind the address labels
+const GRID: [u8; 3] = [0x30, 0x30, 0x30];
+
+/// The color of the addresses labelling each row of a sheet
+const LABEL: [u8; 3] = [0xC0, 0xC0, 0xC0];
+
+/// The colors lit and unlit pixels fade from when their row was just drawn
+const HIGHLIGHT: [u8; 3] = [0xFF, 0xA0, 0x00];
+const HIGHLIGHT_DIM: [u8; 3] = [0x50, 0x28, 0x00];
+
+/// How many image pixels make up a pixel of the address labels
+const LABEL_SCALE: usize = 2;
+
+/// Image pixels between sprites
+const GAP: usize = 2;
+
+/// How sprites are laid out in memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// 8 pixels wide and the given number of rows tall, a byte per row, as drawn by `DXYN`
+    Narrow(usize),
+    /// 16 by 16 pixels, two bytes per row, as drawn by SCHIP's `DXY0`. This emulator runs
+    /// `DXY0` as a draw of no rows, so these are only laid out, never recorded as drawn.
+    Wide,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Narrow(8)
+    }
+}
+
+impl Layout {
+    /// Returns the width of a sprite in pixels
+    pub fn get_width(&self) -> usize {
+        match self {
+            Layout::Narrow(_) => 8,
+            Layout::Wide => 16,
+        }
+    }
+
+    /// Returns the height of a sprite in pixels
+    pub fn get_height(&self) -> usize {
+        match self {
+            Layout::Narrow(rows) => *rows,
+            Layout::Wide => 16,
+        }
+    }
+
+    /// Returns how many bytes of memory a sprite takes
+    pub fn get_size(&self) -> usize {
+        self.get_height() * self.get_width() / 8
+    }
+}
+
+/// Records the memory `DXYN` instructions draw from and the frame they last did, so the bytes a
+/// program uses as sprites can be told apart from the rest of its memory.
+///
+/// The frame is worked out from the number of calls to `record`, so it has to see every
+/// instruction the system runs. Only the bytes the interpreter reads are recorded, so `DXY0`,
+/// which draws no rows, records nothing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrawLog {
+    frames: Vec<Option<u64>>,
+    /// The addresses `I` pointed at for draws and the most bytes drawn from each
+    targets: BTreeMap<usize, usize>,
+    cycles: u64,
+}
+
+impl Default for DrawLog {
+    fn default() -> Self {
+        Self {
+            frames: vec![None; MEMORY_SIZE],
+            targets: BTreeMap::new(),
+            cycles: 0,
+        }
+    }
+}
+
+impl DrawLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the instruction a system is about to run, if it draws
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The system
+    pub fn record(&mut self, system: &System) {
+        let frame = self.get_frame();
+        self.cycles += 1;
+
+        let op = system.get_opcode(system.get_pc());
+        if op & 0xF000 != 0xD000 {
+            return;
+        }
+
+        let len = op as usize & 0x000F;
+        if len == 0 {
+            return;
+        }
+        let start = system.get_i() as usize;
+
+        for address in start..(start + len).min(MEMORY_SIZE) {
+            self.frames[address] = Some(frame);
+        }
+        let drawn = self.targets.entry(start).or_insert(0);
+        *drawn = (*drawn).max(len);
+    }
+
+    /// Returns the frame being run, counted from `0`
+    pub fn get_frame(&self) -> u64 {
+        self.cycles / TICKS_PER_FRAME as u64
+    }
+
+    /// Returns how many frames ago an address was last drawn from, `0` for the current frame
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address
+    pub fn get_age(&self, address: usize) -> Option<u64> {
+        let frame = self.frames.get(address).copied().flatten()?;
+        Some(self.get_frame() - frame)
+    }
+
+    /// Returns the addresses draws started at, with the most bytes drawn from each
+    pub fn get_targets(&self) -> &BTreeMap<usize, usize> {
+        &self.targets
+    }
+}
+
+/// How memory is drawn as a sheet of sprites, see `Sheet::render`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sheet {
+    pub layout: Layout,
+    /// How many sprites make up a row of the sheet
+    pub columns: usize,
+    /// How many image pixels make up a pixel of a sprite
+    pub scale: usize,
+    pub palette: Palette,
+    /// How many frames rows drawn by the program stay highlighted for
+    pub recent: u64,
+}
+
+impl Default for Sheet {
+    fn default() -> Self {
+        Self {
+            layout: Layout::default(),
+            columns: 16,
+            scale: 4,
+            palette: Palette::default(),
+            recent: 60,
+        }
+    }
+}
+
+impl Sheet {
+    /// Renders memory as a grid of sprites with the address of each row's first sprite beside
+    /// it. Rows of sprites the program drew from in the last `recent` frames are highlighted,
+    /// fading with age. Returns the width, height and RGB pixels of the image.
+    ///
+    /// # Arguments
+    ///
+    /// * `memory` - All of memory
+    /// * `start` - The address of the first sprite
+    /// * `end` - The address after the last byte shown
+    /// * `log` - The draws to highlight
+    pub fn render(
+        &self,
+        memory: &[u8],
+        start: usize,
+        end: usize,
+        log: &DrawLog,
+    ) -> (usize, usize, Vec<u8>) {
+        let size = self.layout.get_size();
+        let count = end.saturating_sub(start).div_ceil(size);
+        let rows = count.div_ceil(self.columns).max(1);
+
+        let sprite_width = self.layout.get_width() * self.scale;
+        let sprite_height = self.layout.get_height() * self.scale;
+        let label_width = (3 * osd::GLYPH_ADVANCE + 1) * LABEL_SCALE;
+        let row_height = sprite_height.max(osd::GLYPH_HEIGHT * LABEL_SCALE) + GAP;
+
+        let mut image = Image::new(
+            label_width + self.columns * (sprite_width + GAP) + GAP,
+            rows * row_height + GAP,
+            1,
+            GRID,
+        );
+
+        for idx in 0..count {
+            let address = start + idx * size;
+            let (row, column) = (idx / self.columns, idx % self.columns);
+            let y = GAP + row * row_height;
+
+            if column == 0 {
+                image.text(GAP, y, &format!("{:03X}", address), LABEL_SCALE, LABEL);
+            }
+
+            let x = label_width + GAP + column * (sprite_width + GAP);
+            self.draw_sprite(&mut image, x, y, memory, address..end, Some(log));
+        }
+
+        (image.width, image.height, image.rgb)
+    }
+
+    /// Renders the sheet as a PNG, see `render`
+    pub fn write_png<W: Write>(
+        &self,
+        writer: W,
+        memory: &[u8],
+        start: usize,
+        end: usize,
+        log: &DrawLog,
+    ) -> io::Result<()> {
+        let (width, height, rgb) = self.render(memory, start, end, log);
+
+        encode_png(writer, width, height, &rgb)
+    }
+
+    /// Writes a single sprite as a PNG in the colors of the palette, without highlights or
+    /// borders, e.g. to edit it
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where to write the image
+    /// * `memory` - All of memory
+    /// * `address` - The address of the sprite
+    pub fn write_sprite_png<W: Write>(
+        &self,
+        writer: W,
+        memory: &[u8],
+        address: usize,
+    ) -> io::Result<()> {
+        let mut image = Image::new(
+            self.layout.get_width() * self.scale,
+            self.layout.get_height() * self.scale,
+            1,
+            self.palette.background,
+        );
+        let end = address + self.layout.get_size();
+        self.draw_sprite(&mut image, 0, 0, memory, address..end, None);
+
+        encode_png(writer, image.width, image.height, &image.rgb)
+    }
+
+    /// Draws the sprite at the start of `bytes`, leaving pixels past their end unlit
+    fn draw_sprite(
+        &self,
+        image: &mut Image,
+        x: usize,
+        y: usize,
+        memory: &[u8],
+        bytes: std::ops::Range<usize>,
+        log: Option<&DrawLog>,
+    ) {
+        let row_bytes = self.layout.get_width() / 8;
+
+        for row in 0..self.layout.get_height() {
+            let address = bytes.start + row * row_bytes;
+            let freshness = log.map_or(0.0, |log| {
+                (address..address + row_bytes)
+                    .filter_map(|address| log.get_age(address))
+                    .min()
+                    .filter(|age| *age < self.recent)
+                    .map_or(0.0, |age| 1.0 - age as f32 / self.recent as f32)
+            });
+            let lit = blend(self.palette.foreground, HIGHLIGHT, freshness);
+            let unlit = blend(self.palette.background, HIGHLIGHT_DIM, freshness);
+
+            for column in 0..self.layout.get_width() {
+                let byte = address + column / 8;
+                let bits = if bytes.contains(&byte) {
+                    memory.get(byte).copied().unwrap_or(0)
+                } else {
+                    0
+                };
+                let color = if bits & (0x80 >> (column % 8)) != 0 {
+                    lit
+                } else {
+                    unlit
+                };
+
+                image.fill(
+                    x + column * self.scale,
+                    y + row * self.scale,
+                    self.scale,
+                    self.scale,
+                    color,
+                );
+            }
+        }
+    }
+}
+
+/// Mixes two colors, from all of `from` at `0.0` to all of `to` at `1.0`
+fn blend(from: [u8; 3], to: [u8; 3], amount: f32) -> [u8; 3] {
+    let mut color = from;
+    for (channel, to) in color.iter_mut().zip(to.iter()) {
+        *channel = (*channel as f32 + (*to as f32 - *channel as f32) * amount).round() as u8;
+    }
+
+    color
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(image: &(usize, usize, Vec<u8>), x: usize, y: usize) -> [u8; 3] {
+        let idx = (y * image.0 + x) * 3;
+        [image.2[idx], image.2[idx + 1], image.2[idx + 2]]
+    }
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(Layout::default().get_size(), 8);
+        assert_eq!(Layout::Narrow(5).get_size(), 5);
+        assert_eq!(Layout::Wide.get_width(), 16);
+        assert_eq!(Layout::Wide.get_size(), 32);
+    }
+
+    #[test]
+    fn test_record() {
+        // i := 0x208, draw 3 rows, i := 0x20A, draw no rows, then loop back
+        let mut system = System::new();
+        system.write_rom(vec![
+            0xA2, 0x08, 0xD0, 0x03, 0xA2, 0x0A, 0xD0, 0x00, 0x12, 0x00,
+        ]);
+        let mut log = DrawLog::new();
+
+        for _ in 0..TICKS_PER_FRAME * 2 {
+            log.record(&system);
+            system.tick();
+        }
+
+        assert_eq!(log.get_frame(), 2);
+        assert_eq!(log.get_targets().get(&0x208), Some(&3));
+        assert_eq!(log.get_targets().get(&0x20A), None);
+        assert_eq!(log.get_age(0x208), Some(1));
+        assert_eq!(log.get_age(0x20B), None);
+        assert_eq!(log.get_age(0x207), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[0x300] = 0x80;
+        memory[0x302] = 0x01;
+        let mut log = DrawLog::new();
+        log.frames[0x302] = Some(0);
+
+        let sheet = Sheet {
+            layout: Layout::Narrow(2),
+            columns: 2,
+            scale: 1,
+            recent: 10,
+            ..Sheet::default()
+        };
+        let image = sheet.render(&memory, 0x300, 0x305, &log);
+        let label_width = (3 * osd::GLYPH_ADVANCE + 1) * LABEL_SCALE;
+        let row_height = osd::GLYPH_HEIGHT * LABEL_SCALE + GAP;
+
+        // Three sprites in two rows, the last cut short by the end of the range
+        assert_eq!(image.0, label_width + 2 * (8 + GAP) + GAP);
+        assert_eq!(image.1, 2 * row_height + GAP);
+
+        let (x, y) = (label_width + GAP, GAP);
+        let palette = Palette::default();
+        assert_eq!(pixel(&image, x, y), palette.foreground);
+        assert_eq!(pixel(&image, x + 1, y), palette.background);
+
+        // The drawn row is highlighted
+        let x = x + 8 + GAP;
+        assert_eq!(pixel(&image, x + 7, y), HIGHLIGHT);
+        assert_eq!(pixel(&image, x, y), HIGHLIGHT_DIM);
+        assert_eq!(pixel(&image, x, y + 1), palette.background);
+        assert_eq!(pixel(&image, x - 1, y), GRID);
+
+        let mut png = vec![];
+        sheet.write_sprite_png(&mut png, &memory, 0x300).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+    }
+}
@@ -0,0 +1,323 @@
+use crate::asm::{assemble, Assembly, Target};
+use crate::headless::parse_number;
+use crate::json::Json;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// How far past a label an address is still shown relative to it, e.g. `draw+0x4`
+const MAX_OFFSET: usize = 0x100;
+
+/// Identifies the symbols appended to a save state, see `System::save_state`
+const STATE_MAGIC: &[u8; 4] = b"C8SY";
+
+/// Names for addresses, used to show addresses symbolically and to accept labels wherever an
+/// address is expected.
+///
+/// The text form has a symbol per line as `ADDR NAME`, `NAME ADDR`, `NAME = ADDR` or Octo's
+/// `:const NAME ADDR`, where `#` and `;` start comments. A JSON object of names and addresses is
+/// read too, and Octo source is assembled for its labels, see `Symbols::load`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Symbols {
+    names: BTreeMap<usize, String>,
+    addresses: HashMap<String, usize>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the text form of a symbol map, or a JSON object of names and addresses
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The symbol map
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut symbols = Self::new();
+
+        if source.trim_start().starts_with('{') {
+            let json = Json::parse(source)?;
+            let fields = match &json {
+                Json::Object(fields) => fields,
+                _ => return Err(String::from("expected an object of names and addresses")),
+            };
+
+            for (name, value) in fields {
+                let address = match value {
+                    Json::String(text) => parse_number(text).map(|address| address as u64),
+                    value => value.as_u64(),
+                };
+                let address =
+                    address.ok_or_else(|| format!("invalid address for '{}'", name))? as usize;
+                symbols.insert(address, name);
+            }
+
+            return Ok(symbols);
+        }
+
+        for (idx, line) in source.lines().enumerate() {
+            let line = line.split(['#', ';']).next().unwrap_or("");
+            let words: Vec<&str> = line
+                .split_whitespace()
+                .filter(|word| *word != "=")
+                .collect();
+            let error = || format!("line {}: expected an address and a name", idx + 1);
+
+            let (address, name) = match words[..] {
+                [] => continue,
+                [":const", name, address] => (address, name),
+                // The two-word forms may be written either way round
+                [first, second] if parse_number(first).is_some() => (first, second),
+                [first, second] => (second, first),
+                _ => return Err(error()),
+            };
+            let address = match (parse_number(address), parse_number(name)) {
+                (Some(address), None) => address,
+                _ => return Err(error()),
+            };
+
+            symbols.insert(address as usize, name);
+        }
+
+        Ok(symbols)
+    }
+
+    /// Reads a symbol map from a file. Octo source, ending in `.8o`, is assembled and its labels
+    /// used.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to read
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let error = |err: &dyn std::fmt::Display| format!("{}: {}", path.display(), err);
+        let source = fs::read_to_string(path).map_err(|err| error(&err))?;
+
+        if path.extension().is_some_and(|extension| extension == "8o") {
+            let assembly = assemble(&source, Target::Chip8).map_err(|err| error(&err))?;
+            Ok(Self::from_assembly(&assembly))
+        } else {
+            Self::parse(&source).map_err(|err| error(&err))
+        }
+    }
+
+    /// Returns the labels of an assembled program
+    pub fn from_assembly(assembly: &Assembly) -> Self {
+        let mut symbols = Self::new();
+        for (name, address) in assembly.symbols.iter() {
+            symbols.insert(*address, name);
+        }
+
+        symbols
+    }
+
+    /// Names an address. An address keeps the first name given to it, though every name can be
+    /// looked up.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address
+    /// * `name` - The name, without whitespace
+    pub fn insert(&mut self, address: usize, name: &str) {
+        self.names
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), address);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// Returns the name of an address, if it has one
+    pub fn get_name(&self, address: usize) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    /// Returns the address of a name
+    pub fn get_address(&self, name: &str) -> Option<usize> {
+        self.addresses.get(name).copied()
+    }
+
+    /// Reads an address written as a number, a name, or a name plus an offset like the ones
+    /// `lookup` returns, e.g. `draw+0x4`
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The address
+    pub fn resolve(&self, text: &str) -> Option<usize> {
+        if let Some(address) = parse_number(text) {
+            return Some(address as usize);
+        }
+
+        match text.split_once('+') {
+            Some((name, offset)) => Some(self.get_address(name)? + parse_number(offset)? as usize),
+            None => self.get_address(text),
+        }
+    }
+
+    /// Returns the addresses and their names, in ascending order of address
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.names
+            .iter()
+            .map(|(address, name)| (*address, name.as_str()))
+    }
+
+    /// Names an address after the closest label at or before it, e.g. `draw` or `draw+0x4`, if
+    /// there is one close enough
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address
+    pub fn lookup(&self, address: usize) -> Option<String> {
+        let (label, name) = self.names.range(..=address).next_back()?;
+
+        match address - label {
+            0 => Some(name.clone()),
+            offset if offset < MAX_OFFSET => Some(format!("{}+{:#x}", name, offset)),
+            _ => None,
+        }
+    }
+
+    /// Formats an address symbolically if it can be, see `lookup`, or in hex otherwise
+    pub fn format(&self, address: usize) -> String {
+        self.lookup(address)
+            .unwrap_or_else(|| format!("{:#05x}", address))
+    }
+
+    /// Writes the symbols in their text form, `ADDR NAME` per line
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where to write the symbols
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for (address, name) in self.entries() {
+            writeln!(writer, "{:#05x} {}", address, name)?;
+        }
+
+        writer.flush()
+    }
+
+    /// Returns every name with its address, by address and with the name shown for an address
+    /// first, so reading them back in order gives the same symbols
+    fn entries(&self) -> Vec<(usize, &str)> {
+        let mut entries: Vec<(usize, bool, &str)> = self
+            .addresses
+            .iter()
+            .map(|(name, address)| {
+                let alias = self.get_name(*address) != Some(name.as_str());
+                (*address, alias, name.as_str())
+            })
+            .collect();
+        entries.sort();
+
+        entries
+            .into_iter()
+            .map(|(address, _, name)| (address, name))
+            .collect()
+    }
+
+    /// Appends the symbols to a save state
+    pub(crate) fn write_state(&self, state: &mut Vec<u8>) {
+        let mut symbols = self.entries();
+        symbols.truncate(u16::MAX as usize);
+
+        state.extend_from_slice(STATE_MAGIC);
+        state.extend_from_slice(&(symbols.len() as u16).to_be_bytes());
+        for (address, name) in symbols {
+            let name = &name.as_bytes()[..name.len().min(u8::MAX as usize)];
+
+            state.extend_from_slice(&(address as u16).to_be_bytes());
+            state.push(name.len() as u8);
+            state.extend_from_slice(name);
+        }
+    }
+
+    /// Reads the symbols appended to a save state by `write_state`
+    pub(crate) fn read_state(mut state: &[u8]) -> Option<Self> {
+        let mut take = |len: usize| {
+            if state.len() < len {
+                return None;
+            }
+            let (bytes, rest) = state.split_at(len);
+            state = rest;
+            Some(bytes)
+        };
+        let read_u16 = |bytes: &[u8]| (bytes[0] as usize) << 8 | bytes[1] as usize;
+
+        if take(STATE_MAGIC.len())? != STATE_MAGIC {
+            return None;
+        }
+
+        let mut symbols = Self::new();
+        for _ in 0..read_u16(take(2)?) {
+            let address = read_u16(take(2)?);
+            let len = take(1)?[0] as usize;
+            let name = String::from_utf8_lossy(take(len)?).into_owned();
+            symbols.insert(address, &name);
+        }
+
+        Some(symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let symbols = Symbols::parse(
+            "# labels\n0x200 main\ndraw_paddle 0x24A ; comment\nscore = 0x300\n:const lives 0x301\n\n",
+        )
+        .unwrap();
+
+        assert_eq!(symbols.get_address("main"), Some(0x200));
+        assert_eq!(symbols.get_address("draw_paddle"), Some(0x24A));
+        assert_eq!(symbols.get_address("score"), Some(0x300));
+        assert_eq!(symbols.get_name(0x301), Some("lives"));
+        assert!(Symbols::parse("0x200").is_err());
+        assert!(Symbols::parse("0x200 0x300").is_err());
+        assert!(Symbols::parse("main loop").is_err());
+
+        let json = Symbols::parse(r#"{"main": 512, "draw": "0x20A"}"#).unwrap();
+        assert_eq!(json.get_address("draw"), Some(0x20A));
+        assert_eq!(json.get_name(0x200), Some("main"));
+    }
+
+    #[test]
+    fn test_lookup() {
+        let symbols = Symbols::parse("0x200 main\n0x24A draw_paddle\n").unwrap();
+
+        assert_eq!(symbols.lookup(0x1FE), None);
+        assert_eq!(symbols.lookup(0x200).as_deref(), Some("main"));
+        assert_eq!(symbols.format(0x24E), "draw_paddle+0x4");
+        assert_eq!(symbols.format(0x24A + MAX_OFFSET), "0x34a");
+
+        assert_eq!(symbols.resolve("draw_paddle+0x4"), Some(0x24E));
+        assert_eq!(symbols.resolve("main"), Some(0x200));
+        assert_eq!(symbols.resolve("0x300"), Some(0x300));
+        assert_eq!(symbols.resolve("draw+0x4"), None);
+
+        let mut text = vec![];
+        symbols.write(&mut text).unwrap();
+        assert_eq!(
+            Symbols::parse(&String::from_utf8(text).unwrap()),
+            Ok(symbols)
+        );
+    }
+
+    #[test]
+    fn test_state() {
+        let symbols = Symbols::parse("0x200 start\n0x200 main\n0x300 score\n").unwrap();
+        let mut state = vec![];
+        symbols.write_state(&mut state);
+
+        assert_eq!(Symbols::read_state(&state), Some(symbols));
+        assert_eq!(Symbols::read_state(&state[..state.len() - 1]), None);
+        assert_eq!(Symbols::read_state(b"C8S1"), None);
+    }
+}
@@ -1,12 +1,168 @@
-use ops::{get_op_group, OP_GROUPS};
+use crate::breakpoints::{Access, Break, Breakpoints};
+use crate::heatmap::Heatmap;
+use crate::symbols::Symbols;
+use ops::OP_GROUPS;
+pub(crate) use ops::{combine_words, get_op_group, get_op_words, split_op};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+pub const COLOR_WIDTH: usize = 4;
+
+/// The number of instructions executed for every 60 Hz frame
+pub const TICKS_PER_FRAME: usize = 9;
+
+/// The hexadecimal digit sprites stored at the start of memory, 5 rows of 4 pixels each kept in
+/// the high nibble of every byte
+pub const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
 
-use std::io::Write;
+const MAX_INDEX: usize = WIDTH * HEIGHT * COLOR_WIDTH;
+
+/// Identifies save states written by `System::save_state`, including the layout version
+const STATE_MAGIC: &[u8; 4] = b"C8S2";
+
+/// The size of a save state in bytes: the magic, registers, timers, stack, memory, framebuffer
+/// (one bit per pixel), keys and the random number generator. States of systems with symbols are
+/// followed by the symbols.
+pub const STATE_SIZE: usize =
+    4 + 16 + 2 + 2 + 1 + 1 + 1 + (16 * 2) + 4096 + (WIDTH * HEIGHT / 8) + 2 + 2 + 4;
+
+/// Instructions that behave differently between CHIP-8 interpreters, which programs written for
+/// one of them may rely on. The default is how this emulator has always run them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    /// `8XY6` and `8XYE` shift `VY` into `VX`, rather than shifting `VX` in place
+    pub shift_vy: bool,
+    /// `FX55` and `FX65` leave `I` unchanged, rather than pointing after the last register
+    pub keep_i: bool,
+    /// `8XY1`, `8XY2` and `8XY3` reset `VF`
+    pub vf_reset: bool,
+    /// `BXNN` jumps to `XNN` plus `VX`, rather than `NNN` plus `V0`
+    pub jump_vx: bool,
+}
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+/// The names of the quirks in `Quirks::parse`, in the order of their fields
+const QUIRK_NAMES: [&str; 4] = ["shift-vy", "keep-i", "vf-reset", "jump-vx"];
 
-const COLOR_WIDTH: usize = 4;
-const MAX_INDEX: usize = WIDTH * HEIGHT * COLOR_WIDTH;
+impl Quirks {
+    /// Parses a comma separated list of the quirks to enable, e.g. `shift-vy,vf-reset`, or
+    /// `none`. The names are `shift-vy`, `keep-i`, `vf-reset` and `jump-vx`.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The list of quirks
+    pub fn parse(text: &str) -> Option<Quirks> {
+        let mut quirks = Quirks::default();
+        if text.trim() == "none" {
+            return Some(quirks);
+        }
+
+        for name in text.split(',') {
+            match name.trim() {
+                "shift-vy" => quirks.shift_vy = true,
+                "keep-i" => quirks.keep_i = true,
+                "vf-reset" => quirks.vf_reset = true,
+                "jump-vx" => quirks.jump_vx = true,
+                _ => return None,
+            }
+        }
+
+        Some(quirks)
+    }
+}
+
+/// Writes the quirks as `Quirks::parse` reads them
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let enabled = [self.shift_vy, self.keep_i, self.vf_reset, self.jump_vx];
+        let names: Vec<&str> = QUIRK_NAMES
+            .iter()
+            .zip(enabled)
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect();
+
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join(","))
+        }
+    }
+}
+
+/// The interpreters whose quirks can be emulated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    /// The original interpreter of the COSMAC VIP
+    Chip8,
+    /// SUPER-CHIP on HP calculators, which most later games were written for
+    Schip,
+}
+
+impl Variant {
+    /// Parses a variant name, either `chip8` or `schip`
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the variant, case insensitive
+    pub fn parse(name: &str) -> Option<Variant> {
+        match name.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(Variant::Chip8),
+            "schip" | "superchip" => Some(Variant::Schip),
+            _ => None,
+        }
+    }
+
+    /// Returns the quirks of the variant's interpreter
+    pub fn get_quirks(&self) -> Quirks {
+        match self {
+            Variant::Chip8 => Quirks {
+                shift_vy: true,
+                keep_i: false,
+                vf_reset: true,
+                jump_vx: false,
+            },
+            Variant::Schip => Quirks {
+                shift_vy: false,
+                keep_i: true,
+                vf_reset: false,
+                jump_vx: true,
+            },
+        }
+    }
+}
+
+/// Writes the variant as `Variant::parse` reads it
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Variant::Chip8 => write!(f, "chip8"),
+            Variant::Schip => write!(f, "schip"),
+        }
+    }
+}
 
 pub struct System {
     v: [u8; 16],
@@ -20,6 +176,23 @@ pub struct System {
     vmem: [u8; MAX_INDEX],
     input: u16,
     previous_input: u16,
+    polled: u16,
+    /// The state of the xorshift generator behind `CXNN`, kept in save states so a restored
+    /// state runs the same way again
+    random: u32,
+    /// How many instructions of the current frame have run
+    frame_tick: usize,
+    breakpoints: Breakpoints,
+    /// The memory accessed by the instruction being executed, while breakpoints are set
+    access: Option<(Access, usize, usize)>,
+    /// Messages of log-points not yet taken by `take_logs`
+    logs: Vec<String>,
+    /// A breakpoint on the next instruction, held back while a watchpoint was reported
+    deferred: Option<Break>,
+    heatmap: Option<Box<Heatmap>>,
+    /// Names of addresses for debugging tools, kept with save states
+    symbols: Symbols,
+    quirks: Quirks,
 }
 
 impl System {
@@ -36,63 +209,408 @@ impl System {
             vmem: [0; MAX_INDEX],
             input: 0,
             previous_input: 0,
+            polled: 0,
+            // Never zero, which xorshift cannot leave
+            random: rand::random::<u32>() | 1,
+            frame_tick: 0,
+            breakpoints: Breakpoints::default(),
+            access: None,
+            logs: vec![],
+            deferred: None,
+            heatmap: None,
+            symbols: Symbols::new(),
+            quirks: Quirks::default(),
         };
 
         // Write reserved interpreter memory
-        (&mut system.mem[0x000..0x1FF])
-            .write(&[
-                0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-                0x20, 0x60, 0x20, 0x20, 0x70, // 1
-                0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-                0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-                0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-                0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-                0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-                0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-                0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-                0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-                0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-                0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-                0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-                0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-                0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-                0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-            ])
-            .unwrap();
+        system.mem[..FONT.len()].copy_from_slice(&FONT);
 
         system
     }
 
-    pub fn write_rom(&mut self, rom: Vec<u8>) {
-        for i in 0..rom.len() {
-            self.mem[0x200 + i] = rom[i];
+    /// Creates a new system with the ROM stored at `path` loaded into memory
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The ROM file to load
+    pub fn load_rom<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let rom = fs::read(path)?;
+        let mut system = Self::new();
+
+        if rom.len() > system.mem.len() - 0x200 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "ROM is too large",
+            ));
         }
+
+        system.write_rom(rom);
+
+        Ok(system)
+    }
+
+    pub fn write_rom(&mut self, rom: Vec<u8>) {
+        self.mem[0x200..0x200 + rom.len()].copy_from_slice(&rom[..]);
     }
 
     pub fn set_key(&mut self, key: u8, value: bool) {
         if value {
-            self.input = self.input | (0x1 << key);
+            self.input |= 0x1 << key;
         } else {
-            self.input = self.input & (0xFF ^ (0x1 << key));
+            self.input &= !(0x1 << key);
+        }
+    }
+
+    /// Replaces the state of every key at once
+    ///
+    /// # Arguments
+    ///
+    /// * `mask` - One bit per key, where bit `n` is set while key `n` is held
+    pub fn set_keys(&mut self, mask: u16) {
+        self.input = mask;
+    }
+
+    pub fn get_keys(&self) -> u16 {
+        self.input
+    }
+
+    /// Sets how instructions that differ between interpreters behave. Quirks are not part of
+    /// save states, so they stay as set when a state is loaded.
+    ///
+    /// # Arguments
+    ///
+    /// * `quirks` - The quirks, e.g. from `Variant::get_quirks`
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Returns the keys the program checked during the current frame, which is how far it got
+    /// into the last frame once `run_to_next_frame` returns. Keys tested by `EX9E` and `EXA1` set
+    /// their bit, while waiting for any key with `FX0A` sets every bit.
+    pub fn get_polled_keys(&self) -> u16 {
+        self.polled
+    }
+
+    pub fn get_v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn get_i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn get_pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn get_sp(&self) -> usize {
+        self.sp
+    }
+
+    pub fn get_stack(&self) -> &[usize; 16] {
+        &self.stack
+    }
+
+    pub fn get_delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn get_sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Sets a general purpose register
+    ///
+    /// # Arguments
+    ///
+    /// * `register` - The register, from `0x0` to `0xF`
+    /// * `value` - The new value
+    pub fn set_v(&mut self, register: usize, value: u8) {
+        self.v[register] = value;
+    }
+
+    pub fn set_i(&mut self, value: u16) {
+        self.i = value;
+    }
+
+    /// Moves the program counter
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the next instruction, below the last byte of memory
+    pub fn set_pc(&mut self, address: usize) {
+        self.pc = address;
+    }
+
+    /// Sets the stack pointer. The stack grows upwards from `1`, so `0` is an empty stack.
+    ///
+    /// # Arguments
+    ///
+    /// * `sp` - The index of the latest return address, below `16`
+    pub fn set_sp(&mut self, sp: usize) {
+        self.sp = sp;
+    }
+
+    /// Replaces an entry of the stack
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The entry to replace, below `16`
+    /// * `address` - The return address to store
+    pub fn set_stack(&mut self, idx: usize, address: usize) {
+        self.stack[idx] = address;
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    pub fn get_memory(&self) -> &[u8] {
+        &self.mem[..]
+    }
+
+    /// Returns the memory for writing, e.g. for cheats or debuggers
+    pub fn get_memory_mut(&mut self) -> &mut [u8] {
+        &mut self.mem[..]
+    }
+
+    /// Returns the opcode stored at an address in memory
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the opcode's most significant byte
+    pub fn get_opcode(&self, address: usize) -> u16 {
+        let len = self.mem.len();
+
+        (self.mem[address % len] as u16) << 8 | self.mem[(address + 1) % len] as u16
+    }
+
+    /// Returns whether the program can no longer make progress on its own, either because it
+    /// jumps to itself forever or because it ran into empty memory
+    pub fn is_halted(&self) -> bool {
+        let op = self.get_opcode(self.pc);
+
+        op == 0x0000 || op == 0x1000 | self.pc as u16
+    }
+
+    /// Captures the whole machine state as `STATE_SIZE` bytes, followed by the symbols if any were
+    /// set, to be restored with `load_state`
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(STATE_SIZE);
+
+        state.extend_from_slice(STATE_MAGIC);
+        state.extend_from_slice(&self.v);
+        state.extend_from_slice(&self.i.to_be_bytes());
+        state.extend_from_slice(&(self.pc as u16).to_be_bytes());
+        state.extend_from_slice(&[self.sp as u8, self.delay_timer, self.sound_timer]);
+        for address in self.stack.iter() {
+            state.extend_from_slice(&(*address as u16).to_be_bytes());
+        }
+        state.extend_from_slice(&self.mem);
+
+        for pixels in self.vmem.chunks_exact(COLOR_WIDTH * 8) {
+            let byte = pixels
+                .chunks_exact(COLOR_WIDTH)
+                .fold(0, |byte, pixel| byte << 1 | (pixel[0] != 0) as u8);
+            state.push(byte);
+        }
+
+        state.extend_from_slice(&self.input.to_be_bytes());
+        state.extend_from_slice(&self.previous_input.to_be_bytes());
+        state.extend_from_slice(&self.random.to_be_bytes());
+
+        if !self.symbols.is_empty() {
+            self.symbols.write_state(&mut state);
+        }
+
+        state
+    }
+
+    /// Restores a machine state captured by `save_state`. The system is left untouched if the
+    /// state is invalid.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The bytes returned by `save_state`
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        if state.len() < STATE_SIZE || !state.starts_with(STATE_MAGIC) {
+            return Err(String::from("Not a CHIP-8 save state"));
+        }
+
+        let mut restored = Self::new();
+        let mut rest = &state[STATE_MAGIC.len()..];
+        let mut take = |len: usize| {
+            let (bytes, tail) = rest.split_at(len);
+            rest = tail;
+            bytes
+        };
+        let read_u16 = |bytes: &[u8]| (bytes[0] as u16) << 8 | bytes[1] as u16;
+
+        restored.v.copy_from_slice(take(16));
+        restored.i = read_u16(take(2));
+        restored.pc = read_u16(take(2)) as usize;
+        restored.sp = take(1)[0] as usize;
+        restored.delay_timer = take(1)[0];
+        restored.sound_timer = take(1)[0];
+        for address in restored.stack.iter_mut() {
+            *address = read_u16(take(2)) as usize;
+        }
+        restored.mem.copy_from_slice(take(4096));
+
+        for pixels in restored.vmem.chunks_exact_mut(COLOR_WIDTH * 8) {
+            let byte = take(1)[0];
+
+            for (bit, pixel) in pixels.chunks_exact_mut(COLOR_WIDTH).enumerate() {
+                let value = if byte & (0x80 >> bit) != 0 { 0xFF } else { 0 };
+                pixel.copy_from_slice(&[value; COLOR_WIDTH]);
+            }
+        }
+
+        restored.input = read_u16(take(2));
+        restored.previous_input = read_u16(take(2));
+        let random = take(4);
+        restored.random = u32::from_be_bytes([random[0], random[1], random[2], random[3]]);
+
+        if restored.pc >= restored.mem.len() - 1
+            || restored.sp >= restored.stack.len()
+            || restored.random == 0
+        {
+            return Err(String::from("The save state is corrupted"));
         }
+
+        restored.symbols = if state.len() > STATE_SIZE {
+            Symbols::read_state(&state[STATE_SIZE..])
+                .ok_or_else(|| String::from("The save state's symbols are corrupted"))?
+        } else {
+            std::mem::take(&mut self.symbols)
+        };
+
+        // Breakpoints and the heatmap belong to the tools rather than the machine, so they are
+        // kept
+        restored.breakpoints = std::mem::take(&mut self.breakpoints);
+        restored.heatmap = self.heatmap.take();
+        restored.quirks = self.quirks;
+        *self = restored;
+
+        Ok(())
     }
 
     pub fn get_framebuffer(&self) -> &[u8] {
         &self.vmem[..]
     }
 
-    pub fn run_to_next_frame(&mut self) -> bool {
-        let mut end_execution = false;
+    /// Returns whether a pixel is currently drawn on screen
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - The column of the pixel, from `0` to `WIDTH - 1`
+    /// * `y` - The row of the pixel, from `0` to `HEIGHT - 1`
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.vmem[((y * WIDTH) + x) * COLOR_WIDTH] != 0
+    }
+
+    /// Returns the breakpoints and watchpoints checked by `tick`
+    pub fn get_breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn get_breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// Starts or stops counting memory accesses into a heatmap
+    ///
+    /// # Arguments
+    ///
+    /// * `heatmap` - The heatmap to count into, or `None` to stop counting
+    pub fn set_heatmap(&mut self, heatmap: Option<Heatmap>) {
+        self.heatmap = heatmap.map(Box::new);
+    }
 
-        for _ in 0..9 {
-            end_execution |= self.tick();
+    pub fn get_heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_deref()
+    }
+
+    /// Sets the names of the program's addresses, shown by debugging tools and saved with
+    /// save states
+    ///
+    /// # Arguments
+    ///
+    /// * `symbols` - The names, e.g. from `Symbols::load`
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn get_symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// Returns the messages logged by log-points since the last call
+    pub fn take_logs(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.logs)
+    }
+
+    /// Runs the rest of the current frame, which is a whole frame unless it was interrupted by a
+    /// breakpoint or driven by `tick`. Returns the breakpoint or watchpoint that stopped it
+    /// early, if any.
+    pub fn run_to_next_frame(&mut self) -> Option<Break> {
+        if self.frame_tick == 0 {
+            self.polled = 0;
+        }
+
+        loop {
+            let hit = self.tick();
+
+            if hit.is_some() || self.frame_tick == 0 {
+                return hit;
+            }
         }
+    }
 
-        end_execution
+    /// Returns the breakpoint at the instruction about to run, if any. `tick` only checks the
+    /// instruction it moves to, so this is for the first one, e.g. when a run starts. Messages of
+    /// log-points there are kept for `take_logs`.
+    pub fn check_breakpoints(&mut self) -> Option<Break> {
+        let mut logs = vec![];
+        let hit = self.breakpoints.check_next(self, &mut logs);
+        self.logs.append(&mut logs);
+        hit
     }
 
-    pub fn tick(&mut self) -> bool {
+    /// Returns whether the next `tick` returns a breakpoint held back after a watchpoint instead
+    /// of running an instruction, so callers counting instructions can leave it out
+    pub fn is_break_deferred(&self) -> bool {
+        match self.deferred {
+            Some(Break::Address(address) | Break::Opcode { address, .. }) => address == self.pc,
+            _ => false,
+        }
+    }
+
+    /// Runs one instruction. Returns the breakpoint or watchpoint it hit, if any, see
+    /// `Breakpoints`. When an instruction hits a watchpoint and the next one is at a breakpoint,
+    /// the breakpoint is returned by the following call, which runs nothing, see
+    /// `is_break_deferred`.
+    pub fn tick(&mut self) -> Option<Break> {
+        if self.is_break_deferred() {
+            return self.deferred.take();
+        }
+        self.deferred = None;
+
+        let pc = self.pc;
         let op = (self.mem[self.pc] as u16) << 8 | self.mem[self.pc + 1] as u16;
+        let before = (self.v, self.i);
+
+        if let Some(heatmap) = self.heatmap.as_mut() {
+            heatmap.record_execute(pc);
+        }
 
         self.pc += 2;
         self.execute_op(op);
@@ -106,8 +624,54 @@ impl System {
         }
 
         self.previous_input = self.input;
+        self.frame_tick = (self.frame_tick + 1) % TICKS_PER_FRAME;
 
-        (self.mem[self.pc] as u16) << 8 | self.mem[self.pc + 1] as u16 != 0
+        if self.frame_tick == 0 {
+            if let Some(heatmap) = self.heatmap.as_mut() {
+                heatmap.decay();
+            }
+        }
+
+        if self.breakpoints.is_empty() {
+            return None;
+        }
+
+        // Conditions see the state after the instruction, so evaluate them all here
+        let mut logs = vec![];
+        let hit = self
+            .access
+            .take()
+            .and_then(|access| self.breakpoints.check_access(access, pc, self, &mut logs));
+        let changed = self
+            .breakpoints
+            .check_registers(before, pc, self, &mut logs);
+        let next = self.breakpoints.check_next(self, &mut logs);
+        self.logs.append(&mut logs);
+
+        match hit.or(changed) {
+            Some(hit) => {
+                self.deferred = next;
+                Some(hit)
+            }
+            None => next,
+        }
+    }
+
+    /// Records the executing instruction's memory access for `tick` to check against watchpoints,
+    /// and counts it in the heatmap
+    ///
+    /// # Arguments
+    ///
+    /// * `access` - Whether the memory is read or written
+    /// * `start` - The first address accessed
+    /// * `len` - The number of bytes accessed
+    fn watch(&mut self, access: Access, start: usize, len: usize) {
+        if let Some(heatmap) = self.heatmap.as_mut() {
+            heatmap.record(access, start, len);
+        }
+        if !self.breakpoints.is_empty() {
+            self.access = Some((access, start, len));
+        }
     }
 
     fn execute_op(&mut self, op: u16) {
@@ -117,9 +681,14 @@ impl System {
     }
 }
 
+impl Default for System {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 mod ops {
-    use super::{System, COLOR_WIDTH, MAX_INDEX, WIDTH};
-    use rand::Rng;
+    use super::{Access, System, COLOR_WIDTH, MAX_INDEX, WIDTH};
     use std::io::Write;
 
     /// All avaialable opcodes where the most-significant word (`0x0XXX` - `0xFXXX`) is the index. CHIP-8
@@ -134,12 +703,12 @@ mod ops {
             // 0x0XXX
             match split_op(op).1 {
                 0xE0 => {
-                    (&mut system.vmem[..]).write(&[0; MAX_INDEX]).unwrap();
+                    (&mut system.vmem[..]).write_all(&[0; MAX_INDEX]).unwrap();
                 }
                 0xEE => {
                     // Return from subroutine
                     system.pc = system.stack[system.sp];
-                    system.sp = system.sp - 1;
+                    system.sp -= 1;
                 }
                 _ => {}
             }
@@ -151,7 +720,7 @@ mod ops {
         |system, op| {
             // 0x2XXX
             // Put the program counter on the stack and jump
-            system.sp = system.sp + 1;
+            system.sp += 1;
             system.stack[system.sp] = system.pc;
             system.pc = (op & 0x0FFF) as usize;
         },
@@ -211,13 +780,22 @@ mod ops {
             match words[3] {
                 0x0 => system.v[register1] = system.v[register2],
                 0x1 => {
-                    system.v[register1] = system.v[register1] | system.v[register2];
+                    system.v[register1] |= system.v[register2];
+                    if system.quirks.vf_reset {
+                        system.v[0xF] = 0;
+                    }
                 }
                 0x2 => {
-                    system.v[register1] = system.v[register1] & system.v[register2];
+                    system.v[register1] &= system.v[register2];
+                    if system.quirks.vf_reset {
+                        system.v[0xF] = 0;
+                    }
                 }
                 0x3 => {
-                    system.v[register1] = system.v[register1] ^ system.v[register2];
+                    system.v[register1] ^= system.v[register2];
+                    if system.quirks.vf_reset {
+                        system.v[0xF] = 0;
+                    }
                 }
                 0x4 => {
                     let (value, overflow) =
@@ -234,7 +812,12 @@ mod ops {
                     system.v[register1] = value;
                 }
                 0x6 => {
-                    let (value, overflow) = system.v[register1].overflowing_shr(1);
+                    let source = if system.quirks.shift_vy {
+                        register2
+                    } else {
+                        register1
+                    };
+                    let (value, overflow) = system.v[source].overflowing_shr(1);
 
                     system.v[0xF] = if overflow { 1 } else { 0 };
                     system.v[register1] = value;
@@ -247,7 +830,12 @@ mod ops {
                     system.v[register1] = value;
                 }
                 0xE => {
-                    let (value, overflow) = system.v[register1].overflowing_shl(1);
+                    let source = if system.quirks.shift_vy {
+                        register2
+                    } else {
+                        register1
+                    };
+                    let (value, overflow) = system.v[source].overflowing_shl(1);
 
                     system.v[0xF] = if overflow { 1 } else { 0 };
                     system.v[register1] = value;
@@ -262,16 +850,21 @@ mod ops {
             let cmp_register = words[2] as usize;
 
             if system.v[register] != system.v[cmp_register] {
-                system.pc = system.pc + 2;
+                system.pc += 2;
             }
         },
         |system, op| {
             // 0xAXXX
-            system.i = (op & 0x0FFF) as u16;
+            system.i = op & 0x0FFF;
         },
         |system, op| {
             // 0xBXXX
-            system.pc = (system.v[0x0] + (op & 0x0FFF) as u8) as usize;
+            let register = if system.quirks.jump_vx {
+                get_op_words(op)[1] as usize
+            } else {
+                0x0
+            };
+            system.pc = (system.v[register] + (op & 0x0FFF) as u8) as usize;
         },
         |system, op| {
             // 0xCXXX
@@ -279,8 +872,11 @@ mod ops {
             let register = words[1] as usize;
             let value = combine_words(words[2], words[3]);
 
-            let mut rng = rand::thread_rng();
-            let rand_val = rng.gen_range(0, 255) as u8;
+            // xorshift32
+            system.random ^= system.random << 13;
+            system.random ^= system.random >> 17;
+            system.random ^= system.random << 5;
+            let rand_val = (system.random >> 24) as u8;
 
             system.v[register] = rand_val & value;
         },
@@ -291,13 +887,14 @@ mod ops {
             let y = system.v[words[2] as usize] as usize;
             let num_bytes = words[3] as usize;
 
+            system.watch(Access::Read, system.i as usize, num_bytes);
             let bytes = &system.mem[system.i as usize..system.i as usize + num_bytes];
 
             let mut has_collision = false;
 
-            for idx in 0..num_bytes {
+            for (idx, byte) in bytes.iter().enumerate() {
                 for split_byte in 0..8 {
-                    if bytes[idx] & (0b1000_0000 >> split_byte) != 0 {
+                    if byte & (0b1000_0000 >> split_byte) != 0 {
                         let vmem_idx =
                             ((((y + idx) * WIDTH) + (x + split_byte)) * COLOR_WIDTH) % MAX_INDEX;
 
@@ -321,13 +918,12 @@ mod ops {
             let instruction = combine_words(words[2], words[3]);
 
             match instruction {
-                0x9E => {
-                    if 2u16.pow(system.v[register] as u32) & system.input > 0 {
-                        system.pc += 2;
-                    }
-                }
-                0xA1 => {
-                    if 2u16.pow(system.v[register] as u32) & system.input == 0 {
+                0x9E | 0xA1 => {
+                    let key = 2u16.pow(system.v[register] as u32);
+                    let held = key & system.input > 0;
+                    system.polled |= key;
+
+                    if held == (instruction == 0x9E) {
                         system.pc += 2;
                     }
                 }
@@ -343,6 +939,8 @@ mod ops {
             match instruction {
                 0x07 => system.v[register] = system.delay_timer,
                 0x0A => {
+                    system.polled = 0xFFFF;
+
                     if system.previous_input == system.input {
                         system.pc -= 2;
                     } else {
@@ -354,26 +952,33 @@ mod ops {
                 }
                 0x15 => system.delay_timer = system.v[register],
                 0x18 => system.sound_timer = system.v[register],
-                0x1E => system.i = system.i + system.v[register] as u16,
+                0x1E => system.i += system.v[register] as u16,
                 0x29 => {
                     system.i = (system.v[register] * 5) as u16;
                 }
                 0x33 => {
+                    system.watch(Access::Write, system.i as usize, 3);
                     system.mem[system.i as usize] = system.v[register] / 100;
                     system.mem[(system.i + 1) as usize] = (system.v[register] / 10) % 10;
                     system.mem[(system.i + 2) as usize] = (system.v[register] % 100) % 10;
                 }
                 0x55 => {
+                    system.watch(Access::Write, system.i as usize, register);
                     for read_register in 0..register {
                         system.mem[system.i as usize + read_register] = system.v[read_register];
                     }
-                    system.i = system.i + register as u16 + 1
+                    if !system.quirks.keep_i {
+                        system.i = system.i + register as u16 + 1
+                    }
                 }
                 0x65 => {
+                    system.watch(Access::Read, system.i as usize, register);
                     for read_register in 0..register {
                         system.v[read_register] = system.mem[system.i as usize + read_register];
                     }
-                    system.i = system.i + register as u16 + 1
+                    if !system.quirks.keep_i {
+                        system.i = system.i + register as u16 + 1
+                    }
                 }
                 _ => {}
             }
@@ -385,7 +990,7 @@ mod ops {
     /// # Arguments
     ///
     /// * `op` - The opcode to split
-    fn get_op_words(op: u16) -> [u8; 4] {
+    pub fn get_op_words(op: u16) -> [u8; 4] {
         [
             ((op & 0xF000) >> 12) as u8,
             ((op & 0x0F00) >> 8) as u8,
@@ -400,7 +1005,7 @@ mod ops {
     ///
     /// * `first` - The most significant word in the new byte
     /// * `second` - The least significant word in the new byte
-    fn combine_words(first: u8, second: u8) -> u8 {
+    pub fn combine_words(first: u8, second: u8) -> u8 {
         first << 4 | second
     }
 
@@ -425,6 +1030,8 @@ mod ops {
     #[cfg(test)]
     mod tests {
         use super::System;
+        use crate::symbols::Symbols;
+        use crate::system::{Quirks, Variant, STATE_SIZE};
 
         /// Builds a new system containing the specified ROM memory
         ///
@@ -536,6 +1143,191 @@ mod ops {
             }
         }
 
+        #[test]
+        fn test_halted() {
+            let mut system = build_system(vec![0x60, 0x01, 0x12, 0x02]);
+
+            assert!(!system.is_halted());
+            system.tick();
+            assert!(system.is_halted());
+        }
+
+        #[test]
+        fn test_polled_keys() {
+            // Skip loading 3 if key 5 is held, then skip if key 5 is not held
+            let mut system = build_system(vec![0x60, 0x05, 0xE0, 0x9E, 0x60, 0x03, 0xE0, 0xA1]);
+            system.set_key(5, true);
+
+            for _ in 0..3 {
+                system.tick();
+            }
+            assert_eq!(system.get_pc(), 0x208);
+            assert_eq!(system.get_polled_keys(), 1 << 5);
+
+            let mut system = build_system(vec![0xF0, 0x0A]);
+            system.run_to_next_frame();
+            assert_eq!(system.get_polled_keys(), 0xFFFF);
+        }
+
+        #[test]
+        fn test_set_key() {
+            let mut system = build_system(vec![]);
+            system.set_key(0xA, true);
+            system.set_key(0x3, true);
+            assert_eq!(system.get_keys(), 1 << 0xA | 1 << 0x3);
+
+            system.set_key(0x3, false);
+            assert_eq!(system.get_keys(), 1 << 0xA);
+            system.set_key(0xA, false);
+            assert_eq!(system.get_keys(), 0);
+        }
+
+        #[test]
+        fn test_quirks() {
+            // v1 := 6, v2 := 0x10, vF := 1, v1 |= v2, v3 >>= v2, save v1
+            let rom = vec![
+                0x61, 0x06, 0x62, 0x10, 0x6F, 0x01, 0x81, 0x21, 0x83, 0x26, 0xF1, 0x55,
+            ];
+
+            let mut system = build_system(rom.clone());
+            for _ in 0..4 {
+                system.tick();
+            }
+            assert_eq!(system.v[0xF], 1);
+            system.tick();
+            assert_eq!(system.v[3], 0);
+            system.tick();
+            assert_eq!(system.get_i(), 2);
+
+            let mut system = build_system(rom);
+            system.set_quirks(Variant::Chip8.get_quirks());
+            for _ in 0..4 {
+                system.tick();
+            }
+            assert_eq!(system.v[0xF], 0);
+            system.tick();
+            assert_eq!(system.v[3], 0x8);
+
+            system.set_quirks(Variant::Schip.get_quirks());
+            system.tick();
+            assert_eq!(system.get_i(), 0);
+
+            let quirks = Variant::Chip8.get_quirks();
+            assert_eq!(quirks.to_string(), "shift-vy,vf-reset");
+            assert_eq!(Quirks::parse("shift-vy, vf-reset"), Some(quirks));
+            assert_eq!(Quirks::parse("none"), Some(Quirks::default()));
+            assert_eq!(Quirks::default().to_string(), "none");
+            assert_eq!(Quirks::parse("shift"), None);
+        }
+
+        #[test]
+        fn test_save_state() {
+            // Draw the "0" glyph, then call a subroutine that loops forever
+            let mut system = build_system(vec![0xD0, 0x05, 0x22, 0x04, 0x12, 0x04]);
+            system.tick();
+            system.tick();
+            system.v[0x3] = 0x42;
+            system.delay_timer = 7;
+
+            let state = system.save_state();
+            assert_eq!(state.len(), STATE_SIZE);
+
+            let mut restored = System::new();
+            restored.load_state(&state).unwrap();
+
+            assert_eq!(restored.save_state(), state);
+            assert_eq!(restored.get_pc(), 0x204);
+            assert_eq!(restored.get_stack()[1], 0x204);
+            assert_eq!(restored.get_v()[0x3], 0x42);
+            assert_eq!(restored.get_framebuffer(), system.get_framebuffer());
+
+            assert!(restored.load_state(&state[1..]).is_err());
+
+            // Symbols are saved after the machine state, and kept by states without them
+            let symbols = Symbols::parse("0x204 spin").unwrap();
+            system.set_symbols(symbols.clone());
+            let with_symbols = system.save_state();
+            assert!(with_symbols.len() > STATE_SIZE);
+            restored.load_state(&with_symbols).unwrap();
+            assert_eq!(restored.get_symbols(), &symbols);
+            restored.load_state(&state).unwrap();
+            assert_eq!(restored.get_symbols(), &symbols);
+            assert!(restored
+                .load_state(&with_symbols[..with_symbols.len() - 1])
+                .is_err());
+
+            // The random number generator is saved too, so a restored state replays the same
+            let mut random = build_system(vec![0xC0, 0xFF, 0x12, 0x00]);
+            let state = random.save_state();
+            let values: Vec<u8> = (0..8)
+                .map(|_| {
+                    random.tick();
+                    random.tick();
+                    random.get_v()[0]
+                })
+                .collect();
+            random.load_state(&state).unwrap();
+            for value in values {
+                random.tick();
+                random.tick();
+                assert_eq!(random.get_v()[0], value);
+            }
+        }
+
+        #[test]
+        fn test_breakpoints() {
+            use crate::breakpoints::{Access, Break, OpcodePattern, Register, Watch, WatchKind};
+
+            // i := 0x300, bcd v0, draw, v1 += 1, then loop back
+            let mut system = build_system(vec![
+                0xA3, 0x00, 0xF0, 0x33, 0xD0, 0x01, 0x71, 0x01, 0x12, 0x00,
+            ]);
+            let breakpoints = system.get_breakpoints_mut();
+            breakpoints.add_watch(Watch {
+                start: 0x302,
+                len: 1,
+                kind: WatchKind::Write,
+            });
+            breakpoints.add_opcode(OpcodePattern::parse("DXYN").unwrap());
+            breakpoints.add_register(Register::V(1));
+            breakpoints.add_address(0x208);
+
+            assert_eq!(system.tick(), None);
+            assert_eq!(
+                system.tick(),
+                Some(Break::Memory {
+                    address: 0x302,
+                    access: Access::Write,
+                    pc: 0x202
+                })
+            );
+
+            // The draw's breakpoint comes next, before it runs
+            let draw = OpcodePattern::parse("DXYN").unwrap();
+            assert_eq!(
+                system.tick(),
+                Some(Break::Opcode {
+                    address: 0x204,
+                    pattern: draw
+                })
+            );
+            assert_eq!(system.get_pc(), 0x204);
+            assert_eq!(system.tick(), None);
+
+            assert_eq!(
+                system.run_to_next_frame(),
+                Some(Break::Register {
+                    register: Register::V(1),
+                    old: 0,
+                    new: 1,
+                    pc: 0x206
+                })
+            );
+            assert_eq!(system.tick(), Some(Break::Address(0x208)));
+            assert_eq!(system.tick(), None);
+            assert_eq!(system.get_pc(), 0x200);
+        }
+
         #[test]
         fn test_7000() {
             let mut system = build_system(vec!(
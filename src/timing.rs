@@ -0,0 +1,160 @@
+use std::time::{Duration, Instant};
+
+/// The rate the CHIP-8 runs at, in emulated frames per second
+pub const FRAME_RATE: u32 = 60;
+
+/// The most frames run at once to catch up after a stall. Anything beyond is dropped so a long
+/// pause (e.g. dragging the window) does not fast-forward the game afterwards.
+pub const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+/// Schedules emulated frames at a fixed rate, independently of how often the frontend wakes up.
+/// Elapsed time is collected in an accumulator and spent one whole frame at a time.
+pub struct FrameClock {
+    step: Duration,
+    accumulator: Duration,
+    last: Instant,
+}
+
+impl FrameClock {
+    /// Creates a clock producing `FRAME_RATE` frames per second
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The time the first frame is measured from
+    pub fn new(now: Instant) -> Self {
+        Self {
+            step: Duration::from_secs(1) / FRAME_RATE,
+            accumulator: Duration::from_secs(0),
+            last: now,
+        }
+    }
+
+    /// Returns how many frames should be run to catch up with the current time, at most
+    /// `MAX_CATCH_UP_FRAMES`
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time
+    pub fn advance(&mut self, now: Instant) -> u32 {
+        self.accumulator += now.saturating_duration_since(self.last);
+        self.last = now;
+
+        let mut frames = 0;
+        while self.accumulator >= self.step && frames < MAX_CATCH_UP_FRAMES {
+            self.accumulator -= self.step;
+            frames += 1;
+        }
+
+        if frames == MAX_CATCH_UP_FRAMES {
+            // Too far behind, drop the backlog but keep the phase of the next frame
+            self.accumulator =
+                Duration::from_nanos((self.accumulator.as_nanos() % self.step.as_nanos()) as u64);
+        }
+
+        frames
+    }
+
+    /// Returns when the next frame is due
+    pub fn next_frame(&self) -> Instant {
+        self.last + (self.step - self.accumulator)
+    }
+}
+
+/// Measures how often something happens per second, smoothed over time
+pub struct RateMeter {
+    interval: Duration,
+    started: Instant,
+    count: u32,
+    rate: Option<f64>,
+}
+
+impl RateMeter {
+    /// Creates a meter producing a new measurement every `interval`
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The time the first measurement starts
+    /// * `interval` - How long each measurement lasts
+    pub fn new(now: Instant, interval: Duration) -> Self {
+        Self {
+            interval,
+            started: now,
+            count: 0,
+            rate: None,
+        }
+    }
+
+    /// Counts events and returns the updated rate whenever a measurement completes
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - How many events happened
+    /// * `now` - The current time
+    pub fn add(&mut self, count: u32, now: Instant) -> Option<f64> {
+        self.count += count;
+
+        let elapsed = now.saturating_duration_since(self.started);
+        if elapsed < self.interval {
+            return None;
+        }
+
+        let measured = self.count as f64 / elapsed.as_secs_f64();
+        let rate = match self.rate {
+            Some(rate) => (rate + measured) / 2.0,
+            None => measured,
+        };
+
+        self.rate = Some(rate);
+        self.started = now;
+        self.count = 0;
+
+        Some(rate)
+    }
+
+    /// Returns the latest smoothed rate, once a measurement completed
+    pub fn rate(&self) -> Option<f64> {
+        self.rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(count: u32) -> Duration {
+        (Duration::from_secs(1) / FRAME_RATE) * count
+    }
+
+    #[test]
+    fn test_frame_clock_runs_whole_frames() {
+        let start = Instant::now();
+        let mut clock = FrameClock::new(start);
+
+        assert_eq!(clock.advance(start + frames(1) / 2), 0);
+        assert_eq!(clock.advance(start + frames(1)), 1);
+        assert_eq!(clock.next_frame(), start + frames(2));
+        assert_eq!(clock.advance(start + frames(3) + frames(1) / 2), 2);
+        assert_eq!(clock.next_frame(), start + frames(4));
+    }
+
+    #[test]
+    fn test_frame_clock_drops_backlog() {
+        let start = Instant::now();
+        let mut clock = FrameClock::new(start);
+
+        assert_eq!(clock.advance(start + frames(100)), MAX_CATCH_UP_FRAMES);
+        assert_eq!(clock.advance(start + frames(100)), 0);
+        assert_eq!(clock.advance(start + frames(101)), 1);
+    }
+
+    #[test]
+    fn test_rate_meter() {
+        let start = Instant::now();
+        let mut meter = RateMeter::new(start, Duration::from_secs(1));
+
+        assert_eq!(meter.add(30, start + Duration::from_millis(500)), None);
+        assert_eq!(meter.add(30, start + Duration::from_secs(1)), Some(60.0));
+        assert_eq!(meter.add(30, start + Duration::from_secs(2)), Some(45.0));
+        assert_eq!(meter.rate(), Some(45.0));
+    }
+}
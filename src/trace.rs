@@ -0,0 +1,439 @@
+use crate::disasm::{Instruction, Syntax};
+use crate::headless::parse_number;
+use crate::symbols::Symbols;
+use crate::system::{System, TICKS_PER_FRAME};
+
+use std::io::{self, Read, Write};
+
+/// The first bytes of a binary trace, followed by a format version
+const BINARY_MAGIC: &[u8; 4] = b"C8TR";
+const BINARY_VERSION: u8 = 1;
+
+/// The size of a record in a binary trace
+const RECORD_SIZE: usize = 33;
+
+/// How a trace is written
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TraceFormat {
+    /// One line per instruction, for diffing against other emulators. Symbols are never used,
+    /// so traces compare line for line whatever names were loaded.
+    #[default]
+    Text,
+    /// Text with addresses named after the system's symbols, for reading rather than diffing
+    Symbolic,
+    /// A header followed by fixed size little-endian records, for long runs
+    Binary,
+}
+
+impl TraceFormat {
+    /// Parses `text`, `symbolic` or `binary`
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the format
+    pub fn parse(name: &str) -> Option<TraceFormat> {
+        match name {
+            "text" => Some(TraceFormat::Text),
+            "symbolic" => Some(TraceFormat::Symbolic),
+            "binary" => Some(TraceFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// The state of the system before an instruction runs
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TraceRecord {
+    /// How many instructions ran before this one
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceRecord {
+    /// Captures the state of a system about to run its next instruction
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The system
+    /// * `cycle` - How many instructions the system has run
+    pub fn capture(system: &System, cycle: u64) -> Self {
+        Self {
+            cycle,
+            pc: system.get_pc() as u16,
+            opcode: system.get_opcode(system.get_pc()),
+            v: *system.get_v(),
+            i: system.get_i(),
+            sp: system.get_sp() as u8,
+            delay_timer: system.get_delay_timer(),
+            sound_timer: system.get_sound_timer(),
+        }
+    }
+
+    /// Writes the record as a line of text with fixed width columns, so two traces can be diffed
+    /// line by line:
+    ///
+    /// ```text
+    /// 00000000 0200: 6120  LD V1, 0x20          V0=00 V1=00 ... VF=00 I=0000 SP=0 DT=00 ST=00
+    /// ```
+    ///
+    /// CHIP-8 has no standard trace format, so the layout is modelled on `nestest.log`, the
+    /// reference trace NES emulators are diffed against: the address, the raw opcode, the
+    /// disassembly padded to a fixed width, then every register as `NAME=value` in a fixed order,
+    /// all in upper case hex. Here the line starts with the decimal cycle count, the disassembly
+    /// uses the mnemonics of Cowgod's Chip-8 Technical Reference, and the registers are `V0` to
+    /// `VF`, `I`, `SP`, `DT` and `ST`, holding their values before the instruction ran.
+    ///
+    /// Addresses with symbols are written by name, and the line ends with the name of the
+    /// instruction's address, e.g. `<draw+0x4>`. Pass empty symbols for lines to diff.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where to write the line
+    /// * `symbols` - The names of addresses, which may be empty
+    pub fn write_text<W: Write>(&self, mut writer: W, symbols: &Symbols) -> io::Result<()> {
+        let disassembly = match Instruction::decode(self.opcode) {
+            Some(instruction) => {
+                let target = symbols.lookup(self.opcode as usize & 0x0FFF);
+                instruction.format(Syntax::Cowgod, target.as_deref())
+            }
+            None => String::from("???"),
+        };
+        let location = match symbols.lookup(self.pc as usize) {
+            Some(name) => format!(" <{}>", name),
+            None => String::new(),
+        };
+        let registers: Vec<String> = self
+            .v
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| format!("V{:X}={:02X}", idx, value))
+            .collect();
+
+        writeln!(
+            writer,
+            "{:08} {:04X}: {:04X}  {:<20} {} I={:04X} SP={:X} DT={:02X} ST={:02X}{}",
+            self.cycle,
+            self.pc,
+            self.opcode,
+            disassembly,
+            registers.join(" "),
+            self.i,
+            self.sp,
+            self.delay_timer,
+            self.sound_timer,
+            location
+        )
+    }
+
+    /// Writes the record in the binary format, see `read_binary`
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where to write the record
+    pub fn write_binary<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(RECORD_SIZE);
+
+        bytes.extend_from_slice(&self.cycle.to_le_bytes());
+        bytes.extend_from_slice(&self.pc.to_le_bytes());
+        bytes.extend_from_slice(&self.opcode.to_le_bytes());
+        bytes.extend_from_slice(&self.v);
+        bytes.extend_from_slice(&self.i.to_le_bytes());
+        bytes.extend_from_slice(&[self.sp, self.delay_timer, self.sound_timer]);
+
+        writer.write_all(&bytes)
+    }
+
+    /// Reads the next record of a binary trace, or `None` at the end of the trace
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The trace, after its header, see `read_binary_header`
+    pub fn read_binary<R: Read>(mut reader: R) -> io::Result<Option<Self>> {
+        let mut bytes = [0; RECORD_SIZE];
+        let mut read = 0;
+
+        while read < RECORD_SIZE {
+            match reader.read(&mut bytes[read..])? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                len => read += len,
+            }
+        }
+
+        let u16_at = |idx: usize| u16::from_le_bytes([bytes[idx], bytes[idx + 1]]);
+        let mut cycle = [0; 8];
+        let mut v = [0; 16];
+        cycle.copy_from_slice(&bytes[..8]);
+        v.copy_from_slice(&bytes[12..28]);
+
+        Ok(Some(Self {
+            cycle: u64::from_le_bytes(cycle),
+            pc: u16_at(8),
+            opcode: u16_at(10),
+            v,
+            i: u16_at(28),
+            sp: bytes[30],
+            delay_timer: bytes[31],
+            sound_timer: bytes[32],
+        }))
+    }
+}
+
+/// Checks the header of a binary trace
+///
+/// # Arguments
+///
+/// * `reader` - The start of the trace
+pub fn read_binary_header<R: Read>(mut reader: R) -> io::Result<()> {
+    let mut header = [0; 5];
+    reader.read_exact(&mut header)?;
+
+    if &header[..4] != BINARY_MAGIC || header[4] != BINARY_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a binary trace",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Converts a binary trace to text
+///
+/// # Arguments
+///
+/// * `reader` - The binary trace
+/// * `writer` - Where to write the text trace
+/// * `symbols` - The names of addresses, which may be empty
+pub fn write_binary_as_text<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    symbols: &Symbols,
+) -> io::Result<()> {
+    read_binary_header(&mut reader)?;
+
+    while let Some(record) = TraceRecord::read_binary(&mut reader)? {
+        record.write_text(&mut writer, symbols)?;
+    }
+
+    writer.flush()
+}
+
+/// Which instructions are traced. Ranges are inclusive and everything is traced by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TraceFilter {
+    pub addresses: Option<(usize, usize)>,
+    pub frames: Option<(u64, u64)>,
+}
+
+impl TraceFilter {
+    /// Returns whether an instruction is traced
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the instruction
+    /// * `frame` - The frame it runs in, starting at `0`
+    pub fn matches(&self, address: usize, frame: u64) -> bool {
+        self.addresses
+            .is_none_or(|(start, end)| (start..=end).contains(&address))
+            && self
+                .frames
+                .is_none_or(|(start, end)| (start..=end).contains(&frame))
+    }
+}
+
+/// Parses an inclusive range written `START-END`, or a single number
+///
+/// # Arguments
+///
+/// * `text` - The range, with numbers in decimal or with a `0x` prefix
+pub fn parse_range(text: &str) -> Option<(u32, u32)> {
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+        None => (parse_number(text)?, parse_number(text)?),
+    };
+
+    if start <= end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+/// Writes a record of every instruction a system runs, see `TraceRecord`. Symbolic traces name
+/// addresses after the system's symbols.
+pub struct Tracer<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    filter: TraceFilter,
+    cycle: u64,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Starts a trace, writing the header of a binary trace
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where to write the trace
+    /// * `format` - How to write it
+    /// * `filter` - Which instructions to write
+    pub fn new(mut writer: W, format: TraceFormat, filter: TraceFilter) -> io::Result<Self> {
+        if format == TraceFormat::Binary {
+            writer.write_all(BINARY_MAGIC)?;
+            writer.write_all(&[BINARY_VERSION])?;
+        }
+
+        Ok(Self {
+            writer,
+            format,
+            filter,
+            cycle: 0,
+        })
+    }
+
+    /// Records the instruction a system is about to run. Lines are numbered by the calls before
+    /// them, so skipping an instruction puts the cycles and frames of later lines out of step.
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The system
+    pub fn trace(&mut self, system: &System) -> io::Result<()> {
+        let cycle = self.cycle;
+        self.cycle += 1;
+
+        if !self
+            .filter
+            .matches(system.get_pc(), cycle / TICKS_PER_FRAME as u64)
+        {
+            return Ok(());
+        }
+
+        let record = TraceRecord::capture(system, cycle);
+        match self.format {
+            TraceFormat::Text => record.write_text(&mut self.writer, &Symbols::new()),
+            TraceFormat::Symbolic => record.write_text(&mut self.writer, system.get_symbols()),
+            TraceFormat::Binary => record.write_binary(&mut self.writer),
+        }
+    }
+
+    /// Flushes the trace and returns the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(rom: Vec<u8>, ticks: usize, format: TraceFormat, filter: TraceFilter) -> Vec<u8> {
+        let mut system = System::new();
+        system.write_rom(rom);
+
+        let mut tracer = Tracer::new(vec![], format, filter).unwrap();
+        for _ in 0..ticks {
+            tracer.trace(&system).unwrap();
+            system.tick();
+        }
+
+        tracer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_text() {
+        // v1 := 0x20, i := 0x300
+        let text = trace(
+            vec![0x61, 0x20, 0xA3, 0x00],
+            2,
+            TraceFormat::Text,
+            TraceFilter::default(),
+        );
+        let lines: Vec<&str> = std::str::from_utf8(&text).unwrap().lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "00000000 0200: 6120  LD V1, 0x20          V0=00 V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 \
+             V7=00 V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 VE=00 VF=00 I=0000 SP=0 DT=00 ST=00"
+        );
+        assert!(lines[1].starts_with("00000001 0202: A300  LD I, 0x300          V0=00 V1=20"));
+
+        // Only symbolic traces use the system's symbols
+        let mut system = System::new();
+        system.write_rom(vec![0x12, 0x00]);
+        system.set_symbols(Symbols::parse("0x200 main").unwrap());
+        for (format, line) in [
+            (TraceFormat::Text, "00000000 0200: 1200  JP 0x200 "),
+            (TraceFormat::Symbolic, "00000000 0200: 1200  JP main "),
+        ] {
+            let mut tracer = Tracer::new(vec![], format, TraceFilter::default()).unwrap();
+            tracer.trace(&system).unwrap();
+            let text = String::from_utf8(tracer.finish().unwrap()).unwrap();
+            assert!(text.starts_with(line));
+        }
+    }
+
+    #[test]
+    fn test_binary() {
+        // v0 += 1, then loop back
+        let rom = vec![0x70, 0x01, 0x12, 0x00];
+        let binary = trace(rom.clone(), 10, TraceFormat::Binary, TraceFilter::default());
+        assert_eq!(binary.len(), 5 + 10 * RECORD_SIZE);
+
+        let mut text = vec![];
+        write_binary_as_text(&binary[..], &mut text, &Symbols::new()).unwrap();
+        assert_eq!(
+            text,
+            trace(rom, 10, TraceFormat::Text, TraceFilter::default())
+        );
+
+        assert!(write_binary_as_text(&binary[1..], vec![], &Symbols::new()).is_err());
+        let truncated = &binary[..binary.len() - 1];
+        assert!(write_binary_as_text(truncated, vec![], &Symbols::new()).is_err());
+
+        let symbols = Symbols::parse("0x200 main").unwrap();
+        let mut text = vec![];
+        write_binary_as_text(&binary[..], &mut text, &symbols).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].ends_with("ST=00 <main>"));
+        assert!(lines[1].starts_with("00000001 0202: 1200  JP main "));
+        assert!(lines[1].ends_with("ST=00 <main+0x2>"));
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(parse_range("0x200-0x2FF"), Some((0x200, 0x2FF)));
+        assert_eq!(parse_range("5"), Some((5, 5)));
+        assert_eq!(parse_range("5-4"), None);
+
+        let filter = TraceFilter {
+            addresses: Some((0x202, 0x202)),
+            frames: Some((1, 1)),
+        };
+        let text = trace(
+            vec![0x70, 0x01, 0x12, 0x00],
+            TICKS_PER_FRAME * 3,
+            TraceFormat::Text,
+            filter,
+        );
+        let lines: Vec<&str> = std::str::from_utf8(&text).unwrap().lines().collect();
+
+        // 0x202 runs on every odd cycle
+        let cycles: Vec<String> = (TICKS_PER_FRAME..TICKS_PER_FRAME * 2)
+            .filter(|cycle| cycle % 2 == 1)
+            .map(|cycle| format!("{:08} 0202: 1200  JP 0x200 ", cycle))
+            .collect();
+        assert_eq!(lines.len(), cycles.len());
+        for (line, start) in lines.iter().zip(cycles.iter()) {
+            assert!(line.starts_with(start.as_str()));
+        }
+    }
+}